@@ -0,0 +1,317 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Duration, NaiveDateTime};
+use rust_decimal::Decimal;
+
+use crate::error::StockResult;
+use crate::share_price_model::{Movement, Share, ShareMoment, ShareTimeline};
+use crate::storage::ShareStore;
+
+pub const DEFAULT_COOLDOWN: &str = "1d";
+
+/// What has to happen to a price for a rule to fire
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlertCondition {
+    Below(Decimal),
+    Above(Decimal),
+    /// Up or down by more than this percent since the moment
+    MovesMoreThan { percent: Decimal, moment: ShareMoment },
+    /// The price has gone from one side of its N day average to the other since the last one we saved
+    CrossesAverage { days: u32 },
+}
+
+impl FromStr for AlertCondition {
+    type Err = String;
+
+    /// "below 2500", "above 3000", "moves 5% [vs] [yesterday]", "crosses-average 50"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lowered = s.trim().to_lowercase();
+        let words: Vec<&str> = lowered.split_whitespace().filter(|word| *word != "vs").collect();
+        let bad_condition = || format!("'{}' isn't an alert, try 'below 2500', 'above 3000', 'moves 5% yesterday' or 'crosses-average 50'", s);
+        let number = |word: Option<&&str>| word
+            .and_then(|word| Decimal::from_str(word.trim_end_matches('%')).ok())
+            .ok_or_else(bad_condition);
+        match words.first() {
+            Some(&"below") if words.len() == 2 => Ok(AlertCondition::Below(number(words.get(1))?)),
+            Some(&"above") if words.len() == 2 => Ok(AlertCondition::Above(number(words.get(1))?)),
+            Some(&"moves") if words.len() == 2 || words.len() == 3 => Ok(AlertCondition::MovesMoreThan {
+                percent: number(words.get(1))?,
+                moment: words.get(2).map(|moment| moment.parse()).unwrap_or(Ok(ShareMoment::Yesterday))?,
+            }),
+            Some(&"crosses-average") if words.len() == 2 => words[1].trim_end_matches('d').parse::<u32>()
+                .ok()
+                .filter(|days| *days > 0)
+                .map(|days| AlertCondition::CrossesAverage { days })
+                .ok_or_else(bad_condition),
+            _ => Err(bad_condition()),
+        }
+    }
+}
+
+impl fmt::Display for AlertCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertCondition::Below(price) => write!(f, "below {}", price),
+            AlertCondition::Above(price) => write!(f, "above {}", price),
+            AlertCondition::MovesMoreThan { percent, moment } => write!(f, "moves {}% {}", percent, moment_word(*moment)),
+            AlertCondition::CrossesAverage { days } => write!(f, "crosses-average {}", days),
+        }
+    }
+}
+
+/// The word `ShareMoment::from_str` takes back, so conditions survive the round trip through the DB
+fn moment_word(moment: ShareMoment) -> String {
+    match moment {
+        ShareMoment::Yesterday => "yesterday".to_string(),
+        ShareMoment::LastWeek => "week".to_string(),
+        ShareMoment::LastMonth => "month".to_string(),
+        ShareMoment::LastQuarter => "quarter".to_string(),
+        ShareMoment::YearToDate => "ytd".to_string(),
+        ShareMoment::LastYear => "year".to_string(),
+        ShareMoment::FiveYears => "5y".to_string(),
+        ShareMoment::Days(days) => format!("{}d", days),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertRule {
+    /// Nothing until the store has given it one
+    pub id: Option<i64>,
+    pub company_code: String,
+    pub condition: AlertCondition,
+    /// How long after firing before it can fire again
+    pub cooldown: Duration,
+    pub last_triggered: Option<NaiveDateTime>,
+}
+
+impl AlertRule {
+    fn cooling_down(&self, now: NaiveDateTime) -> bool {
+        self.last_triggered.map(|last_triggered| now - last_triggered < self.cooldown).unwrap_or(false)
+    }
+}
+
+/// A rule that went off, and what it saw
+#[derive(Debug, Clone, PartialEq)]
+pub struct TriggeredAlert {
+    pub rule_id: i64,
+    pub company_code: String,
+    pub condition: String,
+    pub price: Decimal,
+    pub triggered_at: NaiveDateTime,
+    pub message: String,
+    /// Since the rule's moment, or yesterday for rules without one. Not kept in the history.
+    pub movement: Option<(ShareMoment, Movement)>,
+}
+
+/// "30m", "2h", "1d" or a bare number of minutes
+pub fn parse_cooldown(cooldown: &str) -> Result<Duration, String> {
+    let cooldown = cooldown.trim().to_lowercase();
+    let bad_cooldown = || format!("'{}' isn't a cooldown, try 30m, 2h or 1d", cooldown);
+    let (amount, unit) = match cooldown.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => cooldown.split_at(idx),
+        None => (cooldown.as_str(), "m"),
+    };
+    let amount: i64 = amount.parse().map_err(|_| bad_cooldown())?;
+    match unit {
+        "m" => Ok(Duration::minutes(amount)),
+        "h" => Ok(Duration::hours(amount)),
+        "d" => Ok(Duration::days(amount)),
+        _ => Err(bad_cooldown()),
+    }
+}
+
+pub fn format_cooldown(cooldown: Duration) -> String {
+    if cooldown.num_minutes() % (24 * 60) == 0 && cooldown.num_minutes() > 0 {
+        format!("{}d", cooldown.num_days())
+    } else if cooldown.num_minutes() % 60 == 0 && cooldown.num_minutes() > 0 {
+        format!("{}h", cooldown.num_hours())
+    } else {
+        format!("{}m", cooldown.num_minutes())
+    }
+}
+
+/// Runs every rule for the codes we just priced, recording the ones that fire.
+/// Call it before the new prices are saved, so "the last one we saved" means the previous run.
+pub fn check_alerts(store: &mut dyn ShareStore, share_timelines: &[&ShareTimeline]) -> StockResult<Vec<TriggeredAlert>> {
+    let mut triggered = Vec::new();
+    for rule in store.load_alert_rules()? {
+        let share_timeline = match share_timelines.iter().find(|share_timeline| share_timeline.share.company_code == rule.company_code) {
+            Some(share_timeline) => share_timeline,
+            None => continue,
+        };
+        let now = share_timeline.share.price_date;
+        if rule.cooling_down(now) {
+            continue;
+        }
+        if let Some(message) = evaluate(store, &rule.condition, share_timeline)? {
+            let moment = match rule.condition {
+                AlertCondition::MovesMoreThan { moment, .. } => moment,
+                _ => ShareMoment::Yesterday,
+            };
+            let movement = movement_since(store, share_timeline, moment)?.map(|movement| (moment, movement));
+            let alert = TriggeredAlert {
+                rule_id: rule.id.unwrap_or_default(),
+                company_code: rule.company_code.clone(),
+                condition: rule.condition.to_string(),
+                price: share_timeline.share.price.amount,
+                triggered_at: now,
+                message,
+                movement,
+            };
+            store.record_alert(&alert)?;
+            triggered.push(alert);
+        }
+    }
+    Ok(triggered)
+}
+
+/// What to tell the user if the condition holds, nothing if it doesn't
+fn evaluate(store: &mut dyn ShareStore, condition: &AlertCondition, share_timeline: &ShareTimeline) -> StockResult<Option<String>> {
+    let share = &share_timeline.share;
+    let price = share.price.amount;
+    let message = match condition {
+        AlertCondition::Below(limit) if price < *limit => Some(format!("{} is below {}", share.price, limit)),
+        AlertCondition::Above(limit) if price > *limit => Some(format!("{} is above {}", share.price, limit)),
+        AlertCondition::MovesMoreThan { percent, moment } => {
+            movement_since(store, share_timeline, *moment)?
+                .filter(|movement| movement.percent.abs() > *percent)
+                .map(|movement| format!("moved {} since {}, more than {}%", movement.percent_string(), moment, percent))
+        }
+        AlertCondition::CrossesAverage { days } => {
+            let since = share.price_date - Duration::days(i64::from(*days));
+            let history: Vec<Share> = store.load_complete_share_history(&share.company_code)?.into_iter()
+                .filter(|saved| saved.price_date >= since && saved.price_date < share.price_date)
+                //an average over more than one currency is meaningless
+                .filter(|saved| saved.price.same_currency(&share.price))
+                .collect();
+            let previous = history.iter().max_by_key(|saved| saved.price_date);
+            match (average_price(&history), previous) {
+                (Some(average), Some(previous)) => {
+                    let was_above = previous.price.amount >= average;
+                    let is_above = price >= average;
+                    if was_above != is_above {
+                        let direction = if is_above { "above" } else { "below" };
+                        Some(format!("crossed {} its {} day average of {}", direction, days, average.round_dp(2)))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    };
+    Ok(message)
+}
+
+fn movement_since(store: &mut dyn ShareStore, share_timeline: &ShareTimeline, moment: ShareMoment) -> StockResult<Option<Movement>> {
+    let share = &share_timeline.share;
+    Ok(match share_timeline.share_history.get(&moment) {
+        Some(historic) => Movement::between(historic, share),
+        //not one of the moments on the table, so go and find it
+        None => store.load_share_history_segments(&share.company_code, moment.days_ago(share.exchange_date()), share.price_date)?
+            .and_then(|historic| Movement::between(&historic, share)),
+    })
+}
+
+fn average_price(shares: &[Share]) -> Option<Decimal> {
+    if shares.is_empty() {
+        return None;
+    }
+    let total: Decimal = shares.iter().map(|share| share.price.amount).sum();
+    Some(total / Decimal::from(shares.len() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::NaiveDate;
+    use chrono_tz::Tz;
+
+    use crate::share_price_model::Price;
+    use crate::storage::migrations;
+    use crate::storage::sqlite_store::SqliteStore;
+
+    use super::*;
+
+    fn share_at(price: &str, price_date: NaiveDateTime) -> Share {
+        Share {
+            company_code: "NPN".to_string(),
+            price: Price::parse(price).unwrap(),
+            price_date,
+            exchange_tz: Tz::UTC,
+            source: None,
+        }
+    }
+
+    fn timeline_at(price: &str, price_date: NaiveDateTime) -> ShareTimeline {
+        ShareTimeline { share: share_at(price, price_date), share_history: HashMap::new() }
+    }
+
+    fn store_with_rule(condition: &str) -> SqliteStore {
+        let mut store = SqliteStore::open(":memory:").unwrap();
+        migrations::migrate(&mut store).unwrap();
+        store.add_alert_rule(&AlertRule {
+            id: None,
+            company_code: "NPN".to_string(),
+            condition: condition.parse().unwrap(),
+            cooldown: Duration::hours(1),
+            last_triggered: None,
+        }).unwrap();
+        store
+    }
+
+    fn day(day: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2020, 10, day).unwrap().and_hms_opt(15, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn conditions_read_back_what_they_write() {
+        for condition in &["below 2500", "above 3000.5", "moves 5% yesterday", "moves 2.5% 45d", "crosses-average 50"] {
+            let parsed: AlertCondition = condition.parse().unwrap();
+            assert_eq!(parsed.to_string().parse::<AlertCondition>().unwrap(), parsed);
+        }
+        assert_eq!("moves 5 vs week".parse::<AlertCondition>().unwrap(),
+                   AlertCondition::MovesMoreThan { percent: Decimal::from(5), moment: ShareMoment::LastWeek });
+        assert!("sideways 5".parse::<AlertCondition>().is_err());
+        assert_eq!(parse_cooldown("90").unwrap(), Duration::minutes(90));
+        assert_eq!(format_cooldown(parse_cooldown("2d").unwrap()), "2d");
+    }
+
+    #[test]
+    fn rules_fire_once_per_cooldown() {
+        let mut store = store_with_rule("below 2500");
+        assert!(check_alerts(&mut store, &[&timeline_at("2600", day(10))]).unwrap().is_empty());
+
+        let triggered = check_alerts(&mut store, &[&timeline_at("2400", day(11))]).unwrap();
+        assert_eq!(triggered.len(), 1);
+        assert_eq!(triggered[0].message, "2400 is below 2500");
+        let later_that_day = day(11) + Duration::minutes(30);
+        assert!(check_alerts(&mut store, &[&timeline_at("2300", later_that_day)]).unwrap().is_empty());
+        assert_eq!(check_alerts(&mut store, &[&timeline_at("2300", day(12))]).unwrap().len(), 1);
+
+        assert_eq!(store.load_alert_history(10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn moves_are_measured_against_saved_history() {
+        let mut store = store_with_rule("moves 5% yesterday");
+        store.save_prices(&[timeline_at("100", day(10))]).unwrap();
+        assert!(check_alerts(&mut store, &[&timeline_at("104", day(11))]).unwrap().is_empty());
+        let triggered = check_alerts(&mut store, &[&timeline_at("94", day(11))]).unwrap();
+        assert_eq!(triggered[0].message, "moved -6.00% since Yesterday, more than 5%");
+        assert_eq!(triggered[0].movement.map(|(moment, movement)| (moment, movement.absolute)), Some((ShareMoment::Yesterday, Decimal::from(-6))));
+    }
+
+    #[test]
+    fn crossing_the_average_needs_a_change_of_side() {
+        let mut store = store_with_rule("crosses-average 50");
+        store.save_prices(&[timeline_at("100", day(1)), timeline_at("110", day(5)), timeline_at("95", day(9))]).unwrap();
+        //average is 101.67 and the last saved price was under it
+        assert!(check_alerts(&mut store, &[&timeline_at("99", day(10))]).unwrap().is_empty());
+        let triggered = check_alerts(&mut store, &[&timeline_at("105", day(10))]).unwrap();
+        assert_eq!(triggered[0].message, "crossed above its 50 day average of 101.67");
+    }
+}
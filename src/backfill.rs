@@ -0,0 +1,292 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use chrono::{Duration, NaiveDate, NaiveTime, TimeZone};
+use chrono_tz::Tz;
+use rust_decimal::Decimal;
+
+use crate::error::{StockError, StockResult};
+use crate::share_price_model::{plain_amount, CurrencyCode, Price, Share, ShareTimeline, GROUPING_MARKS};
+
+/// What backfilled prices say they came from
+pub const SOURCE_NAME: &str = "import";
+
+/// Header names we look for when no column is given, matched without case
+const DATE_COLUMNS: [&str; 3] = ["date", "timestamp", "time"];
+/// The plain close first, it's what the scraper would have seen on the day
+const CLOSE_COLUMNS: [&str; 5] = ["close", "close/last", "price", "last", "adj close"];
+/// What a close may start with, "R$" is checked before these
+const CURRENCY_SYMBOLS: [char; 5] = ['$', '€', '£', '¥', 'R'];
+
+/// How to read someone else's price file, one row per trading day
+pub struct ImportLayout {
+    /// Worked out from the header when not given
+    pub date_column: Option<String>,
+    pub close_column: Option<String>,
+    pub date_format: String,
+    pub delimiter: u8,
+}
+
+impl Default for ImportLayout {
+    fn default() -> Self {
+        ImportLayout {
+            date_column: None,
+            close_column: None,
+            date_format: "%Y-%m-%d".to_string(),
+            delimiter: b',',
+        }
+    }
+}
+
+/// A day's close, none if the file left it blank or "null" like Yahoo does on holidays
+#[derive(Debug, Clone, PartialEq)]
+pub struct DailyClose {
+    pub date: NaiveDate,
+    pub close: Option<Decimal>,
+}
+
+/// Where each price lands, since a day in a file has no time or zone of its own
+pub struct ImportOptions {
+    pub exchange_tz: Tz,
+    /// On the exchange's clock
+    pub close_time: NaiveTime,
+    pub currency: Option<CurrencyCode>,
+}
+
+/// The position of the named column, or the first of the usual names when there's no name
+fn find_column(headers: &csv::StringRecord, named: Option<&str>, usual: &[&str]) -> StockResult<usize> {
+    let matches = |header: &str, name: &str| header.trim().eq_ignore_ascii_case(name.trim());
+    let found = match named {
+        Some(name) => headers.iter().position(|header| matches(header, name)),
+        None => usual.iter().find_map(|name| headers.iter().position(|header| matches(header, name))),
+    };
+    found.ok_or_else(|| StockError::Parse(format!("No {} column in the header ({})",
+                                                  named.map(|name| format!("'{}'", name)).unwrap_or_else(|| usual.join("/")),
+                                                  headers.iter().collect::<Vec<_>>().join(", "))))
+}
+
+/// Money as it turns up in exports, "2834.50", "$119.02", "2,834.50" or "1 234,80". Blank, "null" and "-" are no price.
+/// Anything but one leading currency symbol, digits and separators is refused, as is a close below zero.
+fn parse_close(raw_close: &str) -> Result<Option<Decimal>, String> {
+    let trimmed = raw_close.trim();
+    if trimmed.is_empty() || trimmed.eq_ignore_ascii_case("null") || trimmed == "-" {
+        return Ok(None);
+    }
+    let amount = trimmed.strip_prefix("R$")
+        .or_else(|| trimmed.strip_prefix(&CURRENCY_SYMBOLS[..]))
+        .unwrap_or(trimmed)
+        .trim_start();
+    if trimmed.starts_with('-') || amount.starts_with('-') {
+        return Err(format!("'{}' is below zero, a close can't be", raw_close));
+    }
+    if !amount.chars().any(|c| c.is_ascii_digit())
+        || !amount.chars().all(|c| c.is_ascii_digit() || c == ',' || c == '.' || GROUPING_MARKS.contains(&c)) {
+        return Err(format!("'{}' isn't a price", raw_close));
+    }
+    plain_amount(amount)
+        .and_then(|plain| Decimal::from_str(&plain).ok())
+        .map(Some)
+        .ok_or_else(|| format!("'{}' isn't a price", raw_close))
+}
+
+/// Every row of the file in the order it came, newest first or oldest first
+pub fn read_daily_closes<R: Read>(reader: R, layout: &ImportLayout) -> StockResult<Vec<DailyClose>> {
+    let mut csv_reader = csv::ReaderBuilder::new()
+        .delimiter(layout.delimiter)
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(reader);
+    let headers = csv_reader.headers()
+        .map_err(|e| StockError::Parse(format!("Couldn't read the header: {}", e)))?
+        .clone();
+    let date_idx = find_column(&headers, layout.date_column.as_deref(), &DATE_COLUMNS)?;
+    let close_idx = find_column(&headers, layout.close_column.as_deref(), &CLOSE_COLUMNS)?;
+
+    let mut daily_closes = Vec::new();
+    for (idx, record) in csv_reader.records().enumerate() {
+        //the header is line 1
+        let line = idx + 2;
+        let record = record.map_err(|e| StockError::Parse(format!("Line {}: {}", line, e)))?;
+        let bad_line = |problem: String| StockError::Parse(format!("Line {}: {}", line, problem));
+        let raw_date = record.get(date_idx).unwrap_or_default();
+        let date = NaiveDate::parse_from_str(raw_date, &layout.date_format)
+            .map_err(|_| bad_line(format!("'{}' isn't a date like {}, see --date-format", raw_date, layout.date_format)))?;
+        let close = parse_close(record.get(close_idx).unwrap_or_default()).map_err(bad_line)?;
+        daily_closes.push(DailyClose { date, close });
+    }
+    Ok(daily_closes)
+}
+
+/// What an import is going to do, worked out before anything is saved so a dry run can show it
+pub struct ImportPlan {
+    pub company_code: String,
+    pub rows: usize,
+    /// Oldest first
+    pub to_save: Vec<Share>,
+    /// Days we already had a price for
+    pub already_saved: usize,
+    /// Days that came up more than once in the file, only the first one counts
+    pub repeated: usize,
+    pub without_price: usize,
+}
+
+impl ImportPlan {
+    /// The new prices, ready for `save_prices`
+    pub fn timelines(&self) -> Vec<ShareTimeline> {
+        self.to_save.iter()
+            .map(|share| ShareTimeline { share: share.clone(), share_history: HashMap::new() })
+            .collect()
+    }
+}
+
+impl fmt::Display for ImportPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.to_save.first(), self.to_save.last()) {
+            (Some(first), Some(last)) => writeln!(f, "{} rows, {} new prices for {} from {} to {}",
+                                                  self.rows, self.to_save.len(), self.company_code, first.exchange_date(), last.exchange_date())?,
+            _ => writeln!(f, "{} rows, no new prices for {}", self.rows, self.company_code)?,
+        }
+        write!(f, "Skipped {} days already saved, {} repeated in the file and {} without a price",
+               self.already_saved, self.repeated, self.without_price)
+    }
+}
+
+/// Turns the rows into prices at the close on each day, leaving out any day the code already has a price for
+pub fn plan_import(company_code: &str, daily_closes: &[DailyClose], existing: &[Share], import_options: &ImportOptions) -> ImportPlan {
+    let mut saved_days: HashSet<NaiveDate> = existing.iter().map(Share::exchange_date).collect();
+    let mut file_days: HashSet<NaiveDate> = HashSet::new();
+    let mut plan = ImportPlan {
+        company_code: company_code.to_string(),
+        rows: daily_closes.len(),
+        to_save: Vec::new(),
+        already_saved: 0,
+        repeated: 0,
+        without_price: 0,
+    };
+    for daily_close in daily_closes {
+        if !file_days.insert(daily_close.date) {
+            plan.repeated += 1;
+            continue;
+        }
+        if saved_days.contains(&daily_close.date) {
+            plan.already_saved += 1;
+            continue;
+        }
+        let close = match daily_close.close {
+            Some(close) => close,
+            None => {
+                plan.without_price += 1;
+                continue;
+            }
+        };
+        saved_days.insert(daily_close.date);
+        plan.to_save.push(Share {
+            company_code: company_code.to_string(),
+            price: Price::in_currency(close, import_options.currency.clone()),
+            price_date: close_in_utc(daily_close.date, import_options),
+            exchange_tz: import_options.exchange_tz,
+            source: Some(SOURCE_NAME.to_string()),
+        });
+    }
+    plan.to_save.sort_by_key(|share| share.price_date);
+    plan
+}
+
+fn close_in_utc(date: NaiveDate, import_options: &ImportOptions) -> chrono::NaiveDateTime {
+    let local_close = date.and_time(import_options.close_time);
+    match import_options.exchange_tz.from_local_datetime(&local_close).earliest() {
+        Some(close) => close.naive_utc(),
+        //a close time skipped by a DST jump, an hour out is still the same day
+        None => local_close - Duration::hours(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, month, day).unwrap()
+    }
+
+    fn close(date: NaiveDate, close: &str) -> DailyClose {
+        DailyClose { date, close: Some(Decimal::from_str(close).unwrap()) }
+    }
+
+    #[test]
+    fn reads_yahoo_layout() {
+        let csv = "Date,Open,High,Low,Close,Adj Close,Volume\n\
+                   2020-10-15,2800.00,2850.00,2790.00,2822.50,2822.50,1200\n\
+                   2020-10-16,null,null,null,null,null,null\n";
+        let daily_closes = read_daily_closes(csv.as_bytes(), &ImportLayout::default()).unwrap();
+        assert_eq!(daily_closes, vec![close(day(10, 15), "2822.50"), DailyClose { date: day(10, 16), close: None }]);
+    }
+
+    #[test]
+    fn reads_other_layouts_with_mapping() {
+        let nasdaq = "Date,Close/Last,Volume,Open,High,Low\n10/16/2020,$119.02,115393800,$121.28,$121.548,$118.81\n";
+        let layout = ImportLayout { date_format: "%m/%d/%Y".to_string(), ..ImportLayout::default() };
+        assert_eq!(read_daily_closes(nasdaq.as_bytes(), &layout).unwrap(), vec![close(day(10, 16), "119.02")]);
+
+        let semicolons = "dag;slot;volume\n16.10.2020;1 234,80;900\n";
+        let layout = ImportLayout {
+            date_column: Some("dag".to_string()),
+            close_column: Some("Slot".to_string()),
+            date_format: "%d.%m.%Y".to_string(),
+            delimiter: b';',
+        };
+        assert_eq!(read_daily_closes(semicolons.as_bytes(), &layout).unwrap(), vec![close(day(10, 16), "1234.80")]);
+    }
+
+    #[test]
+    fn closes_keep_their_sign_and_grouping() {
+        let parsed = |raw: &str| parse_close(raw).map(|close| close.map(|close| close.to_string()));
+        for (raw, expected) in [("2834.50", "2834.50"), ("$119.02", "119.02"), ("R 2 834,50", "2834.50"), ("R$12,50", "12.50"),
+                                ("2,834.50", "2834.50"), ("1.234,80", "1234.80"), ("1,234", "1234"), ("1,234,567", "1234567"),
+                                ("0.500", "0.500"), ("12,5", "12.5"), ("1'234.80", "1234.80")] {
+            assert_eq!(parsed(raw), Ok(Some(expected.to_string())), "{}", raw);
+        }
+        assert_eq!(parsed(" null "), Ok(None));
+        assert!(parsed("-5.20").unwrap_err().contains("below zero"));
+        assert!(parsed("$-5.20").unwrap_err().contains("below zero"));
+        for raw in ["1.2e3", "12.50 USD", "5.20-", "(5.20)", "$$5", "1.234,567,8", "12.", "$"] {
+            assert_eq!(parsed(raw), Err(format!("'{}' isn't a price", raw)), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn bad_rows_say_where() {
+        let e = read_daily_closes("Date,Close\n2020-10-15,1\n16/10/2020,2\n".as_bytes(), &ImportLayout::default()).unwrap_err();
+        assert!(e.to_string().contains("Line 3"));
+        let e = read_daily_closes("Day,Close\n2020-10-15,1\n".as_bytes(), &ImportLayout::default()).unwrap_err();
+        assert!(e.to_string().contains("No date/timestamp/time column"));
+    }
+
+    #[test]
+    fn skips_days_we_already_have() {
+        let jhb = Tz::Africa__Johannesburg;
+        let import_options = ImportOptions { exchange_tz: jhb, close_time: NaiveTime::from_hms_opt(17, 0, 0).unwrap(), currency: Some("ZAR".parse().unwrap()) };
+        //scraped at 10am in Johannesburg on the 14th
+        let existing = vec![Share { company_code: "NPN".to_string(), price: Price::new(Decimal::from(2790)), price_date: day(10, 14).and_hms_opt(8, 0, 0).unwrap(), exchange_tz: jhb, source: None }];
+        let daily_closes = vec![
+            close(day(10, 16), "2834.50"),
+            close(day(10, 15), "2822.50"),
+            close(day(10, 14), "2800.00"),
+            close(day(10, 15), "2822.50"),
+            DailyClose { date: day(10, 13), close: None },
+        ];
+
+        let plan = plan_import("NPN", &daily_closes, &existing, &import_options);
+        assert_eq!((plan.rows, plan.already_saved, plan.repeated, plan.without_price), (5, 1, 1, 1));
+        let saved: Vec<_> = plan.to_save.iter().map(|share| (share.price_date, share.price.to_string(), share.price.currency_label())).collect();
+        assert_eq!(saved, vec![
+            (day(10, 15).and_hms_opt(15, 0, 0).unwrap(), "2822.50".to_string(), "ZAR".to_string()),
+            (day(10, 16).and_hms_opt(15, 0, 0).unwrap(), "2834.50".to_string(), "ZAR".to_string()),
+        ]);
+        assert!(plan.to_save.iter().all(|share| share.source.as_deref() == Some("import")));
+        assert_eq!(plan.to_string(), "5 rows, 2 new prices for NPN from 2020-10-15 to 2020-10-16\n\
+                                      Skipped 1 days already saved, 1 repeated in the file and 1 without a price");
+    }
+}
@@ -1,180 +1,772 @@
 use std::collections::HashMap;
-use std::error::Error;
 use std::io;
-use std::io::Stdout;
+use std::time::Duration;
 
-use log::{debug, error};
-use termion::clear;
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use rust_decimal::prelude::ToPrimitive;
 use termion::event::Key;
-use termion::raw::{IntoRawMode, RawTerminal};
+use termion::raw::IntoRawMode;
 use termion::screen::AlternateScreen;
-use tui::{Frame, Terminal, symbols};
-use tui::backend::TermionBackend;
+use tui::{Terminal, symbols};
+use tui::backend::{Backend, TermionBackend};
 use tui::layout::{Constraint, Direction, Layout, Rect};
 use tui::style::{Color, Style, Modifier};
-use tui::widgets::{Block, Borders, Chart, Dataset, GraphType, Sparkline, Axis};
+use tui::widgets::{Block, Borders, Chart, Dataset, GraphType, Axis, Paragraph};
 
-use crate::share_price_model::{Share, ShareTimeline};
-use crate::util::event::{Event, Events};
-use tui::text::Span;
+use crate::error::{StockError, StockResult};
+use crate::share_price_model::Share;
+use crate::util::event::{Config, Event, Events};
+use tui::text::{Span, Spans};
 
-pub struct ChartGrapher {
-    pub share_data: Vec<ShareTimeline>
+pub struct ChartGrapher;
+
+/// One ticker's prices, ready to plot as (seconds since the epoch, price)
+struct PriceSeries {
+    code: String,
+    exchange_tz: Tz,
+    points: Vec<(f64, f64)>,
+}
+
+/// How the chart panes are laid out on the screen right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ChartGrid {
+    columns: u16,
+    rows: u16,
+}
+
+impl ChartGrid {
+    fn per_page(&self) -> usize {
+        usize::from(self.columns) * usize::from(self.rows)
+    }
 }
 
-const DATA: [(f64, f64); 5] = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 3.0), (4.0, 4.0)];
-const DATA2: [(f64, f64); 7] = [
-    (0.0, 0.0),
-    (10.0, 1.0),
-    (20.0, 0.5),
-    (30.0, 1.5),
-    (40.0, 1.0),
-    (50.0, 2.5),
-    (60.0, 3.0),
+/// Smallest a pane can get before the chart stops being readable
+const MIN_PANE_WIDTH: u16 = 40;
+const MIN_PANE_HEIGHT: u16 = 10;
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+/// How far one press of pan moves the window, as a fraction of what's on screen
+const PAN_FRACTION: f64 = 0.25;
+/// Redraws are driven off the tick, so keep it quick enough that keys feel immediate
+const TICK_RATE: Duration = Duration::from_millis(100);
+/// How much room to leave above and below the prices, as a fraction of their range
+const PRICE_PADDING: f64 = 0.05;
+
+/// Whether we're plotting prices or how far they've moved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PriceScale {
+    Absolute,
+    /// Percent change from the first visible point
+    PercentChange,
+}
+
+impl PriceScale {
+    fn toggled(self) -> PriceScale {
+        match self {
+            PriceScale::Absolute => PriceScale::PercentChange,
+            PriceScale::PercentChange => PriceScale::Absolute,
+        }
+    }
+
+    fn apply(self, points: &[(f64, f64)]) -> Vec<(f64, f64)> {
+        match (self, points.first()) {
+            (PriceScale::PercentChange, Some((_, first_price))) if *first_price != 0.0 => points.iter()
+                .map(|(time, price)| (*time, (price / first_price - 1.0) * 100.0))
+                .collect(),
+            _ => points.to_vec(),
+        }
+    }
+
+    fn axis_title(self) -> &'static str {
+        match self {
+            PriceScale::Absolute => "Price",
+            PriceScale::PercentChange => "Change %",
+        }
+    }
+}
+
+/// How much history is on screen at once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimeRange {
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+    All,
+}
+
+/// Narrowest to widest, in the order the number keys pick them
+const TIME_RANGES: [TimeRange; 6] = [
+    TimeRange::Day,
+    TimeRange::Week,
+    TimeRange::Month,
+    TimeRange::Quarter,
+    TimeRange::Year,
+    TimeRange::All,
 ];
+
+impl TimeRange {
+    /// Width of the window in seconds, nothing when it's everything we have
+    fn span(self) -> Option<f64> {
+        let days = match self {
+            TimeRange::Day => 1.0,
+            TimeRange::Week => 7.0,
+            TimeRange::Month => 30.0,
+            TimeRange::Quarter => 91.0,
+            TimeRange::Year => 365.0,
+            TimeRange::All => return None,
+        };
+        Some(days * SECONDS_PER_DAY)
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            TimeRange::Day => "1D",
+            TimeRange::Week => "1W",
+            TimeRange::Month => "1M",
+            TimeRange::Quarter => "3M",
+            TimeRange::Year => "1Y",
+            TimeRange::All => "All",
+        }
+    }
+
+    fn zoomed_in(self) -> TimeRange {
+        let idx = TIME_RANGES.iter().position(|range| *range == self).unwrap_or(0);
+        TIME_RANGES[idx.saturating_sub(1)]
+    }
+
+    fn zoomed_out(self) -> TimeRange {
+        let idx = TIME_RANGES.iter().position(|range| *range == self).unwrap_or(0);
+        TIME_RANGES[(idx + 1).min(TIME_RANGES.len() - 1)]
+    }
+}
+
+/// Everything the keys can change about what's on screen
+struct ChartState {
+    compare: bool,
+    scale: PriceScale,
+    page: usize,
+    page_count: usize,
+    range: TimeRange,
+    /// Right hand edge of the window, nothing while it's pinned to the latest sample
+    view_end: Option<f64>,
+    /// Where the crosshair sits, nothing while it's hidden
+    cursor: Option<f64>,
+}
+
+impl ChartState {
+    /// False once it's time to go. `sample_times` is every sample we have, sorted, for panning and the cursor.
+    fn handle_key(&mut self, key: Key, sample_times: &[f64]) -> bool {
+        match key {
+            Key::Char('q') => return false,
+            Key::Char('n') | Key::PageDown => self.page = (self.page + 1).min(self.page_count - 1),
+            Key::Char('p') | Key::PageUp => self.page = self.page.saturating_sub(1),
+            Key::Char('c') => self.compare = !self.compare,
+            Key::Char('%') => self.scale = self.scale.toggled(),
+            Key::Char(digit @ '1'..='6') => {
+                self.range = TIME_RANGES[digit as usize - '1' as usize];
+                self.follow_cursor(sample_times);
+            }
+            Key::Char('+') | Key::Char('=') => {
+                self.range = self.range.zoomed_in();
+                self.follow_cursor(sample_times);
+            }
+            Key::Char('-') => {
+                self.range = self.range.zoomed_out();
+                self.follow_cursor(sample_times);
+            }
+            Key::Char('<') | Key::Char(',') | Key::Char('h') => self.pan(-1.0, sample_times),
+            Key::Char('>') | Key::Char('.') | Key::Char('l') => self.pan(1.0, sample_times),
+            Key::Left => self.move_cursor(-1, sample_times),
+            Key::Right => self.move_cursor(1, sample_times),
+            Key::Esc => self.cursor = None,
+            _ => {}
+        }
+        true
+    }
+
+    /// The stretch of time on screen
+    fn window(&self, sample_times: &[f64]) -> [f64; 2] {
+        let all_time = time_bounds(sample_times);
+        match self.range.span() {
+            Some(span) => {
+                let end = self.view_end.unwrap_or(all_time[1]);
+                [end - span, end]
+            }
+            None => all_time,
+        }
+    }
+
+    /// Move the window along, but never off either end of the history
+    fn set_view_end(&mut self, end: f64, sample_times: &[f64]) {
+        let span = match self.range.span() {
+            Some(span) => span,
+            None => return,
+        };
+        let all_time = time_bounds(sample_times);
+        self.view_end = if end >= all_time[1] || all_time[0] + span >= all_time[1] {
+            None
+        } else {
+            Some(end.max(all_time[0] + span))
+        };
+    }
+
+    fn pan(&mut self, direction: f64, sample_times: &[f64]) {
+        if let Some(span) = self.range.span() {
+            let end = self.window(sample_times)[1] + direction * span * PAN_FRACTION;
+            self.set_view_end(end, sample_times);
+        }
+    }
+
+    /// Step the crosshair to the previous or next sample, starting on the latest one in view
+    fn move_cursor(&mut self, step: isize, sample_times: &[f64]) {
+        if sample_times.is_empty() {
+            return;
+        }
+        let idx = match self.cursor {
+            Some(cursor) => {
+                let current = nearest_index(sample_times, cursor) as isize;
+                (current + step).max(0).min(sample_times.len() as isize - 1) as usize
+            }
+            None => {
+                let window = self.window(sample_times);
+                sample_times.iter().rposition(|time| *time <= window[1]).unwrap_or(0)
+            }
+        };
+        self.cursor = Some(sample_times[idx]);
+        self.follow_cursor(sample_times);
+    }
+
+    /// Drag the window along so the crosshair stays on screen
+    fn follow_cursor(&mut self, sample_times: &[f64]) {
+        if let (Some(cursor), Some(span)) = (self.cursor, self.range.span()) {
+            let window = self.window(sample_times);
+            if cursor < window[0] {
+                self.set_view_end(cursor + span, sample_times);
+            } else if cursor > window[1] {
+                self.set_view_end(cursor, sample_times);
+            }
+        }
+    }
+}
+
+/// One chart's worth of series, with the axes worked out to fit them and the crosshair
+struct ChartView<'a> {
+    title: &'a str,
+    series: Vec<ChartSeries<'a>>,
+    x_bounds: [f64; 2],
+    /// The dates along the bottom are on this clock
+    label_tz: Tz,
+    y_bounds: [f64; 2],
+    crosshair: Vec<(f64, f64)>,
+}
+
+impl<'a> ChartView<'a> {
+    fn new(title: &'a str, series: Vec<ChartSeries<'a>>, window: [f64; 2], label_tz: Tz, cursor: Option<f64>) -> ChartView<'a> {
+        let all_points: Vec<(f64, f64)> = series.iter()
+            .flat_map(|(_, points, _)| points.iter().copied())
+            .collect();
+        let y_bounds = price_bounds(&all_points);
+        //a vertical line from the bottom of the chart to the top
+        let crosshair = match cursor {
+            Some(cursor) if cursor >= window[0] && cursor <= window[1] => vec![(cursor, y_bounds[0]), (cursor, y_bounds[1])],
+            _ => Vec::new(),
+        };
+        ChartView {
+            title,
+            series,
+            x_bounds: window,
+            label_tz,
+            y_bounds,
+            crosshair,
+        }
+    }
+}
+
+/// A ticker's code, the points to plot for it and the colour to plot them in
+type ChartSeries<'a> = (&'a str, &'a [(f64, f64)], Color);
+
+/// Colours handed out to tickers in turn when they share a chart
+const SERIES_COLORS: [Color; 8] = [
+    Color::Yellow,
+    Color::Cyan,
+    Color::Magenta,
+    Color::Green,
+    Color::LightRed,
+    Color::LightBlue,
+    Color::White,
+    Color::LightYellow,
+];
+
 impl ChartGrapher {
-    /// helper function to create a centered rect using up
-    /// certain percentage of the available rect `r`
-    fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
-        let popup_layout = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(
-                [
-                    Constraint::Percentage((100 - percent_y) / 2),
-                    Constraint::Percentage(percent_y),
-                    Constraint::Percentage((100 - percent_y) / 2),
-                ]
-                    .as_ref(),
-            )
-            .split(r);
+    /// Each code in its own pane, or with `compare` all of them overlaid on one chart
+    pub fn draw_graph(share_data: &HashMap<String, Vec<Share>>, compare: bool) -> StockResult<()> {
+        let mut codes: Vec<&String> = share_data.keys().collect();
+        codes.sort();
+        let price_series = codes.into_iter()
+            .map(|code| Ok(PriceSeries {
+                code: code.to_string(),
+                exchange_tz: share_data[code].last().map(|share| share.exchange_tz).unwrap_or(Tz::UTC),
+                points: price_points(code, &share_data[code])?,
+            }))
+            .collect::<StockResult<Vec<PriceSeries>>>()?;
+        let mut sample_times: Vec<f64> = price_series.iter()
+            .flat_map(|series| series.points.iter().map(|(time, _)| *time))
+            .collect();
+        sample_times.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        sample_times.dedup();
 
-        Layout::default()
-            .direction(Direction::Horizontal)
-            .constraints(
-                [
-                    Constraint::Percentage((100 - percent_x) / 2),
-                    Constraint::Percentage(percent_x),
-                    Constraint::Percentage((100 - percent_x) / 2),
-                ]
-                    .as_ref(),
-            )
-            .split(popup_layout[1])[1]
-    }
-
-    pub fn draw_graph(share_data: &HashMap<String, Vec<Share>>) -> Result<(), Box<dyn Error>> {
-        let stdout = io::stdout().into_raw_mode()?;
+        let stdout = io::stdout().into_raw_mode().map_err(render_error)?;
         // let stdout = MouseTerminal::from(stdout);
         let stdout = AlternateScreen::from(stdout);
         let backend = TermionBackend::new(stdout);
-        let mut terminal = Terminal::new(backend)?;
-
-        // println!("{}", clear::All);
+        let mut terminal = Terminal::new(backend).map_err(render_error)?;
 
         // Setup event handlers
-        let events = Events::new();
-        let mut share_price_arr = HashMap::new();
-        for (code, share_prices) in share_data {
-            share_price_arr.insert(code.to_string(), share_prices.iter()
-                .map(|share| {
-                    // debug!("share.price: '{}'", share.price);
-                    (share.display_date(), share.price.parse::<f32>().unwrap())
-                }).collect::<Vec<(String, f32)>>());
-        }
+        let events = Events::with_config(Config {
+            tick_rate: TICK_RATE,
+            ..Config::default()
+        });
+        let mut state = ChartState {
+            compare,
+            scale: PriceScale::Absolute,
+            page: 0,
+            page_count: 1,
+            range: TimeRange::All,
+            view_end: None,
+            cursor: None,
+        };
 
         loop {
-            terminal.draw(|f| {
-                let size = f.size();
-                // let rect = Chart::centered_rect(40, 100, size);
-
-                let chunks = ChartGrapher::get_layout_chunks(size);
-
-                // debug!("About to draw graph");
-                for (idx, share_history) in share_price_arr.iter().enumerate() {
-                    // debug!("Idx = {}", idx);
-                    let share_history_data = &share_history.1.iter()
-                        .map(|(date_str, price_float)|
-                            (idx as f64, f64::from(*price_float)) ).collect::<Vec<(f64, f64)>>();
-
-                    // debug!("Share price array: {}", share_history_data.len());
-                    for (index, price) in share_history_data {
-                        // debug!("idx: {}, index: {}, price: {}\n",idx, index, price);
-                        // break;
-                    }
-                    // debug!("ABOUT TO BREAK AAAAH!");
-                    // panic!("ABOUT TO BREAK AAAAH!");
-
-                    let share_price_dataset = vec![Dataset::default()
-                        .name(share_history.0)
-                        .marker(symbols::Marker::Braille)
-                        .style(Style::default().fg(Color::Yellow))
-                        .graph_type(GraphType::Line)
-                        // .data(share_history_data)
-                        .data(&DATA)
-                    ];
-
-                    let chart = Chart::new(share_price_dataset)
-                        .block(
-                        Block::default()
-                            .title(Span::styled(
-                                share_history.0,
-                                Style::default()
-                                    .fg(Color::Cyan)
-                                    .add_modifier(Modifier::BOLD),
-                            ))
-                            .borders(Borders::ALL),
-                    ) .x_axis(
-                            Axis::default()
-                                .title("X Axis")
-                                .bounds([0.0, 10.0])
-                                .style(Style::default().fg(Color::Gray))
-                        ).y_axis(
-                        Axis::default()
-                            .title("Y Axis")
-                            .style(Style::default().fg(Color::Gray))
-                            .bounds([0.0, 20.0])
-                            // .labels(vec![
-                            //     Span::styled("0", Style::default().add_modifier(Modifier::BOLD)),
-                            //     Span::raw("2.5"),
-                            //     Span::styled("5.0", Style::default().add_modifier(Modifier::BOLD)),
-                            // ]),
-                    );
-                    // let sparkline = Sparkline::default()
-                    //     .block(
-                    //         Block::default()
-                    //             .title(String::from(share_history.0))
-                    //             .borders(Borders::LEFT | Borders::RIGHT),
-                    //     )
-                    //     .data(share_price_arr)
-                    //     .style(Style::default().fg(Color::Cyan));
-                    f.render_widget(chart, chunks[idx]);
-                    // break;
-                };
-            })?;
-
-            match events.next()? {
+            match events.next().map_err(render_error)? {
+                //keys only change the state, the next tick picks it up
                 Event::Input(input) => {
-                    if input == Key::Char('q') {
+                    if !state.handle_key(input, &sample_times) {
                         break;
                     }
                 }
-                _ => {}
+                Event::Tick => ChartGrapher::draw_frame(&mut terminal, &price_series, &mut state, &sample_times)?,
             }
         }
         Ok(())
     }
 
-    fn get_layout_chunks(area: Rect) -> Vec<Rect> {
+    /// Laid out from scratch every draw, so a resized terminal just gets a new grid
+    fn draw_frame<B: Backend>(terminal: &mut Terminal<B>, price_series: &[PriceSeries], state: &mut ChartState,
+                              sample_times: &[f64]) -> StockResult<()> {
+        let window = state.window(sample_times);
+        let visible: Vec<Vec<(f64, f64)>> = price_series.iter()
+            .map(|series| visible_points(&series.points, window))
+            .collect();
+        let plotted: Vec<Vec<(f64, f64)>> = visible.iter()
+            .map(|points| state.scale.apply(points))
+            .collect();
+
+        terminal.draw(|f| {
+            let (chart_area, status_area) = ChartGrapher::split_status_line(f.size());
+            let on_screen: Vec<usize> = if state.compare {
+                state.page_count = 1;
+                let all_series: Vec<ChartSeries> = price_series.iter()
+                    .zip(&plotted)
+                    .enumerate()
+                    .map(|(idx, (series, points))| (series.code.as_str(), points.as_slice(), series_color(idx)))
+                    .collect();
+                //one clock along the bottom, so UTC unless they all trade on the same one
+                let label_tz = match price_series.first() {
+                    Some(first) if price_series.iter().all(|series| series.exchange_tz == first.exchange_tz) => first.exchange_tz,
+                    _ => Tz::UTC,
+                };
+                let view = ChartView::new("Comparison", all_series, window, label_tz, state.cursor);
+                f.render_widget(price_chart(&view, state.scale), chart_area);
+                (0..price_series.len()).collect()
+            } else {
+                let grid = ChartGrapher::grid_for(price_series.len(), chart_area);
+                state.page_count = price_series.len().div_ceil(grid.per_page()).max(1);
+                state.page = state.page.min(state.page_count - 1);
+
+                let page_indices: Vec<usize> = (0..price_series.len())
+                    .skip(state.page * grid.per_page())
+                    .take(grid.per_page())
+                    .collect();
+                let chunks = ChartGrapher::get_layout_chunks(chart_area, grid, page_indices.len());
+                for (idx, chunk) in page_indices.iter().zip(chunks) {
+                    let series = &price_series[*idx];
+                    let single_series = vec![(series.code.as_str(), plotted[*idx].as_slice(), SERIES_COLORS[0])];
+                    let view = ChartView::new(&series.code, single_series, window, series.exchange_tz, state.cursor);
+                    f.render_widget(price_chart(&view, state.scale), chunk);
+                }
+                page_indices
+            };
+
+            let readout: Vec<(&PriceSeries, &[(f64, f64)])> = on_screen.into_iter()
+                .map(|idx| (&price_series[idx], visible[idx].as_slice()))
+                .collect();
+            f.render_widget(status_line(state, &readout), status_area);
+        }).map_err(render_error)?;
+        Ok(())
+    }
+
+    /// Everything but the bottom two lines goes to the charts
+    fn split_status_line(area: Rect) -> (Rect, Rect) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(2)].as_ref())
+            .split(area);
+        (chunks[0], chunks[1])
+    }
+
+    /// As few columns as we can get away with, since charts like to be wide,
+    /// but never more panes than fit at the minimum readable size
+    fn grid_for(pane_count: usize, area: Rect) -> ChartGrid {
+        let columns_that_fit = (area.width / MIN_PANE_WIDTH).max(1);
+        let rows_that_fit = (area.height / MIN_PANE_HEIGHT).max(1);
+        let pane_count = pane_count.max(1) as u16;
+
+        let columns = pane_count.div_ceil(rows_that_fit).min(columns_that_fit);
+        let rows = pane_count.div_ceil(columns).min(rows_that_fit);
+        ChartGrid { columns, rows }
+    }
+
+    /// One rect per pane on this page, filled in left to right, top to bottom
+    fn get_layout_chunks(area: Rect, grid: ChartGrid, pane_count: usize) -> Vec<Rect> {
+        let row_constraints = vec![Constraint::Ratio(1, u32::from(grid.rows)); usize::from(grid.rows)];
+        let column_constraints = vec![Constraint::Ratio(1, u32::from(grid.columns)); usize::from(grid.columns)];
         Layout::default()
             .direction(Direction::Vertical)
-            .margin(2)
-            .constraints(
-                [
-                    Constraint::Length(3),
-                    Constraint::Length(3),
-                    Constraint::Length(7),
-                    Constraint::Min(0),
-                ]
-                    .as_ref(),
-            ).split(area)
-    }
-}
\ No newline at end of file
+            .constraints(row_constraints)
+            .split(area)
+            .into_iter()
+            .flat_map(|row| Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(column_constraints.clone())
+                .split(row))
+            .take(pane_count)
+            .collect()
+    }
+}
+
+fn series_color(idx: usize) -> Color {
+    SERIES_COLORS[idx % SERIES_COLORS.len()]
+}
+
+/// What's on screen and where the cursor is on the first line, the keys on the second
+fn status_line(state: &ChartState, readout: &[(&PriceSeries, &[(f64, f64)])]) -> Paragraph<'static> {
+    let mut position = format!("Range {}", state.range.label());
+    if !state.compare && state.page_count > 1 {
+        position.push_str(&format!("  Page {}/{}", state.page + 1, state.page_count));
+    }
+    if let Some(cursor) = state.cursor {
+        for (series, points) in readout {
+            if let Some((time, price)) = points.get(nearest_index_by_time(points, cursor)) {
+                let price_date = series.exchange_tz.from_utc_datetime(&DateTime::from_timestamp(*time as i64, 0).unwrap_or_default().naive_utc());
+                position.push_str(&format!("  {} {} {:.2}", series.code, price_date.format("%Y-%m-%d %H:%M"), price));
+            }
+        }
+    }
+
+    let paging = if !state.compare && state.page_count > 1 { "n/p: page  " } else { "" };
+    let view = if state.compare { "c: separate charts" } else { "c: compare" };
+    let scale = match state.scale {
+        PriceScale::Absolute => "%: percent change",
+        PriceScale::PercentChange => "%: prices",
+    };
+    let keys = format!("1-6/+/-: range  </>: pan  \u{2190}/\u{2192}: cursor  esc: hide cursor  {}{}  {}  q: quit", paging, view, scale);
+
+    Paragraph::new(vec![
+        Spans::from(Span::styled(position, Style::default().fg(Color::White))),
+        Spans::from(Span::styled(keys, Style::default().fg(Color::Gray))),
+    ])
+}
+
+fn render_error<E: std::fmt::Display>(e: E) -> StockError {
+    StockError::Render(e.to_string())
+}
+
+/// Just the samples inside the window
+fn visible_points(points: &[(f64, f64)], window: [f64; 2]) -> Vec<(f64, f64)> {
+    points.iter()
+        .filter(|(time, _)| *time >= window[0] && *time <= window[1])
+        .copied()
+        .collect()
+}
+
+/// Index of the sorted time closest to `target`
+fn nearest_index(times: &[f64], target: f64) -> usize {
+    let after = times.partition_point(|time| *time < target);
+    match (after.checked_sub(1), times.get(after)) {
+        (Some(before), Some(next)) if target - times[before] <= next - target => before,
+        (Some(before), None) => before,
+        _ => after,
+    }
+}
+
+/// Same as `nearest_index`, for plot points sorted by time
+fn nearest_index_by_time(points: &[(f64, f64)], target: f64) -> usize {
+    let times: Vec<f64> = points.iter().map(|(time, _)| *time).collect();
+    nearest_index(&times, target)
+}
+
+fn price_points(code: &str, share_prices: &[Share]) -> StockResult<Vec<(f64, f64)>> {
+    share_prices.iter()
+        .map(|share| {
+            share.price.amount.to_f64()
+                .map(|price| (share.price_date.and_utc().timestamp() as f64, price))
+                .ok_or_else(|| StockError::Parse(format!("Price {} for {} is too big to chart", share.price, code)))
+        })
+        .collect()
+}
+
+/// First to last sample, opened up by a day either side when there's only one moment to show
+fn time_bounds(times: &[f64]) -> [f64; 2] {
+    let first = times.iter().copied().fold(f64::INFINITY, f64::min);
+    let last = times.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if !first.is_finite() {
+        let now = Utc::now().timestamp() as f64;
+        return [now - SECONDS_PER_DAY, now];
+    }
+    if last - first < 1.0 {
+        return [first - SECONDS_PER_DAY, last + SECONDS_PER_DAY];
+    }
+    [first, last]
+}
+
+/// Lowest to highest price with a bit of padding, so the line doesn't sit on the border
+fn price_bounds(points: &[(f64, f64)]) -> [f64; 2] {
+    let low = points.iter().map(|(_, price)| *price).fold(f64::INFINITY, f64::min);
+    let high = points.iter().map(|(_, price)| *price).fold(f64::NEG_INFINITY, f64::max);
+    if !low.is_finite() {
+        return [0.0, 1.0];
+    }
+    let padding = if high > low {
+        (high - low) * PRICE_PADDING
+    } else {
+        (low.abs() * PRICE_PADDING).max(1.0)
+    };
+    [low - padding, high + padding]
+}
+
+/// On the exchange's clock, like the cursor readout
+fn date_labels(bounds: [f64; 2], label_tz: Tz) -> Vec<Span<'static>> {
+    let date_fmt = if bounds[1] - bounds[0] <= 2.0 * SECONDS_PER_DAY { "%m-%d %H:%M" } else { "%Y-%m-%d" };
+    let middle = (bounds[0] + bounds[1]) / 2.0;
+    [bounds[0], middle, bounds[1]].iter()
+        .map(|time| DateTime::from_timestamp(*time as i64, 0).unwrap_or_default().with_timezone(&label_tz))
+        .map(|label_time| Span::raw(label_time.format(date_fmt).to_string()))
+        .collect()
+}
+
+fn price_labels(bounds: [f64; 2], scale: PriceScale) -> Vec<Span<'static>> {
+    let middle = (bounds[0] + bounds[1]) / 2.0;
+    let label = |value: f64| match scale {
+        PriceScale::Absolute => format!("{:.2}", value),
+        PriceScale::PercentChange => format!("{:+.1}%", value),
+    };
+    vec![
+        Span::raw(label(bounds[0])),
+        Span::raw(label(middle)),
+        Span::styled(label(bounds[1]), Style::default().add_modifier(Modifier::BOLD)),
+    ]
+}
+
+/// One dataset per series plus the crosshair, if it's on screen
+fn price_chart<'a>(view: &'a ChartView<'a>, scale: PriceScale) -> Chart<'a> {
+    let mut share_price_datasets: Vec<Dataset> = view.series.iter()
+        .map(|(code, points, color)| Dataset::default()
+            .name(*code)
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(*color))
+            .graph_type(GraphType::Line)
+            .data(points))
+        .collect();
+    if !view.crosshair.is_empty() {
+        share_price_datasets.push(Dataset::default()
+            .name("cursor")
+            .marker(symbols::Marker::Braille)
+            .style(Style::default().fg(Color::Gray))
+            .graph_type(GraphType::Line)
+            .data(&view.crosshair));
+    }
+
+    Chart::new(share_price_datasets)
+        .block(
+            Block::default()
+                .title(Span::styled(
+                    view.title,
+                    Style::default()
+                        .fg(Color::Cyan)
+                        .add_modifier(Modifier::BOLD),
+                ))
+                .borders(Borders::ALL),
+        )
+        .x_axis(
+            Axis::default()
+                .title("Date")
+                .style(Style::default().fg(Color::Gray))
+                .bounds(view.x_bounds)
+                .labels(date_labels(view.x_bounds, view.label_tz)),
+        )
+        .y_axis(
+            Axis::default()
+                .title(scale.axis_title())
+                .style(Style::default().fg(Color::Gray))
+                .bounds(view.y_bounds)
+                .labels(price_labels(view.y_bounds, scale)),
+        )
+        //keep the legend up even with a handful of tickers in it
+        .hidden_legend_constraints((Constraint::Ratio(1, 2), Constraint::Ratio(1, 2)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sample at midnight on each of the first `days` days of the epoch
+    fn daily_samples(days: usize) -> Vec<f64> {
+        (0..days).map(|day| day as f64 * SECONDS_PER_DAY).collect()
+    }
+
+    fn state_showing(range: TimeRange) -> ChartState {
+        ChartState {
+            compare: false,
+            scale: PriceScale::Absolute,
+            page: 0,
+            page_count: 1,
+            range,
+            view_end: None,
+            cursor: None,
+        }
+    }
+
+    fn area(width: u16, height: u16) -> Rect {
+        Rect::new(0, 0, width, height)
+    }
+
+    #[test]
+    fn grid_prefers_rows_until_they_run_out() {
+        assert_eq!(ChartGrapher::grid_for(1, area(120, 40)), ChartGrid { columns: 1, rows: 1 });
+        assert_eq!(ChartGrapher::grid_for(0, area(120, 40)), ChartGrid { columns: 1, rows: 1 });
+        assert_eq!(ChartGrapher::grid_for(4, area(120, 40)), ChartGrid { columns: 1, rows: 4 });
+        assert_eq!(ChartGrapher::grid_for(6, area(120, 40)), ChartGrid { columns: 2, rows: 3 });
+        //only 2x2 fits, the rest go on another page
+        let grid = ChartGrapher::grid_for(6, area(80, 20));
+        assert_eq!(grid, ChartGrid { columns: 2, rows: 2 });
+        assert_eq!(grid.per_page(), 4);
+        //too small for even one pane still gets one
+        assert_eq!(ChartGrapher::grid_for(3, area(10, 5)), ChartGrid { columns: 1, rows: 1 });
+    }
+
+    #[test]
+    fn time_ranges_zoom_without_falling_off_the_ends() {
+        assert_eq!(TimeRange::Week.span(), Some(7.0 * SECONDS_PER_DAY));
+        assert_eq!(TimeRange::All.span(), None);
+        assert_eq!(TimeRange::Week.zoomed_in(), TimeRange::Day);
+        assert_eq!(TimeRange::Day.zoomed_in(), TimeRange::Day);
+        assert_eq!(TimeRange::Year.zoomed_out(), TimeRange::All);
+        assert_eq!(TimeRange::All.zoomed_out(), TimeRange::All);
+    }
+
+    #[test]
+    fn window_ends_on_the_latest_sample_until_panned() {
+        let samples = daily_samples(100);
+        let last = samples[99];
+        assert_eq!(state_showing(TimeRange::All).window(&samples), [0.0, last]);
+        assert_eq!(state_showing(TimeRange::Week).window(&samples), [last - 7.0 * SECONDS_PER_DAY, last]);
+        //a lone sample is opened up a day either side
+        assert_eq!(time_bounds(&[SECONDS_PER_DAY]), [0.0, 2.0 * SECONDS_PER_DAY]);
+    }
+
+    #[test]
+    fn panning_stops_at_either_end() {
+        let samples = daily_samples(100);
+        let week = 7.0 * SECONDS_PER_DAY;
+        let mut state = state_showing(TimeRange::Week);
+
+        state.pan(1.0, &samples);
+        assert_eq!(state.view_end, None);
+        state.pan(-1.0, &samples);
+        assert_eq!(state.view_end, Some(samples[99] - week * PAN_FRACTION));
+        for _ in 0..1000 {
+            state.pan(-1.0, &samples);
+        }
+        assert_eq!(state.window(&samples), [0.0, week]);
+        for _ in 0..1000 {
+            state.pan(1.0, &samples);
+        }
+        assert_eq!(state.view_end, None);
+
+        //nothing to pan when it's all on screen, or the history is shorter than the window
+        let mut everything = state_showing(TimeRange::All);
+        everything.pan(-1.0, &samples);
+        assert_eq!(everything.view_end, None);
+        let mut short = state_showing(TimeRange::Year);
+        short.pan(-1.0, &samples);
+        assert_eq!(short.view_end, None);
+    }
+
+    #[test]
+    fn cursor_steps_through_samples_and_drags_the_window() {
+        let samples = daily_samples(100);
+        let mut state = state_showing(TimeRange::Week);
+
+        state.move_cursor(1, &samples);
+        assert_eq!(state.cursor, Some(samples[99]));
+        state.move_cursor(1, &samples);
+        assert_eq!(state.cursor, Some(samples[99]));
+        for _ in 0..10 {
+            state.move_cursor(-1, &samples);
+        }
+        assert_eq!(state.cursor, Some(samples[89]));
+        //the cursor went off the left of the week, so the week followed it
+        let window = state.window(&samples);
+        assert_eq!(window, [samples[89], samples[89] + 7.0 * SECONDS_PER_DAY]);
+
+        for _ in 0..200 {
+            state.move_cursor(-1, &samples);
+        }
+        assert_eq!(state.cursor, Some(0.0));
+        assert_eq!(state.window(&samples)[0], 0.0);
+
+        //zooming in keeps it on screen too
+        state.cursor = Some(samples[50]);
+        state.range = TimeRange::Day;
+        state.follow_cursor(&samples);
+        let window = state.window(&samples);
+        assert!(window[0] <= samples[50] && samples[50] <= window[1]);
+    }
+
+    #[test]
+    fn date_labels_are_on_the_exchange_clock() {
+        let labels = |bounds: [f64; 2], label_tz: Tz| date_labels(bounds, label_tz).into_iter().map(|span| span.content.to_string()).collect::<Vec<_>>();
+        assert_eq!(labels([0.0, 3600.0], Tz::Africa__Johannesburg), ["01-01 02:00", "01-01 02:30", "01-01 03:00"]);
+        assert_eq!(labels([0.0, 3600.0], Tz::UTC), ["01-01 00:00", "01-01 00:30", "01-01 01:00"]);
+        //just after midnight UTC is still the day before in New York
+        assert_eq!(labels([3600.0, 10.0 * SECONDS_PER_DAY], Tz::America__New_York)[0], "1969-12-31");
+    }
+
+    #[test]
+    fn nearest_index_takes_the_earlier_sample_on_a_tie() {
+        let times = [0.0, 10.0, 20.0];
+        assert_eq!(nearest_index(&times, 5.0), 0);
+        assert_eq!(nearest_index(&times, 6.0), 1);
+        assert_eq!(nearest_index(&times, 10.0), 1);
+        assert_eq!(nearest_index(&times, -5.0), 0);
+        assert_eq!(nearest_index(&times, 50.0), 2);
+        assert_eq!(nearest_index(&[], 5.0), 0);
+        assert_eq!(nearest_index_by_time(&[(0.0, 1.0), (10.0, 2.0)], 7.0), 1);
+    }
+
+    #[test]
+    fn percent_change_is_from_the_first_point() {
+        let points = [(0.0, 100.0), (1.0, 110.0), (2.0, 90.0)];
+        let changes = PriceScale::PercentChange.apply(&points);
+        let rounded: Vec<(f64, f64)> = changes.iter().map(|(time, change)| (*time, (change * 100.0).round() / 100.0)).collect();
+        assert_eq!(rounded, vec![(0.0, 0.0), (1.0, 10.0), (2.0, -10.0)]);
+        assert_eq!(PriceScale::Absolute.apply(&points), points.to_vec());
+        //nothing to rebase on
+        assert_eq!(PriceScale::PercentChange.apply(&[(0.0, 0.0), (1.0, 5.0)]), vec![(0.0, 0.0), (1.0, 5.0)]);
+        assert!(PriceScale::PercentChange.apply(&[]).is_empty());
+        assert_eq!(PriceScale::Absolute.toggled(), PriceScale::PercentChange);
+    }
+}
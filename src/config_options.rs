@@ -1,8 +1,30 @@
 // use mysql::Opts;
+use std::path::Path;
+
 use crate::db_model::ConnectionDetails;
+use crate::error::{StockError, StockResult};
+use crate::notifier::NotifyConfig;
+
+const DB_CONFIG_FILE: &str = "db.json";
+const NOTIFY_CONFIG_FILE: &str = "notify.json";
 
-pub fn read_db_config() -> Result<ConnectionDetails, Box<dyn std::error::Error>> {
-    let json = std::fs::read_to_string("db.json")?;
+/// Without a db.json we fall back to a local sqlite file, so there's nothing to set up
+pub fn read_db_config() -> StockResult<ConnectionDetails> {
+    if !Path::new(DB_CONFIG_FILE).exists() {
+        return Ok(ConnectionDetails::default());
+    }
+    let json = std::fs::read_to_string(DB_CONFIG_FILE)
+        .map_err(|e| StockError::Config(format!("Couldn't read {}: {}", DB_CONFIG_FILE, e)))?;
     // let c:Connection = serde_json::from_str(&json).unwrap();
     Ok(serde_json::from_str(&json)?)
-}
\ No newline at end of file
+}
+
+/// Where alerts get sent. Without a notify.json they only show up in the terminal.
+pub fn read_notify_config() -> StockResult<NotifyConfig> {
+    if !Path::new(NOTIFY_CONFIG_FILE).exists() {
+        return Ok(NotifyConfig::default());
+    }
+    let json = std::fs::read_to_string(NOTIFY_CONFIG_FILE)
+        .map_err(|e| StockError::Config(format!("Couldn't read {}: {}", NOTIFY_CONFIG_FILE, e)))?;
+    serde_json::from_str(&json).map_err(|e| StockError::Config(format!("Couldn't read {}: {}", NOTIFY_CONFIG_FILE, e)))
+}
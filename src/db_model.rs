@@ -1,7 +1,50 @@
 use serde::Deserialize;
+
+pub const DEFAULT_SQLITE_PATH: &str = "stockchecker.db";
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Sqlite,
+    Mysql,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ConnectionDetails {
+    pub backend: Option<Backend>,
+    #[serde(default)]
     pub database: String,
+    #[serde(default)]
     pub username: String,
-    pub password: String
-}
\ No newline at end of file
+    #[serde(default)]
+    pub password: String,
+    #[serde(default = "default_sqlite_path")]
+    pub sqlite_path: String,
+}
+
+fn default_sqlite_path() -> String {
+    DEFAULT_SQLITE_PATH.to_string()
+}
+
+impl ConnectionDetails {
+    /// Old db.json files only had the mysql details in them, so those stay on mysql
+    pub fn backend(&self) -> Backend {
+        match self.backend {
+            Some(backend) => backend,
+            None if !self.database.is_empty() => Backend::Mysql,
+            None => Backend::Sqlite,
+        }
+    }
+}
+
+impl Default for ConnectionDetails {
+    fn default() -> Self {
+        ConnectionDetails {
+            backend: Some(Backend::Sqlite),
+            database: String::new(),
+            username: String::new(),
+            password: String::new(),
+            sqlite_path: default_sqlite_path(),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+
+/// Everything that can go wrong in stockchecker, bucketed by where it went wrong
+#[derive(Debug)]
+pub enum StockError {
+    Config(String),
+    Network(String),
+    Parse(String),
+    Db(String),
+    Render(String),
+}
+
+pub type StockResult<T> = Result<T, StockError>;
+
+impl StockError {
+    /// Each kind of failure gets its own exit code so scripts can tell them apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            StockError::Config(_) => 2,
+            StockError::Network(_) => 3,
+            StockError::Parse(_) => 4,
+            StockError::Db(_) => 5,
+            StockError::Render(_) => 6,
+        }
+    }
+}
+
+impl fmt::Display for StockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StockError::Config(msg) => write!(f, "Configuration problem: {}", msg),
+            StockError::Network(msg) => write!(f, "Network problem: {}", msg),
+            StockError::Parse(msg) => write!(f, "Couldn't understand the data: {}", msg),
+            StockError::Db(msg) => write!(f, "Database problem: {}", msg),
+            StockError::Render(msg) => write!(f, "Couldn't draw the output: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StockError {}
+
+impl From<mysql::Error> for StockError {
+    fn from(e: mysql::Error) -> Self {
+        StockError::Db(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for StockError {
+    fn from(e: reqwest::Error) -> Self {
+        StockError::Network(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StockError {
+    fn from(e: serde_json::Error) -> Self {
+        StockError::Config(e.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for StockError {
+    fn from(e: rusqlite::Error) -> Self {
+        StockError::Db(e.to_string())
+    }
+}
@@ -0,0 +1,283 @@
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, FixedLenByteArray, FixedLenByteArrayType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::parser::parse_message_type;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::error::{StockError, StockResult};
+use crate::quote_format::{decimal_number, utc_string, utc_timestamp};
+use crate::share_price_model::Share;
+
+pub const FORMAT_NAMES: [&str; 3] = ["csv", "jsonl", "parquet"];
+
+const CSV_HEADER: [&str; 6] = ["code", "price", "currency", "price_date", "exchange_tz", "source"];
+
+/// How saved prices get written out, one row per price
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    JsonLines,
+    Parquet,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" | "ndjson" => Ok(ExportFormat::JsonLines),
+            "parquet" => Ok(ExportFormat::Parquet),
+            _ => Err(format!("'{}' isn't an export format, try one of {}", s, FORMAT_NAMES.join(", "))),
+        }
+    }
+}
+
+/// Which saved prices go out. Days are UTC and both ends are included.
+#[derive(Debug, Default)]
+pub struct ExportFilter {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// Prices saved before we kept the source never match one
+    pub source: Option<String>,
+}
+
+impl ExportFilter {
+    pub fn matches(&self, share: &Share) -> bool {
+        let day = share.price_date.date();
+        self.from.is_none_or(|from| day >= from)
+            && self.to.is_none_or(|to| day <= to)
+            && self.source.as_ref().is_none_or(|source| share.source.as_ref() == Some(source))
+    }
+}
+
+/// A saved price as a line of JSON, the same fields as the CSV columns
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    code: &'a str,
+    #[serde(serialize_with = "decimal_number")]
+    price: Decimal,
+    currency: Option<String>,
+    #[serde(serialize_with = "utc_timestamp")]
+    price_date: NaiveDateTime,
+    exchange_tz: &'a str,
+    source: Option<&'a str>,
+}
+
+impl<'a> ExportRow<'a> {
+    fn new(share: &'a Share) -> ExportRow<'a> {
+        ExportRow {
+            code: &share.company_code,
+            price: share.price.amount,
+            currency: share.price.currency.as_ref().map(ToString::to_string),
+            price_date: share.price_date,
+            exchange_tz: share.exchange_tz.name(),
+            source: share.source.as_deref(),
+        }
+    }
+}
+
+/// Writes every share, handing back how many that was. CSV keeps prices exactly as saved,
+/// JSON Lines has them as numbers like `--format json` does and Parquet as exact decimals.
+pub fn write_prices<W: Write>(mut writer: W, format: ExportFormat, shares: &[Share]) -> StockResult<usize> {
+    let write_error = |e: std::io::Error| StockError::Render(format!("Couldn't write the export: {}", e));
+    match format {
+        ExportFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            let csv_error = |e: csv::Error| StockError::Render(format!("Couldn't write the export: {}", e));
+            csv_writer.write_record(CSV_HEADER).map_err(csv_error)?;
+            for share in shares {
+                csv_writer.write_record(&[
+                    share.company_code.clone(),
+                    share.price.amount.to_string(),
+                    share.price.currency_label(),
+                    utc_string(share.price_date),
+                    share.exchange_tz.name().to_string(),
+                    share.source.clone().unwrap_or_default(),
+                ]).map_err(csv_error)?;
+            }
+            csv_writer.flush().map_err(write_error)?;
+        }
+        ExportFormat::JsonLines => {
+            for share in shares {
+                serde_json::to_writer(&mut writer, &ExportRow::new(share))
+                    .map_err(|e| StockError::Render(format!("Couldn't write the export: {}", e)))?;
+                writer.write_all(b"\n").map_err(write_error)?;
+            }
+            writer.flush().map_err(write_error)?;
+        }
+        ExportFormat::Parquet => {
+            let file = parquet_file(shares).map_err(|e| StockError::Render(format!("Couldn't write the export: {}", e)))?;
+            writer.write_all(&file).map_err(write_error)?;
+            writer.flush().map_err(write_error)?;
+        }
+    }
+    Ok(shares.len())
+}
+
+/// The whole file at once, the parquet writer won't take stdout as it isn't Send.
+/// Prices are DECIMAL(38, n), n being the most decimals any of them has.
+fn parquet_file(shares: &[Share]) -> Result<Vec<u8>, ParquetError> {
+    let scale = shares.iter().map(|share| share.price.amount.scale()).max().unwrap_or(0);
+    let schema = parse_message_type(&format!("message price {{
+        REQUIRED BYTE_ARRAY code (STRING);
+        REQUIRED FIXED_LEN_BYTE_ARRAY (16) price (DECIMAL(38, {}));
+        OPTIONAL BYTE_ARRAY currency (STRING);
+        REQUIRED INT64 price_date (TIMESTAMP(MICROS, true));
+        REQUIRED BYTE_ARRAY exchange_tz (STRING);
+        OPTIONAL BYTE_ARRAY source (STRING);
+    }}", scale))?;
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut file_writer = SerializedFileWriter::new(Vec::new(), Arc::new(schema), Arc::new(properties))?;
+
+    let mut row_group = file_writer.next_row_group()?;
+    let codes: Vec<_> = shares.iter().map(|share| ByteArray::from(share.company_code.as_str())).collect();
+    write_column::<ByteArrayType>(&mut row_group, &codes, None)?;
+    let prices = shares.iter().map(|share| scaled_price(share.price.amount, scale)).collect::<Result<Vec<_>, _>>()?;
+    write_column::<FixedLenByteArrayType>(&mut row_group, &prices, None)?;
+    write_optional_text(&mut row_group, shares.iter().map(|share| share.price.currency.as_ref().map(ToString::to_string)))?;
+    let price_dates: Vec<_> = shares.iter().map(|share| share.price_date.and_utc().timestamp_micros()).collect();
+    write_column::<Int64Type>(&mut row_group, &price_dates, None)?;
+    let zones: Vec<_> = shares.iter().map(|share| ByteArray::from(share.exchange_tz.name())).collect();
+    write_column::<ByteArrayType>(&mut row_group, &zones, None)?;
+    write_optional_text(&mut row_group, shares.iter().map(|share| share.source.clone()))?;
+    row_group.close()?;
+
+    file_writer.into_inner()
+}
+
+fn write_column<T: DataType>(row_group: &mut SerializedRowGroupWriter<'_, Vec<u8>>, values: &[T::T], def_levels: Option<&[i16]>) -> Result<(), ParquetError> {
+    let mut column = row_group.next_column()?
+        .ok_or_else(|| ParquetError::General("More columns written than the schema has".to_string()))?;
+    column.typed::<T>().write_batch(values, def_levels, None)?;
+    column.close()
+}
+
+/// Only the values that are there get written, a definition level of 0 marks a missing one
+fn write_optional_text<I: Iterator<Item = Option<String>>>(row_group: &mut SerializedRowGroupWriter<'_, Vec<u8>>, texts: I) -> Result<(), ParquetError> {
+    let mut values = Vec::new();
+    let mut def_levels = Vec::new();
+    for text in texts {
+        def_levels.push(i16::from(text.is_some()));
+        values.extend(text.map(|text| ByteArray::from(text.into_bytes())));
+    }
+    write_column::<ByteArrayType>(row_group, &values, Some(&def_levels))
+}
+
+/// The price's digits without its point, moved up to the shared scale, as 16 big-endian bytes
+fn scaled_price(price: Decimal, scale: u32) -> Result<FixedLenByteArray, ParquetError> {
+    let too_big = || ParquetError::General(format!("{} doesn't fit 38 digits at {} decimals", price, scale));
+    let unscaled = i128::from_str(&price.to_string().replace('.', "")).map_err(|_| too_big())?;
+    let scaled = 10_i128.checked_pow(scale - price.scale())
+        .and_then(|factor| unscaled.checked_mul(factor))
+        .filter(|scaled| scaled.unsigned_abs() < 10_u128.pow(38))
+        .ok_or_else(too_big)?;
+    Ok(FixedLenByteArray::from(scaled.to_be_bytes().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+    use serde_json::Value;
+
+    use crate::share_price_model::Price;
+
+    use super::*;
+
+    fn share(price: &str, day: u32, source: Option<&str>) -> Share {
+        Share {
+            company_code: "NPN".to_string(),
+            price: Price::in_currency(Decimal::from_str(price).unwrap(), Some("ZAR".parse().unwrap())),
+            price_date: NaiveDate::from_ymd_opt(2020, 10, day).unwrap().and_hms_opt(15, 0, 0).unwrap(),
+            exchange_tz: Tz::Africa__Johannesburg,
+            source: source.map(ToString::to_string),
+        }
+    }
+
+    fn export(format: ExportFormat, shares: &[Share]) -> String {
+        let mut out = Vec::new();
+        assert_eq!(write_prices(&mut out, format, shares).unwrap(), shares.len());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_keeps_prices_as_saved() {
+        let csv = export(ExportFormat::Csv, &[share("2834.50", 16, Some("google")), share("2750.00", 15, None)]);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines, vec![
+            "code,price,currency,price_date,exchange_tz,source",
+            "NPN,2834.50,ZAR,2020-10-16T15:00:00Z,Africa/Johannesburg,google",
+            "NPN,2750.00,ZAR,2020-10-15T15:00:00Z,Africa/Johannesburg,",
+        ]);
+    }
+
+    #[test]
+    fn json_lines_has_one_object_a_line() {
+        let jsonl = export(ExportFormat::JsonLines, &[share("2834.50", 16, Some("import")), share("2750.00", 15, None)]);
+        let rows: Vec<Value> = jsonl.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["price"], 2834.5);
+        assert_eq!(rows[0]["price_date"], "2020-10-16T15:00:00Z");
+        assert_eq!(rows[0]["source"], "import");
+        assert!(rows[1]["source"].is_null());
+    }
+
+    #[test]
+    fn parquet_reads_back_with_exact_prices() {
+        use std::convert::TryInto;
+
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+        use parquet::record::RowAccessor;
+
+        let path = std::env::temp_dir().join(format!("stockchecker-export-{}.parquet", std::process::id()));
+        let shares = [share("2834.5", 16, Some("google")), share("-0.125", 15, None), share("1200", 14, Some("import"))];
+        write_prices(std::fs::File::create(&path).unwrap(), ExportFormat::Parquet, &shares).unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let rows: Vec<_> = reader.get_row_iter(None).unwrap().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 3);
+        let prices: Vec<_> = rows.iter()
+            .map(|row| row.get_decimal(1).unwrap())
+            .map(|price| Decimal::from_i128_with_scale(i128::from_be_bytes(price.data().try_into().unwrap()), price.scale() as u32).to_string())
+            .collect();
+        assert_eq!(prices, vec!["2834.500", "-0.125", "1200.000"]);
+        assert_eq!(rows[0].get_string(0).unwrap(), "NPN");
+        assert_eq!(rows[0].get_string(2).unwrap(), "ZAR");
+        let micros = NaiveDate::from_ymd_opt(2020, 10, 16).unwrap().and_hms_opt(15, 0, 0).unwrap().and_utc().timestamp_micros();
+        assert_eq!(rows[0].get_timestamp_micros(3).unwrap(), micros);
+        assert_eq!(rows[0].get_string(4).unwrap(), "Africa/Johannesburg");
+        assert_eq!(rows[0].get_string(5).unwrap(), "google");
+        assert!(rows[1].get_string(5).is_err());
+        assert_eq!(rows[2].get_string(5).unwrap(), "import");
+    }
+
+    #[test]
+    fn parquet_refuses_prices_past_38_digits() {
+        let shares = [share("79228162514264337593543950335", 16, None), share("0.0000000001", 15, None)];
+        let e = write_prices(Vec::new(), ExportFormat::Parquet, &shares).unwrap_err();
+        assert!(e.to_string().contains("doesn't fit 38 digits"), "{}", e);
+    }
+
+    #[test]
+    fn filter_by_day_and_source() {
+        let shares = [share("1", 14, Some("google")), share("2", 15, Some("google")), share("3", 16, None), share("4", 17, Some("import"))];
+        let kept = |export_filter: ExportFilter| shares.iter()
+            .filter(|share| export_filter.matches(share))
+            .map(|share| share.price.to_string())
+            .collect::<Vec<_>>();
+        let from = Some(NaiveDate::from_ymd_opt(2020, 10, 15).unwrap());
+        let to = Some(NaiveDate::from_ymd_opt(2020, 10, 16).unwrap());
+        assert_eq!(kept(ExportFilter { from, to, source: None }), vec!["2", "3"]);
+        assert_eq!(kept(ExportFilter { source: Some("google".to_string()), ..ExportFilter::default() }), vec!["1", "2"]);
+        assert_eq!(kept(ExportFilter::default()).len(), 4);
+    }
+}
@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+use log::debug;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::{One, Zero};
+use serde::Deserialize;
+
+use crate::error::{StockError, StockResult};
+use crate::portfolio::Holding;
+use crate::share_price_model::{CurrencyCode, FetchOutcome, Price, Share, ShareTimeline};
+
+pub mod google;
+pub mod replay;
+
+pub const CSV_SOURCE_NAME: &str = "csv";
+
+/// Converted prices are shown to the cent, like everything else we show. Underneath they keep
+/// every place the rate gave them, so movements between two of them come out right.
+const CONVERTED_PLACES: u32 = 2;
+
+/// Codes quoted in hundredths of another currency, like LSE listings in pence
+const MINOR_UNITS: [(&str, &str); 2] = [("GBX", "GBP"), ("ZAC", "ZAR")];
+
+/// What one `base` was worth in `quote` on a day
+#[derive(Debug, Clone, PartialEq)]
+pub struct FxRate {
+    pub base: CurrencyCode,
+    pub quote: CurrencyCode,
+    pub rate_date: NaiveDate,
+    pub rate: Decimal,
+    /// Where the rate came from, a source name or "csv"
+    pub source: String,
+}
+
+/// Somewhere we can go and get today's exchange rate
+#[async_trait]
+pub trait RateSource: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn fetch_rate(&self, base: &CurrencyCode, quote: &CurrencyCode) -> StockResult<FxRate>;
+}
+
+/// The rate source to go with the price source picked on the command line
+pub fn rate_source_for_name(source_name: &str, fixtures_dir: &str) -> StockResult<Box<dyn RateSource>> {
+    match source_name {
+        google::SOURCE_NAME => Ok(Box::new(google::GoogleRates::new())),
+        replay::SOURCE_NAME => Ok(Box::new(replay::ReplayRates::new(fixtures_dir))),
+        _ => Err(StockError::Config(format!("Unknown rate source '{}'", source_name))),
+    }
+}
+
+/// Pence to pounds and the like, anything else comes back as it was
+pub fn in_major_unit(price: &Price) -> Price {
+    let currency = match &price.currency {
+        Some(currency) => currency.to_string(),
+        None => return price.clone(),
+    };
+    match MINOR_UNITS.iter().find(|(minor, _)| *minor == currency) {
+        //only ever three letters, so the parse can't fail
+        Some((_, major)) => Price::in_currency(price.amount / Decimal::from(100), Some(major.parse().unwrap())),
+        None => price.clone(),
+    }
+}
+
+/// Every rate we know, by pair and then by day
+#[derive(Debug, Default)]
+pub struct FxTable {
+    rates: HashMap<(CurrencyCode, CurrencyCode), BTreeMap<NaiveDate, Decimal>>,
+}
+
+impl FxTable {
+    pub fn new(fx_rates: &[FxRate]) -> FxTable {
+        let mut fx_table = FxTable::default();
+        fx_table.add(fx_rates);
+        fx_table
+    }
+
+    pub fn add(&mut self, fx_rates: &[FxRate]) {
+        for fx_rate in fx_rates {
+            self.rates.entry((fx_rate.base.clone(), fx_rate.quote.clone()))
+                .or_default()
+                .insert(fx_rate.rate_date, fx_rate.rate);
+        }
+    }
+
+    /// The latest rate from on or before `on`, either way round. Never one from after it,
+    /// a price from last year gets last year's rate or none at all.
+    pub fn rate_on(&self, from: &CurrencyCode, to: &CurrencyCode, on: NaiveDate) -> Option<Decimal> {
+        if from == to {
+            return Some(Decimal::one());
+        }
+        let latest = |base: &CurrencyCode, quote: &CurrencyCode| self.rates.get(&(base.clone(), quote.clone()))
+            .and_then(|by_day| by_day.range(..=on).next_back())
+            .map(|(rate_date, rate)| (*rate_date, *rate));
+        let direct = latest(from, to);
+        let inverse = latest(to, from)
+            .filter(|(_, rate)| !rate.is_zero())
+            .map(|(rate_date, rate)| (rate_date, Decimal::one() / rate));
+        match (direct, inverse) {
+            (Some(direct), Some(inverse)) => Some(if inverse.0 > direct.0 { inverse.1 } else { direct.1 }),
+            (direct, inverse) => direct.or(inverse).map(|(_, rate)| rate),
+        }
+    }
+
+    /// Whether there's a rate from exactly that day, either way round
+    pub fn has_rate_for(&self, from: &CurrencyCode, to: &CurrencyCode, on: NaiveDate) -> bool {
+        let on_day = |base: &CurrencyCode, quote: &CurrencyCode| self.rates.get(&(base.clone(), quote.clone()))
+            .map(|by_day| by_day.contains_key(&on))
+            .unwrap_or(false);
+        on_day(from, to) || on_day(to, from)
+    }
+}
+
+/// A price we couldn't convert for want of a rate
+#[derive(Debug, Clone, PartialEq)]
+pub struct MissingRate {
+    pub from: CurrencyCode,
+    pub to: CurrencyCode,
+    pub on: NaiveDate,
+}
+
+impl fmt::Display for MissingRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No {} to {} rate on or before {}, see `stockchecker fx`", self.from, self.to, self.on)
+    }
+}
+
+/// Puts prices into the reporting currency, each one at the rate of the day it was taken
+pub struct Converter {
+    pub to: CurrencyCode,
+    pub fx_table: FxTable,
+}
+
+impl Converter {
+    pub fn new(to: CurrencyCode, fx_table: FxTable) -> Converter {
+        Converter { to, fx_table }
+    }
+
+    /// A price without a currency can't be converted, so it's left as it is
+    pub fn convert_price(&self, price: &Price, on: NaiveDate) -> Result<Price, MissingRate> {
+        let price = in_major_unit(price);
+        let from = match &price.currency {
+            Some(from) if *from != self.to => from,
+            _ => return Ok(price),
+        };
+        match self.fx_table.rate_on(from, &self.to, on) {
+            Some(rate) => Ok(Price {
+                display_places: Some(CONVERTED_PLACES),
+                ..Price::in_currency(price.amount * rate, Some(self.to.clone()))
+            }),
+            None => Err(MissingRate { from: from.clone(), to: self.to.clone(), on }),
+        }
+    }
+
+    /// The share at the rate of its own UTC day, or as it was if there isn't one
+    fn convert_share(&self, share: &Share, missing_rates: &mut Vec<MissingRate>) -> Share {
+        let price = match self.convert_price(&share.price, share.price_date.date()) {
+            Ok(price) => price,
+            Err(missing_rate) => {
+                if !missing_rates.contains(&missing_rate) {
+                    missing_rates.push(missing_rate);
+                }
+                share.price.clone()
+            }
+        };
+        Share { price, ..share.clone() }
+    }
+
+    /// Everything we found in the reporting currency. Whatever couldn't be converted stays as it was,
+    /// so it shows up as a currency mismatch rather than a made up movement.
+    /// History saved before we kept the currency is taken to be in today's.
+    pub fn convert_outcomes(&self, company_prices: &[FetchOutcome]) -> (Vec<FetchOutcome>, Vec<MissingRate>) {
+        let mut missing_rates = Vec::new();
+        let converted = company_prices.iter()
+            .map(|outcome| match outcome {
+                FetchOutcome::Found(share_timeline) => FetchOutcome::Found(ShareTimeline {
+                    share: self.convert_share(&share_timeline.share, &mut missing_rates),
+                    share_history: share_timeline.share_history.iter()
+                        .map(|(moment, share)| {
+                            let mut share = share.clone();
+                            if share.price.currency.is_none() {
+                                share.price.currency = share_timeline.share.price.currency.clone();
+                            }
+                            (*moment, self.convert_share(&share, &mut missing_rates))
+                        })
+                        .collect(),
+                }),
+                failure => failure.clone(),
+            })
+            .collect();
+        (converted, missing_rates)
+    }
+
+    /// Every price in a code's history or none of them, so a chart never mixes currencies.
+    /// Prices saved before we kept the currency are taken to be in the latest one's.
+    pub fn convert_series(&self, shares: &[Share], missing_rates: &mut Vec<MissingRate>) -> Vec<Share> {
+        let latest_currency = shares.iter().rev().find_map(|share| share.price.currency.clone());
+        let mut series_missing = Vec::new();
+        let mut converted = Vec::with_capacity(shares.len());
+        for share in shares {
+            let mut share = share.clone();
+            if share.price.currency.is_none() {
+                share.price.currency = latest_currency.clone();
+            }
+            match self.convert_price(&share.price, share.price_date.date()) {
+                Ok(price) => converted.push(Share { price, ..share }),
+                Err(missing_rate) if !series_missing.contains(&missing_rate) => series_missing.push(missing_rate),
+                Err(_) => {}
+            }
+        }
+        if series_missing.is_empty() {
+            return converted;
+        }
+        for missing_rate in series_missing {
+            if !missing_rates.contains(&missing_rate) {
+                missing_rates.push(missing_rate);
+            }
+        }
+        shares.to_vec()
+    }
+
+    /// Today's prices in the reporting currency, and each lot's cost at the rate on the day it was bought.
+    /// Lots are taken to be in the currency their code is quoted in. A code with a price or lot that
+    /// can't be converted stays in its own currency, so it gets a total of its own.
+    pub fn convert_portfolio(&self, holdings: &[Holding], current_prices: HashMap<String, Share>) -> (Vec<Holding>, HashMap<String, Share>, Vec<MissingRate>) {
+        let mut missing_rates = Vec::new();
+        let mut converted_holdings = holdings.to_vec();
+        let mut converted_prices = HashMap::new();
+        for (company_code, share) in current_prices {
+            let mut code_missing = Vec::new();
+            let mut convert = |amount: Decimal, on: NaiveDate| match self.convert_price(&Price::in_currency(amount, share.price.currency.clone()), on) {
+                Ok(price) => Some(price),
+                Err(missing_rate) => {
+                    if !code_missing.contains(&missing_rate) {
+                        code_missing.push(missing_rate);
+                    }
+                    None
+                }
+            };
+            let converted_price = convert(share.price.amount, share.price_date.date());
+            let lots: Vec<_> = holdings.iter().enumerate()
+                .filter(|(_, holding)| holding.company_code == company_code)
+                .map(|(idx, holding)| (idx, convert(holding.buy_price, holding.buy_date), convert(holding.fees, holding.buy_date)))
+                .collect();
+            match converted_price {
+                Some(price) if code_missing.is_empty() => {
+                    for (idx, buy_price, fees) in lots {
+                        if let (Some(buy_price), Some(fees)) = (buy_price, fees) {
+                            converted_holdings[idx].buy_price = buy_price.amount;
+                            converted_holdings[idx].fees = fees.amount;
+                        }
+                    }
+                    converted_prices.insert(company_code, Share { price, ..share });
+                }
+                _ => {
+                    for missing_rate in code_missing {
+                        if !missing_rates.contains(&missing_rate) {
+                            missing_rates.push(missing_rate);
+                        }
+                    }
+                    converted_prices.insert(company_code, share);
+                }
+            }
+        }
+        (converted_holdings, converted_prices, missing_rates)
+    }
+}
+
+/// Today's price for each code we found one for
+pub fn todays_prices(company_prices: &[FetchOutcome]) -> Vec<&Price> {
+    company_prices.iter()
+        .filter_map(FetchOutcome::found)
+        .map(|share_timeline| &share_timeline.share.price)
+        .collect()
+}
+
+/// The currencies of today's prices we'll need a rate for
+pub fn currencies_to_convert(todays_prices: &[&Price], to: &CurrencyCode) -> Vec<CurrencyCode> {
+    let mut currencies = Vec::new();
+    for price in todays_prices {
+        if let Some(currency) = in_major_unit(price).currency {
+            if currency != *to && !currencies.contains(&currency) {
+                currencies.push(currency);
+            }
+        }
+    }
+    currencies
+}
+
+/// Today's rate into `to` for each currency we don't already have one for today.
+/// Hands back the rates it got and what went wrong with the rest.
+pub async fn fetch_missing_rates(rate_source: &dyn RateSource, fx_table: &FxTable, currencies: &[CurrencyCode], to: &CurrencyCode) -> (Vec<FxRate>, Vec<StockError>) {
+    let today = Utc::now().date_naive();
+    let mut fetched = Vec::new();
+    let mut problems = Vec::new();
+    for from in currencies.iter().filter(|from| !fx_table.has_rate_for(from, to, today)) {
+        debug!("Fetching the {} to {} rate from {}", from, to, rate_source.name());
+        match rate_source.fetch_rate(from, to).await {
+            Ok(fx_rate) => fetched.push(fx_rate),
+            Err(e) => problems.push(e),
+        }
+    }
+    (fetched, problems)
+}
+
+#[derive(Debug, Deserialize)]
+struct RateRecord {
+    date: String,
+    base: String,
+    quote: String,
+    rate: String,
+}
+
+/// Reads a CSV with a `date,base,quote,rate` header, dates as YYYY-MM-DD, one `base` being worth `rate` of `quote`
+pub fn read_rates_csv<R: Read>(reader: R) -> StockResult<Vec<FxRate>> {
+    let mut csv_reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(reader);
+    let mut fx_rates = Vec::new();
+    for (idx, record) in csv_reader.deserialize::<RateRecord>().enumerate() {
+        //the header is line 1
+        let line = idx + 2;
+        let record = record.map_err(|e| StockError::Parse(format!("Line {}: {}", line, e)))?;
+        let bad_line = |problem: String| StockError::Parse(format!("Line {}: {}", line, problem));
+        let rate = Decimal::from_str(&record.rate)
+            .ok()
+            .filter(|rate| *rate > Decimal::zero())
+            .ok_or_else(|| bad_line(format!("'{}' needs to be a rate bigger than zero", record.rate)))?;
+        fx_rates.push(FxRate {
+            base: record.base.parse().map_err(bad_line)?,
+            quote: record.quote.parse().map_err(bad_line)?,
+            rate_date: NaiveDate::parse_from_str(&record.date, "%Y-%m-%d")
+                .map_err(|_| bad_line(format!("'{}' needs to be a date like 2020-10-16", record.date)))?,
+            rate,
+            source: CSV_SOURCE_NAME.to_string(),
+        });
+    }
+    Ok(fx_rates)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Tz;
+
+    use crate::share_price_model::{Movement, ShareMoment};
+
+    use super::*;
+
+    fn code(currency: &str) -> CurrencyCode {
+        currency.parse().unwrap()
+    }
+
+    fn day(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2020, month, day).unwrap()
+    }
+
+    fn fx_rate(base: &str, quote: &str, rate_date: NaiveDate, rate: &str) -> FxRate {
+        FxRate { base: code(base), quote: code(quote), rate_date, rate: Decimal::from_str(rate).unwrap(), source: CSV_SOURCE_NAME.to_string() }
+    }
+
+    fn price(amount: &str, currency: &str) -> Price {
+        Price::in_currency(Decimal::from_str(amount).unwrap(), Some(code(currency)))
+    }
+
+    fn converted_price(amount: &str, currency: &str) -> Price {
+        Price { display_places: Some(CONVERTED_PLACES), ..price(amount, currency) }
+    }
+
+    fn share(amount: &str, currency: &str, price_date: NaiveDate) -> Share {
+        Share { company_code: "AAPL".to_string(), price: price(amount, currency), price_date: price_date.and_hms_opt(15, 0, 0).unwrap(), exchange_tz: Tz::UTC, source: None }
+    }
+
+    fn zar_converter() -> Converter {
+        Converter::new(code("ZAR"), FxTable::new(&[
+            fx_rate("USD", "ZAR", day(9, 1), "16.50"),
+            fx_rate("USD", "ZAR", day(10, 16), "18.00"),
+            fx_rate("ZAR", "GBP", day(10, 1), "0.04"),
+        ]))
+    }
+
+    #[test]
+    fn uses_the_rate_from_the_day_of_the_price() {
+        let converter = zar_converter();
+        assert_eq!(converter.convert_price(&price("100", "USD"), day(9, 15)).unwrap(), converted_price("1650.00", "ZAR"));
+        assert_eq!(converter.convert_price(&price("100", "USD"), day(10, 16)).unwrap(), converted_price("1800.00", "ZAR"));
+        let missing = converter.convert_price(&price("100", "USD"), day(8, 31)).unwrap_err();
+        assert_eq!(missing.to_string(), "No USD to ZAR rate on or before 2020-08-31, see `stockchecker fx`");
+    }
+
+    #[test]
+    fn pence_and_inverse_rates() {
+        let converter = zar_converter();
+        //1234.5 pence is 12.345 pounds, at 25 rand a pound
+        let pounds = converter.convert_price(&price("1234.5", "GBX"), day(10, 2)).unwrap();
+        assert_eq!(pounds.amount, Decimal::from_str("308.625").unwrap());
+        assert_eq!(pounds.to_string(), "308.63");
+        assert_eq!(converter.convert_price(&price("2834.50", "ZAR"), day(10, 2)).unwrap(), price("2834.50", "ZAR"));
+        assert_eq!(converter.convert_price(&Price::new(Decimal::from(5)), day(10, 2)).unwrap(), Price::new(Decimal::from(5)));
+    }
+
+    #[test]
+    fn small_prices_keep_their_movement() {
+        let converter = zar_converter();
+        //half a penny is 12.5 cents, then 15 cents, which is 20% up and not 0.13 to 0.15
+        let before = Share { price: converter.convert_price(&price("0.5", "GBX"), day(10, 2)).unwrap(), ..share("0", "GBX", day(10, 2)) };
+        let after = Share { price: converter.convert_price(&price("0.6", "GBX"), day(10, 2)).unwrap(), ..share("0", "GBX", day(10, 2)) };
+        let movement = Movement::between(&before, &after).unwrap();
+        assert_eq!(movement.percent, Decimal::from(20));
+        assert_eq!(after.price.shown(movement.absolute).to_string(), "0.03");
+        assert_eq!((before.price.to_string(), after.price.to_string()), ("0.13".to_string(), "0.15".to_string()));
+    }
+
+    #[test]
+    fn history_is_converted_at_its_own_rate() {
+        let mut share_history = HashMap::new();
+        share_history.insert(ShareMoment::LastMonth, share("100", "USD", day(9, 16)));
+        share_history.insert(ShareMoment::LastYear, share("90", "USD", day(1, 2)));
+        //saved before we kept the currency
+        share_history.insert(ShareMoment::LastWeek, Share { price: Price::new(Decimal::from(95)), ..share("0", "USD", day(10, 9)) });
+        let company_prices = vec![FetchOutcome::Found(ShareTimeline { share: share("100", "USD", day(10, 16)), share_history })];
+
+        let (converted, missing_rates) = zar_converter().convert_outcomes(&company_prices);
+        let share_timeline = converted[0].found().unwrap();
+        let last_month = &share_timeline.share_history[&ShareMoment::LastMonth];
+        //the same dollars, but the rand weakened
+        let movement = Movement::between(last_month, &share_timeline.share).unwrap();
+        assert_eq!(movement.absolute, Decimal::from_str("150.00").unwrap());
+        assert_eq!(share_timeline.share_history[&ShareMoment::LastWeek].price, converted_price("1567.50", "ZAR"));
+        //no rate that far back, so it stays in dollars and there's no movement
+        assert!(Movement::between(&share_timeline.share_history[&ShareMoment::LastYear], &share_timeline.share).is_none());
+        assert_eq!(missing_rates, vec![MissingRate { from: code("USD"), to: code("ZAR"), on: day(1, 2) }]);
+        assert_eq!(currencies_to_convert(&todays_prices(&company_prices), &code("ZAR")), vec![code("USD")]);
+    }
+
+    #[test]
+    fn portfolio_lots_are_converted_on_their_buy_day() {
+        let holding = |company_code: &str, buy_price: &str, buy_date: NaiveDate| Holding {
+            id: None,
+            company_code: company_code.to_string(),
+            quantity: Decimal::from(2),
+            buy_price: Decimal::from_str(buy_price).unwrap(),
+            buy_date,
+            fees: Decimal::from(1),
+        };
+        let holdings = [holding("AAPL", "100", day(9, 15)), holding("OLD", "100", day(10, 1)), holding("OLD", "90", day(1, 2))];
+        let current_prices = vec![
+            ("AAPL".to_string(), share("110", "USD", day(10, 16))),
+            ("OLD".to_string(), Share { company_code: "OLD".to_string(), ..share("95", "USD", day(10, 16)) }),
+        ];
+
+        let (holdings, current_prices, missing_rates) = zar_converter().convert_portfolio(&holdings, current_prices.into_iter().collect());
+        //bought at 16.50, priced at 18.00
+        assert_eq!((holdings[0].buy_price, holdings[0].fees), (Decimal::from_str("1650.00").unwrap(), Decimal::from_str("16.50").unwrap()));
+        assert_eq!(current_prices["AAPL"].price, converted_price("1980.00", "ZAR"));
+        //one lot is from before any rate, so the whole code stays in dollars
+        assert_eq!(holdings[1].buy_price, Decimal::from(100));
+        assert_eq!(current_prices["OLD"].price, price("95", "USD"));
+        assert_eq!(missing_rates, vec![MissingRate { from: code("USD"), to: code("ZAR"), on: day(1, 2) }]);
+    }
+
+    #[test]
+    fn a_series_is_converted_whole_or_not_at_all() {
+        let converter = zar_converter();
+        let mut missing_rates = Vec::new();
+        //saved before we kept the currency, so it's taken to be in dollars like the rest
+        let shares = [Share { price: Price::new(Decimal::from(100)), ..share("0", "USD", day(9, 15)) }, share("100", "USD", day(10, 16))];
+        let prices: Vec<_> = converter.convert_series(&shares, &mut missing_rates).into_iter().map(|share| share.price.to_string()).collect();
+        assert_eq!(prices, vec!["1650.00", "1800.00"]);
+        assert!(missing_rates.is_empty());
+
+        let shares = [share("90", "USD", day(1, 2)), share("100", "USD", day(10, 16))];
+        let prices: Vec<_> = converter.convert_series(&shares, &mut missing_rates).into_iter().map(|share| share.price).collect();
+        assert_eq!(prices, vec![price("90", "USD"), price("100", "USD")]);
+        assert_eq!(missing_rates.len(), 1);
+    }
+
+    #[test]
+    fn reads_rates_from_csv() {
+        let csv = "date,base,quote,rate\n2020-10-16, usd, ZAR, 16.4523\n";
+        assert_eq!(read_rates_csv(csv.as_bytes()).unwrap(), vec![fx_rate("USD", "ZAR", day(10, 16), "16.4523")]);
+        let error = read_rates_csv("date,base,quote,rate\n2020-10-16,USD,ZAR,0\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("Line 2"));
+    }
+}
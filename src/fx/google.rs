@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use regex::Regex;
+use rust_decimal::Decimal;
+use select::document::Document;
+use select::predicate::Name;
+use std::str::FromStr;
+
+use crate::error::{StockError, StockResult};
+use crate::fx::{FxRate, RateSource};
+use crate::share_price_model::{plain_amount, CurrencyCode};
+
+pub const SOURCE_NAME: &str = "google";
+
+/// Scrapes the "1 United States Dollar equals 18.45 South African Rand" box off a currency search
+pub struct GoogleRates {
+    equals: Regex,
+}
+
+impl GoogleRates {
+    pub fn new() -> GoogleRates {
+        GoogleRates {
+            equals: Regex::new(r"\bequals\s*(\d[\d\s.,]*\d|\d)\s+\p{L}").unwrap(),
+        }
+    }
+
+    /// The rate off the search result page, an error if there's no converter box on it
+    pub fn extract_rate(&self, body: &str, base: &CurrencyCode, quote: &CurrencyCode) -> StockResult<Decimal> {
+        let page_text: String = Document::from(body).find(Name("body")).map(|node| node.text()).collect();
+        let raw_rate = self.equals.captures(&page_text)
+            .map(|captures| captures[1].to_string())
+            .ok_or_else(|| StockError::Parse(format!("No {} to {} rate on the page", base, quote)))?;
+        plain_amount(&raw_rate)
+            .and_then(|plain| Decimal::from_str(&plain).ok())
+            .ok_or_else(|| StockError::Parse(format!("Couldn't read '{}' as a rate", raw_rate)))
+    }
+}
+
+impl Default for GoogleRates {
+    fn default() -> Self {
+        GoogleRates::new()
+    }
+}
+
+#[async_trait]
+impl RateSource for GoogleRates {
+    fn name(&self) -> &'static str {
+        SOURCE_NAME
+    }
+
+    async fn fetch_rate(&self, base: &CurrencyCode, quote: &CurrencyCode) -> StockResult<FxRate> {
+        let res = reqwest::get(&format!("https://www.google.com/search?hl=en&q=1+{}+in+{}", base, quote)).await
+            .map_err(|e| StockError::Network(format!("Couldn't fetch the {} to {} rate from google: {}", base, quote, e)))?;
+        let body = res.text().await?;
+        Ok(FxRate {
+            base: base.clone(),
+            quote: quote.clone(),
+            rate_date: Utc::now().date_naive(),
+            rate: self.extract_rate(&body, base, quote)?,
+            source: SOURCE_NAME.to_string(),
+        })
+    }
+}
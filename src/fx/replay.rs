@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::error::{StockError, StockResult};
+use crate::fx::{FxRate, RateSource};
+use crate::fx::google::GoogleRates;
+use crate::share_price_model::CurrencyCode;
+
+pub const SOURCE_NAME: &str = "replay";
+
+/// Plays back saved currency searches from the fixtures directory, one `fx-<BASE>-<QUOTE>.html` per pair
+pub struct ReplayRates {
+    fixtures_dir: PathBuf,
+    scraper: GoogleRates,
+}
+
+impl ReplayRates {
+    pub fn new(fixtures_dir: &str) -> ReplayRates {
+        ReplayRates {
+            fixtures_dir: PathBuf::from(fixtures_dir),
+            scraper: GoogleRates::new(),
+        }
+    }
+
+    fn fixture_path(&self, base: &CurrencyCode, quote: &CurrencyCode) -> PathBuf {
+        self.fixtures_dir.join(format!("fx-{}-{}.html", base, quote))
+    }
+}
+
+#[async_trait]
+impl RateSource for ReplayRates {
+    fn name(&self) -> &'static str {
+        SOURCE_NAME
+    }
+
+    async fn fetch_rate(&self, base: &CurrencyCode, quote: &CurrencyCode) -> StockResult<FxRate> {
+        let fixture_path = self.fixture_path(base, quote);
+        let body = tokio::fs::read_to_string(&fixture_path).await
+            .map_err(|e| StockError::Config(format!("No fixture at {}: {}", fixture_path.display(), e)))?;
+        Ok(FxRate {
+            base: base.clone(),
+            quote: quote.clone(),
+            rate_date: Utc::now().date_naive(),
+            rate: self.scraper.extract_rate(&body, base, quote)?,
+            source: SOURCE_NAME.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture_source() -> ReplayRates {
+        ReplayRates::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures"))
+    }
+
+    async fn replay_rate(base: &str, quote: &str) -> StockResult<String> {
+        fixture_source().fetch_rate(&base.parse().unwrap(), &quote.parse().unwrap()).await
+            .map(|fx_rate| fx_rate.rate.to_string())
+    }
+
+    #[tokio::test]
+    async fn comma_decimal_rate() {
+        assert_eq!(replay_rate("USD", "ZAR").await.unwrap(), "16.4523");
+    }
+
+    #[tokio::test]
+    async fn dot_decimal_rate() {
+        assert_eq!(replay_rate("GBP", "ZAR").await.unwrap(), "21.2871");
+    }
+
+    #[tokio::test]
+    async fn page_without_converter_is_an_error() {
+        let body = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/NPN.html")).unwrap();
+        assert!(GoogleRates::new().extract_rate(&body, &"USD".parse().unwrap(), &"ZAR".parse().unwrap()).is_err());
+        assert!(replay_rate("JPY", "ZAR").await.is_err());
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDate};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::Zero;
+use serde::Deserialize;
+
+use crate::error::{StockError, StockResult};
+use crate::portfolio::Holding;
+
+pub const LOT_METHOD_NAMES: [&str; 3] = ["fifo", "lifo", "average"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Buy,
+    Sell,
+    /// Cash paid out, `price` holds the amount
+    Dividend,
+    /// `quantity` holds how many new shares each old one became
+    Split,
+}
+
+impl FromStr for TransactionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "buy" => Ok(TransactionKind::Buy),
+            "sell" => Ok(TransactionKind::Sell),
+            "dividend" => Ok(TransactionKind::Dividend),
+            "split" => Ok(TransactionKind::Split),
+            _ => Err(format!("'{}' isn't a transaction type, try buy, sell, dividend or split", s)),
+        }
+    }
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransactionKind::Buy => write!(f, "buy"),
+            TransactionKind::Sell => write!(f, "sell"),
+            TransactionKind::Dividend => write!(f, "dividend"),
+            TransactionKind::Split => write!(f, "split"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    /// Nothing until the store has given it one
+    pub id: Option<i64>,
+    pub company_code: String,
+    pub kind: TransactionKind,
+    pub trade_date: NaiveDate,
+    pub quantity: Decimal,
+    pub price: Decimal,
+    pub fees: Decimal,
+}
+
+/// Which shares a sale is taken to have come out of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LotMethod {
+    Fifo,
+    Lifo,
+    /// Every share costs the pool's average
+    Average,
+}
+
+impl FromStr for LotMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "fifo" => Ok(LotMethod::Fifo),
+            "lifo" => Ok(LotMethod::Lifo),
+            "average" => Ok(LotMethod::Average),
+            _ => Err(format!("'{}' isn't a lot method, try fifo, lifo or average", s)),
+        }
+    }
+}
+
+/// When the tax year rolls over, 1 January unless told otherwise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaxYearStart {
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Default for TaxYearStart {
+    fn default() -> Self {
+        TaxYearStart { month: 1, day: 1 }
+    }
+}
+
+impl FromStr for TaxYearStart {
+    type Err = String;
+
+    /// Month and day, like "03-01"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        NaiveDate::parse_from_str(&format!("2000-{}", s.trim()), "%Y-%m-%d")
+            .map(|date| TaxYearStart { month: date.month(), day: date.day() })
+            .map_err(|_| format!("'{}' needs to be a month and day like 03-01", s))
+    }
+}
+
+impl TaxYearStart {
+    /// Named for the year it ends in, like "2021" or "2020/2021" when it doesn't start in January
+    pub fn tax_year_of(&self, date: NaiveDate) -> String {
+        if self.month == 1 && self.day == 1 {
+            return date.year().to_string();
+        }
+        let end_year = if (date.month(), date.day()) >= (self.month, self.day) { date.year() + 1 } else { date.year() };
+        format!("{}/{}", end_year - 1, end_year)
+    }
+}
+
+/// A parcel of shares still held, at what each one cost
+#[derive(Debug, Clone)]
+struct Lot {
+    quantity: Decimal,
+    unit_cost: Decimal,
+    /// The earliest buy once pooled
+    buy_date: NaiveDate,
+}
+
+/// Running totals for one ticker or one tax year
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RealizedTotals {
+    pub quantity_sold: Decimal,
+    pub proceeds: Decimal,
+    pub cost: Decimal,
+    pub dividends: Decimal,
+}
+
+impl RealizedTotals {
+    pub fn gain(&self) -> Decimal {
+        self.proceeds - self.cost
+    }
+
+    fn add_sale(&mut self, quantity: Decimal, proceeds: Decimal, cost: Decimal) {
+        self.quantity_sold += quantity;
+        self.proceeds += proceeds;
+        self.cost += cost;
+    }
+}
+
+/// Realized gains and dividends, by ticker and by tax year
+#[derive(Debug, Default)]
+pub struct LedgerReport {
+    pub by_ticker: BTreeMap<String, RealizedTotals>,
+    pub by_tax_year: BTreeMap<String, RealizedTotals>,
+    /// What's still held in each ticker once everything has played out
+    pub held: BTreeMap<String, Decimal>,
+    /// The lots behind `held`, fees and splits folded into the buy price, ready for the portfolio to value
+    pub open_lots: Vec<Holding>,
+}
+
+impl LedgerReport {
+    pub fn total(&self) -> RealizedTotals {
+        let mut total = RealizedTotals::default();
+        for totals in self.by_ticker.values() {
+            total.add_sale(totals.quantity_sold, totals.proceeds, totals.cost);
+            total.dividends += totals.dividends;
+        }
+        total
+    }
+}
+
+/// Play the transactions through in date order, matching each sale to lots with `method`.
+/// Ones on the same day keep the order they're given in, which is the order they went into the store.
+pub fn realize(transactions: &[Transaction], method: LotMethod, tax_year_start: TaxYearStart) -> StockResult<LedgerReport> {
+    let mut ordered: Vec<&Transaction> = transactions.iter().collect();
+    ordered.sort_by_key(|transaction| transaction.trade_date);
+
+    let mut lots: BTreeMap<&str, VecDeque<Lot>> = BTreeMap::new();
+    let mut report = LedgerReport::default();
+    for transaction in ordered {
+        let ticker_lots = lots.entry(&transaction.company_code).or_default();
+        let tax_year = tax_year_start.tax_year_of(transaction.trade_date);
+        match transaction.kind {
+            TransactionKind::Buy => {
+                if transaction.quantity <= Decimal::zero() {
+                    return Err(bad_transaction(transaction, "a buy needs a quantity above zero"));
+                }
+                let unit_cost = (transaction.quantity * transaction.price + transaction.fees) / transaction.quantity;
+                ticker_lots.push_back(Lot { quantity: transaction.quantity, unit_cost, buy_date: transaction.trade_date });
+                if method == LotMethod::Average {
+                    pool(ticker_lots);
+                }
+            }
+            TransactionKind::Sell => {
+                if transaction.quantity <= Decimal::zero() {
+                    return Err(bad_transaction(transaction, "a sell needs a quantity above zero"));
+                }
+                let cost = take_from_lots(ticker_lots, transaction.quantity, method)
+                    .ok_or_else(|| bad_transaction(transaction, "selling more than was held"))?;
+                let proceeds = transaction.quantity * transaction.price - transaction.fees;
+                report.by_ticker.entry(transaction.company_code.clone()).or_default()
+                    .add_sale(transaction.quantity, proceeds, cost);
+                report.by_tax_year.entry(tax_year).or_default()
+                    .add_sale(transaction.quantity, proceeds, cost);
+            }
+            TransactionKind::Dividend => {
+                let dividend = transaction.price - transaction.fees;
+                report.by_ticker.entry(transaction.company_code.clone()).or_default().dividends += dividend;
+                report.by_tax_year.entry(tax_year).or_default().dividends += dividend;
+            }
+            TransactionKind::Split => {
+                if transaction.quantity <= Decimal::zero() {
+                    return Err(bad_transaction(transaction, "a split needs a ratio above zero"));
+                }
+                //same money in, just spread over more (or fewer) shares
+                for lot in ticker_lots.iter_mut() {
+                    lot.quantity *= transaction.quantity;
+                    lot.unit_cost /= transaction.quantity;
+                }
+            }
+        }
+    }
+
+    for (company_code, ticker_lots) in lots {
+        let held: Decimal = ticker_lots.iter().map(|lot| lot.quantity).sum();
+        report.held.insert(company_code.to_string(), held);
+        report.open_lots.extend(ticker_lots.into_iter().map(|lot| Holding {
+            id: None,
+            company_code: company_code.to_string(),
+            quantity: lot.quantity,
+            buy_price: lot.unit_cost,
+            buy_date: lot.buy_date,
+            fees: Decimal::zero(),
+        }));
+    }
+    Ok(report)
+}
+
+/// Roll every lot into one at the average cost
+fn pool(lots: &mut VecDeque<Lot>) {
+    let quantity: Decimal = lots.iter().map(|lot| lot.quantity).sum();
+    let cost: Decimal = lots.iter().map(|lot| lot.quantity * lot.unit_cost).sum();
+    let buy_date = lots.iter().map(|lot| lot.buy_date).min();
+    lots.clear();
+    if let Some(buy_date) = buy_date.filter(|_| !quantity.is_zero()) {
+        lots.push_back(Lot { quantity, unit_cost: cost / quantity, buy_date });
+    }
+}
+
+/// The cost of the shares sold, nothing if there weren't enough to sell
+fn take_from_lots(lots: &mut VecDeque<Lot>, quantity: Decimal, method: LotMethod) -> Option<Decimal> {
+    let held: Decimal = lots.iter().map(|lot| lot.quantity).sum();
+    if quantity > held {
+        return None;
+    }
+    let mut remaining = quantity;
+    let mut cost = Decimal::zero();
+    while remaining > Decimal::zero() {
+        let lot = match method {
+            LotMethod::Fifo | LotMethod::Average => lots.front_mut(),
+            LotMethod::Lifo => lots.back_mut(),
+        }?;
+        let taken = remaining.min(lot.quantity);
+        cost += taken * lot.unit_cost;
+        lot.quantity -= taken;
+        remaining -= taken;
+        if lot.quantity.is_zero() {
+            match method {
+                LotMethod::Fifo | LotMethod::Average => lots.pop_front(),
+                LotMethod::Lifo => lots.pop_back(),
+            };
+        }
+    }
+    Some(cost)
+}
+
+fn bad_transaction(transaction: &Transaction, problem: &str) -> StockError {
+    StockError::Parse(format!("{} {} of {} on {}: {}",
+                              transaction.kind, transaction.quantity, transaction.company_code, transaction.trade_date, problem))
+}
+
+/// One line of a transactions CSV: date,code,type,quantity,price,fees
+#[derive(Debug, Deserialize)]
+struct TransactionRecord {
+    date: String,
+    code: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    quantity: String,
+    #[serde(default)]
+    price: String,
+    #[serde(default)]
+    fees: String,
+}
+
+/// Reads a CSV with a `date,code,type,quantity,price,fees` header, dates as YYYY-MM-DD.
+/// Blank numbers count as zero, so a dividend only needs its price (the amount paid).
+pub fn read_transactions_csv<R: Read>(reader: R) -> StockResult<Vec<Transaction>> {
+    let mut csv_reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(reader);
+    let mut transactions = Vec::new();
+    for (idx, record) in csv_reader.deserialize::<TransactionRecord>().enumerate() {
+        //the header is line 1
+        let line = idx + 2;
+        let record = record.map_err(|e| StockError::Parse(format!("Line {}: {}", line, e)))?;
+        let bad_line = |problem: String| StockError::Parse(format!("Line {}: {}", line, problem));
+        transactions.push(Transaction {
+            id: None,
+            kind: record.kind.parse().map_err(bad_line)?,
+            trade_date: NaiveDate::parse_from_str(&record.date, "%Y-%m-%d")
+                .map_err(|_| bad_line(format!("'{}' needs to be a date like 2020-10-16", record.date)))?,
+            quantity: parse_amount(&record.quantity).map_err(bad_line)?,
+            price: parse_amount(&record.price).map_err(bad_line)?,
+            fees: parse_amount(&record.fees).map_err(bad_line)?,
+            company_code: record.code,
+        });
+    }
+    Ok(transactions)
+}
+
+fn parse_amount(amount: &str) -> Result<Decimal, String> {
+    if amount.is_empty() {
+        return Ok(Decimal::zero());
+    }
+    Decimal::from_str(amount).map_err(|_| format!("'{}' isn't a number", amount))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(kind: TransactionKind, date: (i32, u32, u32), quantity: i64, price: i64, fees: i64) -> Transaction {
+        Transaction {
+            id: None,
+            company_code: "NPN".to_string(),
+            kind,
+            trade_date: NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap(),
+            quantity: Decimal::from(quantity),
+            price: Decimal::from(price),
+            fees: Decimal::from(fees),
+        }
+    }
+
+    fn two_buys_and_a_sale() -> Vec<Transaction> {
+        vec![
+            transaction(TransactionKind::Buy, (2020, 1, 10), 10, 100, 0),
+            transaction(TransactionKind::Buy, (2020, 2, 10), 10, 200, 0),
+            transaction(TransactionKind::Sell, (2020, 3, 10), 10, 250, 10),
+        ]
+    }
+
+    #[test]
+    fn lot_methods_pick_different_costs() {
+        let gain_with = |method| realize(&two_buys_and_a_sale(), method, TaxYearStart::default()).unwrap().total().gain();
+        assert_eq!(gain_with(LotMethod::Fifo), Decimal::from(1490));
+        assert_eq!(gain_with(LotMethod::Lifo), Decimal::from(490));
+        assert_eq!(gain_with(LotMethod::Average), Decimal::from(990));
+    }
+
+    #[test]
+    fn fees_and_splits_carry_into_the_cost() {
+        let transactions = vec![
+            transaction(TransactionKind::Buy, (2020, 1, 10), 10, 100, 20),
+            transaction(TransactionKind::Split, (2020, 6, 1), 2, 0, 0),
+            transaction(TransactionKind::Sell, (2020, 7, 1), 5, 60, 0),
+        ];
+        let report = realize(&transactions, LotMethod::Fifo, TaxYearStart::default()).unwrap();
+        let npn = &report.by_ticker["NPN"];
+        assert_eq!(npn.cost, Decimal::from(255));
+        assert_eq!(npn.gain(), Decimal::from(45));
+        assert_eq!(report.held["NPN"], Decimal::from(15));
+        let open_lot = &report.open_lots[0];
+        assert_eq!((open_lot.quantity, open_lot.buy_price, open_lot.buy_date), (Decimal::from(15), Decimal::from(51), NaiveDate::from_ymd_opt(2020, 1, 10).unwrap()));
+        assert_eq!(open_lot.cost_basis(), Decimal::from(765));
+    }
+
+    #[test]
+    fn same_day_keeps_the_order_they_went_in() {
+        //a saved buy, then a sale being imported the same day that hasn't got an id yet
+        let transactions = vec![
+            Transaction { id: Some(7), ..transaction(TransactionKind::Buy, (2020, 1, 10), 10, 100, 0) },
+            transaction(TransactionKind::Sell, (2020, 1, 10), 4, 110, 0),
+        ];
+        let report = realize(&transactions, LotMethod::Fifo, TaxYearStart::default()).unwrap();
+        assert_eq!(report.held["NPN"], Decimal::from(6));
+        assert_eq!(report.open_lots.len(), 1);
+    }
+
+    #[test]
+    fn gains_and_dividends_land_in_their_tax_year() {
+        let mut transactions = two_buys_and_a_sale();
+        transactions.push(transaction(TransactionKind::Dividend, (2020, 2, 28), 0, 50, 5));
+        let report = realize(&transactions, LotMethod::Fifo, "03-01".parse().unwrap()).unwrap();
+        assert_eq!(report.by_tax_year["2019/2020"].dividends, Decimal::from(45));
+        assert_eq!(report.by_tax_year["2020/2021"].gain(), Decimal::from(1490));
+        assert_eq!(TaxYearStart::default().tax_year_of(NaiveDate::from_ymd_opt(2020, 12, 31).unwrap()), "2020");
+    }
+
+    #[test]
+    fn selling_more_than_held_is_refused() {
+        let transactions = vec![
+            transaction(TransactionKind::Buy, (2020, 1, 10), 5, 100, 0),
+            transaction(TransactionKind::Sell, (2020, 3, 10), 6, 100, 0),
+        ];
+        assert!(realize(&transactions, LotMethod::Fifo, TaxYearStart::default()).is_err());
+    }
+
+    #[test]
+    fn selling_nothing_or_less_is_refused() {
+        for quantity in &[0, -5] {
+            let transactions = vec![
+                transaction(TransactionKind::Buy, (2020, 1, 10), 5, 100, 0),
+                transaction(TransactionKind::Sell, (2020, 3, 10), *quantity, 100, 0),
+            ];
+            let e = realize(&transactions, LotMethod::Fifo, TaxYearStart::default()).unwrap_err();
+            assert!(e.to_string().contains("a sell needs a quantity above zero"));
+        }
+    }
+
+    #[test]
+    fn reads_transactions_from_csv() {
+        let csv = "date,code,type,quantity,price,fees\n\
+                   2020-01-10, NPN, buy, 10, 2500.50, 25\n\
+                   2020-03-01,NPN,dividend,,120.00,\n";
+        let transactions = read_transactions_csv(csv.as_bytes()).unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].kind, TransactionKind::Buy);
+        assert_eq!(transactions[0].price, Decimal::from_str("2500.50").unwrap());
+        assert_eq!(transactions[1].quantity, Decimal::zero());
+
+        let error = read_transactions_csv("date,code,type,quantity,price,fees\n2020-01-10,NPN,gift,1,1,0\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("Line 2"));
+    }
+}
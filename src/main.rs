@@ -1,177 +1,1067 @@
 extern crate prettytable;
 
-use std::borrow::Borrow;
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::error::Error;
+use std::sync::Arc;
 use std::string::ToString;
+use std::time::Duration;
 
-use chrono::prelude::*;
-use clap::{App, Arg, ArgMatches};
-use mysql::{OptsBuilder, params, Pool, PooledConn};
-use mysql::prelude::*;
+use clap::{App, AppSettings, Arg, ArgMatches};
+use futures::FutureExt;
 use prettytable::{Attr, Cell, color, Row, Table};
-use regex::Regex;
+use chrono::{Local, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
 use rust_decimal::Decimal;
 use rust_decimal::prelude::Zero;
-use select::document::Document;
-use select::predicate::Name;
+use std::str::FromStr;
 
+use crate::alerts::{AlertRule, TriggeredAlert};
+use crate::backfill::{ImportLayout, ImportOptions};
 use crate::chart_grapher::ChartGrapher;
-use crate::share_price_model::{Share, ShareMoment, ShareTimeline};
-use std::collections::hash_map::RandomState;
+use crate::error::{StockError, StockResult};
+use crate::export::{ExportFilter, ExportFormat};
+use crate::fx::{Converter, FxRate, FxTable, MissingRate};
+use crate::ledger::{LedgerReport, LotMethod, RealizedTotals, TaxYearStart, Transaction};
+use crate::notifier::{Delivery, DeliveryOutcome, NotifyTarget};
+use crate::portfolio::{Holding, Portfolio};
+use crate::price_source::{FetchOptions, PriceSource};
+use crate::quote_format::{OutputFormat, QuoteRow};
+use crate::share_price_model::{CurrencyCode, FAILURE_KINDS, FetchOutcome, Movement, Price, Share, ShareMoment, ShareTimeline, TickerInfo};
+use crate::storage::ShareStore;
+use crate::storage::migrations;
+use crate::watch_screen::{WatchCommand, WatchScreen};
 
 mod share_price_model;
 mod config_options;
 mod db_model;
 mod util;
 mod chart_grapher;
-use log::{debug, error};
-use std::str::FromStr;
+mod price_source;
+mod error;
+mod storage;
+mod watch_screen;
+mod portfolio;
+mod ledger;
+mod alerts;
+mod notifier;
+mod quote_format;
+mod fx;
+mod backfill;
+mod export;
+use log::{debug, warn};
 
 fn init() -> ArgMatches {
     App::new("Share price checker")
         .version("1.0")
         .author("Foom <lordfoom@gmail.com>")
         .about("Scrape price changes from Google")
-        .arg(Arg::new("code")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(Arg::with_name("code")
                  .value_name("COMPANY_CODE")
                  .index(1)
-                 .required(true)
+                 .required_unless("list")
                  .multiple(true)
              // .validator(is_valid_code)
              ,
         )
-        .arg("-c --chart 'Draw chart'")
+        .arg(list_arg())
+        .arg(Arg::with_name("chart")
+                 .about("Draw chart")
+                 .short('c')
+                 .long("chart"),
+        )
+        .arg(Arg::with_name("compare")
+                 .about("Draw all the codes on one chart")
+                 .long("compare"),
+        )
+        .arg(Arg::with_name("source")
+                 .about("Where to get the current prices from")
+                 .short('s')
+                 .long("source")
+                 .value_name("SOURCE")
+                 .possible_values(&price_source::SOURCE_NAMES)
+                 .default_value(price_source::google::SOURCE_NAME),
+        )
+        .arg(Arg::with_name("moments")
+                 .about("Which moments in history to compare against: yesterday, week, month, quarter, ytd, year, 5y or a number of days like 45d")
+                 .short('m')
+                 .long("moments")
+                 .value_name("MOMENTS")
+                 .use_delimiter(true)
+                 .default_value(share_price_model::DEFAULT_MOMENTS)
+                 .validator(|moment| moment.parse::<ShareMoment>().map(|_| ())),
+        )
+        .arg(Arg::with_name("format")
+                 .about("How to print the quote table, everything but table is meant for scripts")
+                 .short('f')
+                 .long("format")
+                 .value_name("FORMAT")
+                 .possible_values(&quote_format::FORMAT_NAMES)
+                 .default_value("table"),
+        )
+        .arg(Arg::with_name("report_currency")
+                 .about("Show every price and movement in this currency, at the rate of the day each price was taken")
+                 .long("report-currency")
+                 .value_name("CURRENCY")
+                 .validator(is_currency),
+        )
+        .arg(Arg::with_name("fixtures")
+                 .about("Directory of saved <CODE>.html search pages for the replay source")
+                 .long("fixtures")
+                 .value_name("DIR")
+                 .default_value("fixtures"),
+        )
+        .arg(Arg::with_name("concurrency")
+                 .about("How many prices to fetch at the same time")
+                 .short('j')
+                 .long("concurrency")
+                 .value_name("COUNT")
+                 .default_value("8")
+                 .validator(is_positive_number),
+        )
+        .arg(Arg::with_name("timeout")
+                 .about("Seconds to wait for a single price before giving up on it")
+                 .short('t')
+                 .long("timeout")
+                 .value_name("SECONDS")
+                 .default_value("10")
+                 .validator(is_positive_number),
+        )
+        .subcommand(App::new("db")
+            .about("Look after the price database")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("migrate").about("Bring the schema up to date"))
+            .subcommand(App::new("status").about("Show which migrations have been applied")),
+        )
+        .subcommand(App::new("import")
+            .about("Backfill a code's history from a CSV of daily prices. Only the close is kept, open, high, low and volume are ignored")
+            .arg(Arg::with_name("code").value_name("COMPANY_CODE").index(1).required(true))
+            .arg(Arg::with_name("file").value_name("FILE").index(2).required(true))
+            .arg(Arg::with_name("date_column")
+                     .about("Header of the date column, date/timestamp/time if left out")
+                     .long("date-column")
+                     .value_name("NAME"))
+            .arg(Arg::with_name("close_column")
+                     .about("Header of the price column, close/close/last/price/last/adj close if left out")
+                     .long("close-column")
+                     .value_name("NAME"))
+            .arg(Arg::with_name("date_format")
+                     .about("How the dates are written, like %m/%d/%Y")
+                     .long("date-format")
+                     .value_name("FORMAT")
+                     .default_value("%Y-%m-%d"))
+            .arg(Arg::with_name("delimiter")
+                     .about("What separates the columns, a single character or 'tab'")
+                     .long("delimiter")
+                     .value_name("CHAR")
+                     .default_value(",")
+                     .validator(|delimiter| read_delimiter(delimiter).map(|_| ())))
+            .arg(Arg::with_name("tz")
+                     .about("Zone of the exchange, the one the code was last priced in if left out")
+                     .long("tz")
+                     .value_name("ZONE")
+                     .validator(|tz| tz.parse::<Tz>().map(|_| ())))
+            .arg(Arg::with_name("close_time")
+                     .about("Time of day on the exchange to stamp each close with")
+                     .long("close-time")
+                     .value_name("HH:MM")
+                     .default_value("17:00")
+                     .validator(|close_time| NaiveTime::parse_from_str(close_time, "%H:%M").map(|_| ()).map_err(|_| format!("'{}' needs to be a time like 17:00", close_time))))
+            .arg(Arg::with_name("currency")
+                     .about("Currency of the prices, the one the code was last priced in if left out")
+                     .long("currency")
+                     .value_name("CURRENCY")
+                     .validator(is_currency))
+            .arg(Arg::with_name("dry-run")
+                     .about("Show what would be imported without saving it")
+                     .long("dry-run")),
+        )
+        .subcommand(App::new("export")
+            .about("Write saved prices out as CSV, JSON Lines or Parquet, every code with a price if none are given")
+            .arg(Arg::with_name("code").value_name("COMPANY_CODE").index(1).multiple(true))
+            .arg(list_arg())
+            .arg(Arg::with_name("format")
+                     .about("How to write the prices")
+                     .short('f')
+                     .long("format")
+                     .value_name("FORMAT")
+                     .possible_values(&export::FORMAT_NAMES)
+                     .default_value("csv"))
+            .arg(Arg::with_name("from")
+                     .about("Leave out prices before this day, in UTC")
+                     .long("from")
+                     .value_name("DATE")
+                     .validator(is_date))
+            .arg(Arg::with_name("to")
+                     .about("Leave out prices after this day, in UTC")
+                     .long("to")
+                     .value_name("DATE")
+                     .validator(is_date))
+            .arg(Arg::with_name("source")
+                     .about("Only prices found by this source, like google or import")
+                     .long("source")
+                     .value_name("NAME"))
+            .arg(Arg::with_name("output")
+                     .about("File to write to instead of the screen")
+                     .short('o')
+                     .long("output")
+                     .value_name("FILE")),
+        )
+        .subcommand(App::new("watch")
+            .about("Keep the quote table up on screen, refreshing it every so often")
+            .arg(Arg::with_name("code")
+                     .value_name("COMPANY_CODE")
+                     .index(1)
+                     .required_unless("list")
+                     .multiple(true),
+            )
+            .arg(list_arg())
+            .arg(Arg::with_name("interval")
+                     .about("Seconds between refreshes")
+                     .short('i')
+                     .long("interval")
+                     .value_name("SECONDS")
+                     .default_value("60")
+                     .validator(is_positive_number),
+            )
+            .arg(Arg::with_name("save")
+                     .about("Save every refresh to the database")
+                     .long("save"),
+            ),
+        )
+        .subcommand(App::new("watchlist")
+            .about("Keep named lists of codes, and notes on each code")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("add")
+                .about("Put codes on a list, starting the list if it's new")
+                .arg(Arg::with_name("name").value_name("LIST").index(1).required(true))
+                .arg(Arg::with_name("code").value_name("COMPANY_CODE").index(2).required(true).multiple(true)),
+            )
+            .subcommand(App::new("remove")
+                .about("Take codes off a list, or drop the whole list if no codes are given")
+                .arg(Arg::with_name("name").value_name("LIST").index(1).required(true))
+                .arg(Arg::with_name("code").value_name("COMPANY_CODE").index(2).multiple(true)),
+            )
+            .subcommand(App::new("list").about("Show every list"))
+            .subcommand(App::new("show")
+                .about("Show the codes on a list with their details")
+                .arg(Arg::with_name("name").value_name("LIST").index(1).required(true)),
+            )
+            .subcommand(App::new("describe")
+                .about("Set the display name, exchange or notes for a code, an empty value clears it")
+                .arg(Arg::with_name("code").value_name("COMPANY_CODE").index(1).required(true))
+                .arg(Arg::with_name("display_name").about("Name to show for the code").long("name").value_name("NAME"))
+                .arg(Arg::with_name("exchange").about("Exchange it trades on").long("exchange").value_name("EXCHANGE"))
+                .arg(Arg::with_name("notes").about("Anything worth remembering about it").long("notes").value_name("NOTES")),
+            ),
+        )
+        .subcommand(App::new("portfolio")
+            .about("What the holdings are worth at today's prices")
+            .subcommand(App::new("add")
+                .about("Record a purchase")
+                .arg(Arg::with_name("code").value_name("COMPANY_CODE").index(1).required(true))
+                .arg(Arg::with_name("quantity").value_name("QUANTITY").index(2).required(true).validator(is_decimal))
+                .arg(Arg::with_name("buy_price")
+                         .about("Price paid per share, in the currency the code is quoted in (pence for GBX)")
+                         .value_name("BUY_PRICE")
+                         .index(3)
+                         .required(true)
+                         .validator(is_decimal))
+                .arg(Arg::with_name("buy_date")
+                         .about("When it was bought, YYYY-MM-DD, today if left out")
+                         .long("date")
+                         .value_name("DATE")
+                         .validator(is_date))
+                .arg(Arg::with_name("fees")
+                         .about("Brokerage and costs on top of the price")
+                         .long("fees")
+                         .value_name("AMOUNT")
+                         .default_value("0")
+                         .validator(is_decimal)),
+            )
+            .subcommand(App::new("remove")
+                .about("Forget a purchase")
+                .arg(Arg::with_name("id").value_name("ID").index(1).required(true).validator(is_positive_number)),
+            )
+            .subcommand(App::new("lots").about("Show every purchase with its id, and the lots the ledger still holds")),
+        )
+        .subcommand(App::new("ledger")
+            .about("Buys, sells, dividends and splits, and the gains they realized")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("import")
+                .about("Add transactions from a CSV with a date,code,type,quantity,price,fees header")
+                .arg(Arg::with_name("file").value_name("FILE").index(1).required(true)),
+            )
+            .subcommand(App::new("list")
+                .about("Show the transactions")
+                .arg(Arg::with_name("code").value_name("COMPANY_CODE").index(1).multiple(true)),
+            )
+            .subcommand(App::new("report")
+                .about("Realized gains and dividends per code and per tax year")
+                .arg(Arg::with_name("method")
+                         .about("How sales are matched to purchases")
+                         .short('m')
+                         .long("method")
+                         .value_name("METHOD")
+                         .possible_values(&ledger::LOT_METHOD_NAMES)
+                         .default_value("fifo"))
+                .arg(Arg::with_name("tax_year_start")
+                         .about("Month and day the tax year starts, like 03-01")
+                         .long("tax-year-start")
+                         .value_name("MM-DD")
+                         .default_value("01-01")
+                         .validator(|start| start.parse::<TaxYearStart>().map(|_| ()))),
+            ),
+        )
+        .subcommand(App::new("alert")
+            .about("Rules that flag a price when it does something interesting")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("add")
+                .about("Watch a code for 'below 2500', 'above 3000', 'moves 5% [yesterday|week|...]' or 'crosses-average 50'")
+                .arg(Arg::with_name("code").value_name("COMPANY_CODE").index(1).required(true))
+                .arg(Arg::with_name("condition")
+                         .value_name("CONDITION")
+                         .index(2)
+                         .required(true)
+                         .multiple(true))
+                .arg(Arg::with_name("cooldown")
+                         .about("How long before the rule can go off again: 30m, 2h, 1d")
+                         .long("cooldown")
+                         .value_name("COOLDOWN")
+                         .default_value(alerts::DEFAULT_COOLDOWN)
+                         .validator(|cooldown| alerts::parse_cooldown(cooldown).map(|_| ()))),
+            )
+            .subcommand(App::new("remove")
+                .about("Drop a rule")
+                .arg(Arg::with_name("id").value_name("ID").index(1).required(true).validator(is_positive_number)),
+            )
+            .subcommand(App::new("list").about("Show every rule"))
+            .subcommand(App::new("history")
+                .about("Show the alerts that have gone off")
+                .arg(Arg::with_name("limit")
+                         .about("How many to show")
+                         .short('n')
+                         .long("limit")
+                         .value_name("COUNT")
+                         .default_value("20")
+                         .validator(is_positive_number)),
+            ),
+        )
+        .subcommand(App::new("fx")
+            .about("The exchange rates --report-currency converts with")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("fetch")
+                .about("Get today's rate for each currency from the price source")
+                .arg(Arg::with_name("base").value_name("CURRENCY").index(1).required(true).multiple(true).validator(is_currency))
+                .arg(Arg::with_name("to")
+                         .about("Currency to get the rates in")
+                         .long("to")
+                         .value_name("CURRENCY")
+                         .required(true)
+                         .validator(is_currency)),
+            )
+            .subcommand(App::new("import")
+                .about("Add rates from a CSV with a date,base,quote,rate header")
+                .arg(Arg::with_name("file").value_name("FILE").index(1).required(true)),
+            )
+            .subcommand(App::new("list")
+                .about("Show the saved rates")
+                .arg(Arg::with_name("currency").value_name("CURRENCY").index(1).multiple(true).validator(is_currency)),
+            ),
+        )
+        .subcommand(App::new("notify")
+            .about("The places alerts get sent, set up in notify.json")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(App::new("list").about("Show every target"))
+            .subcommand(App::new("test")
+                .about("Send a test alert to every target")
+                .arg(Arg::with_name("code")
+                         .about("Code to put on the test alert")
+                         .value_name("COMPANY_CODE")
+                         .index(1)
+                         .default_value("TEST"))
+                .arg(Arg::with_name("dry-run")
+                         .about("Show what would be sent without sending it")
+                         .long("dry-run")),
+            ),
+        )
         .get_matches()
 }
 
+fn list_arg() -> Arg<'static> {
+    Arg::with_name("list")
+        .about("Use the codes on a saved watchlist instead of typing them")
+        .short('l')
+        .long("list")
+        .value_name("NAME")
+        .conflicts_with("code")
+}
+
+fn is_positive_number(val: &str) -> Result<(), String> {
+    match val.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(format!("'{}' needs to be a number bigger than zero", val)),
+    }
+}
+
+fn is_decimal(val: &str) -> Result<(), String> {
+    Decimal::from_str(val).map(|_| ()).map_err(|_| format!("'{}' needs to be a number like 12.50", val))
+}
+
+fn is_currency(val: &str) -> Result<(), String> {
+    val.parse::<CurrencyCode>().map(|_| ())
+}
+
+/// A single byte, or "tab" since that's awkward to type
+fn read_delimiter(val: &str) -> Result<u8, String> {
+    match val.as_bytes() {
+        b"tab" | b"\\t" => Ok(b'\t'),
+        [delimiter] => Ok(*delimiter),
+        _ => Err(format!("'{}' needs to be a single character or 'tab'", val)),
+    }
+}
+
+fn is_date(val: &str) -> Result<(), String> {
+    NaiveDate::parse_from_str(val, "%Y-%m-%d").map(|_| ()).map_err(|_| format!("'{}' needs to be a date like 2020-10-16", val))
+}
+
+fn read_fetch_options(args: &ArgMatches) -> FetchOptions {
+    FetchOptions {
+        concurrency: args.value_of_t_or_exit("concurrency"),
+        timeout: Duration::from_secs(args.value_of_t_or_exit("timeout")),
+    }
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
     env_logger::init();
     debug!("Starting stockchecker...");
     let args = init();
     debug!("stockchecker inited...");
 
-    let company_codes: Vec<_> = args.values_of("code").unwrap().collect();
-    if args.is_present("chart") {
-        let company_price_data :HashMap<String,Vec<Share>>= get_historical_price_data(company_codes).await;
-        print_price_chart(&company_price_data);
+    if let Err(e) = run(&args).await {
+        eprintln!("{}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+async fn run(args: &ArgMatches) -> StockResult<()> {
+    if let ("db", Some(db_args)) = args.subcommand() {
+        return run_db_command(db_args);
+    }
+    if let ("import", Some(import_args)) = args.subcommand() {
+        return run_import(import_args);
+    }
+    if let ("export", Some(export_args)) = args.subcommand() {
+        return run_export(export_args);
+    }
+    if let ("watch", Some(watch_args)) = args.subcommand() {
+        return run_watch(args, watch_args).await;
+    }
+    if let ("watchlist", Some(watchlist_args)) = args.subcommand() {
+        return run_watchlist_command(watchlist_args);
+    }
+    if let ("alert", Some(alert_args)) = args.subcommand() {
+        return run_alert_command(alert_args);
+    }
+    if let ("fx", Some(fx_args)) = args.subcommand() {
+        return run_fx_command(args, fx_args).await;
+    }
+    if let ("notify", Some(notify_args)) = args.subcommand() {
+        return run_notify_command(notify_args).await;
+    }
+    if let ("ledger", Some(ledger_args)) = args.subcommand() {
+        return run_ledger_command(ledger_args);
+    }
+    if let ("portfolio", Some(portfolio_args)) = args.subcommand() {
+        return run_portfolio_command(args, portfolio_args).await;
+    }
+
+    let mut store = storage::open_migrated_store()?;
+    let company_codes = read_company_codes(args, store.as_mut())?;
+    let company_codes: Vec<&str> = company_codes.iter().map(String::as_str).collect();
+    if args.is_present("chart") || args.is_present("compare") {
+        let company_price_data :HashMap<String,Vec<Share>>= get_historical_price_data(store.as_mut(), company_codes).await?;
+        let latest_prices: Vec<_> = company_price_data.values().filter_map(|shares| shares.last()).map(|share| &share.price).collect();
+        let (converter, fx_problems) = load_converter(args, store.as_mut(), &latest_prices).await?;
+        let mut missing_rates = Vec::new();
+        let company_price_data = match &converter {
+            Some(converter) => company_price_data.iter()
+                .map(|(company_code, shares)| (company_code.clone(), converter.convert_series(shares, &mut missing_rates)))
+                .collect(),
+            None => company_price_data,
+        };
+        print_price_chart(&company_price_data, args.is_present("compare"))?;
+        for problem in fx_problems.iter().map(ToString::to_string).chain(missing_rates.iter().map(ToString::to_string)) {
+            eprintln!("{}", problem);
+        }
     } else {
-        let company_prices = get_company_prices(company_codes).await?;
-        print_price_table(&company_prices);
-        if let Err(e) = save_prices(company_prices) {
-            panic!("Couldn't save prices {}", e.to_string())
+        let source = price_source::source_for_name(args.value_of("source").unwrap(), args.value_of("fixtures").unwrap())?;
+        let moments = read_moments(args);
+        let notify_targets = notifier::load_targets(&config_options::read_notify_config()?);
+        let company_prices = get_company_prices(source.as_ref(), store.as_mut(), company_codes, &moments, read_fetch_options(args)).await?;
+        let triggered = check_alerts(store.as_mut(), &company_prices)?;
+        let (converter, fx_problems) = load_converter(args, store.as_mut(), &fx::todays_prices(&company_prices)).await?;
+        let (shown_prices, missing_rates) = in_report_currency(converter.as_ref(), &company_prices);
+        //validated by clap
+        let format: OutputFormat = args.value_of("format").unwrap().parse().unwrap();
+        match format {
+            OutputFormat::Table => print_price_table(&shown_prices, &moments, &triggered),
+            _ => print_quotes(format, &shown_prices, &moments, &triggered)?,
+        }
+        for problem in fx_problems.iter().map(ToString::to_string).chain(missing_rates.iter().map(ToString::to_string)) {
+            eprintln!("{}", problem);
         }
+        let deliveries = notifier::notify_all(&notify_targets, &triggered).await;
+        if !deliveries.is_empty() && format == OutputFormat::Table {
+            print_deliveries(&deliveries);
+        }
+        let found: Vec<ShareTimeline> = company_prices.into_iter().filter_map(FetchOutcome::into_found).collect();
+        store.save_prices(&found)?;
     }
 
     Ok(())
 }
 
-async fn get_historical_price_data(company_codes: Vec<&str>) -> HashMap<String, Vec<Share>> {
+fn read_moments(args: &ArgMatches) -> Vec<ShareMoment> {
+    let mut moments: Vec<ShareMoment> = Vec::new();
+    for moment in args.values_of("moments").unwrap() {
+        //already validated by clap
+        let moment = moment.parse().unwrap();
+        if !moments.contains(&moment) {
+            moments.push(moment);
+        }
+    }
+    moments
+}
+
+/// Source, moments and fetch options come from the top level, so `stockchecker -s replay watch NPN`
+async fn run_watch(args: &ArgMatches, watch_args: &ArgMatches) -> StockResult<()> {
+    let interval = Duration::from_secs(watch_args.value_of_t_or_exit("interval"));
+    let save = watch_args.is_present("save");
+    let source = price_source::source_for_name(args.value_of("source").unwrap(), args.value_of("fixtures").unwrap())?;
+    let moments = read_moments(args);
+    let mut store = storage::open_migrated_store()?;
+    let company_codes = read_company_codes(watch_args, store.as_mut())?;
+    let company_codes: Vec<&str> = company_codes.iter().map(String::as_str).collect();
+    let notify_targets = Arc::new(notifier::load_targets(&config_options::read_notify_config()?));
+
+    let mut screen = WatchScreen::new(&moments, interval)?;
+    let mut deliveries = Vec::new();
+    while let WatchCommand::Refresh = screen.next_command()? {
+        let company_prices = get_company_prices(source.as_ref(), store.as_mut(), company_codes.clone(), &moments, read_fetch_options(args)).await?;
+        let triggered = check_alerts(store.as_mut(), &company_prices)?;
+        //anything that can't be converted shows up as a currency mismatch on screen, and why on the status line
+        let (converter, fx_problems) = load_converter(args, store.as_mut(), &fx::todays_prices(&company_prices)).await?;
+        let (shown_prices, missing_rates) = in_report_currency(converter.as_ref(), &company_prices);
+        screen.update(&shown_prices, &triggered);
+        screen.note_fx_problems(&fx_problems.iter().map(ToString::to_string).chain(missing_rates.iter().map(ToString::to_string)).collect::<Vec<_>>());
+        if !triggered.is_empty() {
+            let (notify_targets, delivered) = (notify_targets.clone(), screen.delivery_sender());
+            deliveries.push(tokio::spawn(async move {
+                //nobody to tell if the screen has already gone
+                let _ = delivered.send(notifier::notify_all(&notify_targets, &triggered).await);
+            }));
+        }
+        if save {
+            let found: Vec<ShareTimeline> = company_prices.into_iter().filter_map(FetchOutcome::into_found).collect();
+            store.save_prices(&found)?;
+            screen.note_saved(found.len());
+        }
+    }
+
+    //put the terminal back before waiting on anything still being sent
+    drop(screen);
+    let unfinished: Vec<_> = deliveries.into_iter()
+        .filter_map(|mut delivery| (&mut delivery).now_or_never().is_none().then_some(delivery))
+        .collect();
+    if !unfinished.is_empty() {
+        eprintln!("Waiting for {} alert deliveries to finish...", unfinished.len());
+        futures::future::join_all(unfinished).await;
+    }
+    Ok(())
+}
+
+/// The rates to show prices in `--report-currency` with, after fetching today's for any currency we haven't got one for.
+/// Rates that couldn't be fetched come back as problems, the saved ones still get used.
+/// Today's rate is fetched for the currency of each of `todays_prices` that doesn't have one yet
+async fn load_converter(args: &ArgMatches, store: &mut dyn ShareStore, todays_prices: &[&Price]) -> StockResult<(Option<Converter>, Vec<StockError>)> {
+    let to: CurrencyCode = match args.value_of("report_currency") {
+        //validated by clap
+        Some(to) => to.parse().unwrap(),
+        None => return Ok((None, Vec::new())),
+    };
+    let mut fx_table = FxTable::new(&store.load_fx_rates()?);
+    let rate_source = fx::rate_source_for_name(args.value_of("source").unwrap(), args.value_of("fixtures").unwrap())?;
+    let currencies = fx::currencies_to_convert(todays_prices, &to);
+    let (fetched, problems) = fx::fetch_missing_rates(rate_source.as_ref(), &fx_table, &currencies, &to).await;
+    store.save_fx_rates(&fetched)?;
+    fx_table.add(&fetched);
+    Ok((Some(Converter::new(to, fx_table)), problems))
+}
+
+/// The prices as they go on screen, converted when there's a reporting currency.
+/// What gets saved and alerted on is always the price as it was quoted.
+fn in_report_currency<'a>(converter: Option<&Converter>, company_prices: &'a [FetchOutcome]) -> (Cow<'a, [FetchOutcome]>, Vec<MissingRate>) {
+    match converter {
+        Some(converter) => {
+            let (converted, missing_rates) = converter.convert_outcomes(company_prices);
+            (Cow::Owned(converted), missing_rates)
+        }
+        None => (Cow::Borrowed(company_prices), Vec::new()),
+    }
+}
+
+/// Has to happen before the new prices are saved, so the alerts compare against the last run
+fn check_alerts(store: &mut dyn ShareStore, company_prices: &[FetchOutcome]) -> StockResult<Vec<TriggeredAlert>> {
+    let found: Vec<&ShareTimeline> = company_prices.iter().filter_map(FetchOutcome::found).collect();
+    alerts::check_alerts(store, &found)
+}
+
+/// The codes typed in, or the ones on the `--list` watchlist
+fn read_company_codes(args: &ArgMatches, store: &mut dyn ShareStore) -> StockResult<Vec<String>> {
+    match args.value_of("list") {
+        Some(watchlist_name) => {
+            let company_codes = store.watchlist_codes(watchlist_name)?;
+            if company_codes.is_empty() {
+                return Err(StockError::Config(format!("There's no watchlist called '{}', see `stockchecker watchlist list`", watchlist_name)));
+            }
+            Ok(company_codes)
+        }
+        None => Ok(args.values_of("code").unwrap().map(ToString::to_string).collect()),
+    }
+}
+
+/// Anything not given on the command line comes from the last price we have for the code
+fn run_import(import_args: &ArgMatches) -> StockResult<()> {
+    let mut store = storage::open_migrated_store()?;
+    let company_code = import_args.value_of("code").unwrap();
+    let existing = store.load_complete_share_history(company_code)?;
+    let latest = existing.iter().max_by_key(|share| share.price_date);
+    //all validated by clap
+    let layout = ImportLayout {
+        date_column: import_args.value_of("date_column").map(ToString::to_string),
+        close_column: import_args.value_of("close_column").map(ToString::to_string),
+        date_format: import_args.value_of("date_format").unwrap().to_string(),
+        delimiter: read_delimiter(import_args.value_of("delimiter").unwrap()).unwrap(),
+    };
+    let import_options = ImportOptions {
+        exchange_tz: import_args.value_of("tz").map(|tz| tz.parse().unwrap())
+            .or_else(|| latest.map(|share| share.exchange_tz))
+            .unwrap_or(Tz::UTC),
+        close_time: NaiveTime::parse_from_str(import_args.value_of("close_time").unwrap(), "%H:%M").unwrap(),
+        currency: import_args.value_of("currency").map(|currency| currency.parse().unwrap())
+            .or_else(|| latest.and_then(|share| share.price.currency.clone())),
+    };
+
+    let file_name = import_args.value_of("file").unwrap();
+    let file = std::fs::File::open(file_name)
+        .map_err(|e| StockError::Config(format!("Couldn't open {}: {}", file_name, e)))?;
+    let daily_closes = backfill::read_daily_closes(file, &layout)?;
+    let plan = backfill::plan_import(company_code, &daily_closes, &existing, &import_options);
+    println!("{}", plan);
+    if import_args.is_present("dry-run") {
+        println!("Dry run, nothing saved");
+    } else {
+        store.save_prices(&plan.timelines())?;
+        println!("Imported {} prices for {}", plan.to_save.len(), company_code);
+    }
+    Ok(())
+}
+
+/// Oldest first within each code, codes in the order given
+fn run_export(export_args: &ArgMatches) -> StockResult<()> {
+    let mut store = storage::open_migrated_store()?;
+    let company_codes = if export_args.is_present("code") || export_args.is_present("list") {
+        read_company_codes(export_args, store.as_mut())?
+    } else {
+        store.priced_codes()?
+    };
+    //all validated by clap
+    let read_day = |name: &str| export_args.value_of(name).map(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d").unwrap());
+    let export_filter = ExportFilter {
+        from: read_day("from"),
+        to: read_day("to"),
+        source: export_args.value_of("source").map(ToString::to_string),
+    };
+    let format: ExportFormat = export_args.value_of("format").unwrap().parse().unwrap();
+
+    let mut shares = Vec::new();
+    for company_code in &company_codes {
+        shares.extend(store.load_complete_share_history(company_code)?.into_iter()
+            .filter(|share| export_filter.matches(share)));
+    }
+
+    match export_args.value_of("output") {
+        Some(file_name) => {
+            let file = std::fs::File::create(file_name)
+                .map_err(|e| StockError::Config(format!("Couldn't create {}: {}", file_name, e)))?;
+            let exported = export::write_prices(std::io::BufWriter::new(file), format, &shares)?;
+            println!("Exported {} prices to {}", exported, file_name);
+        }
+        None => {
+            let stdout = std::io::stdout();
+            export::write_prices(std::io::BufWriter::new(stdout.lock()), format, &shares)?;
+        }
+    }
+    Ok(())
+}
+
+fn run_watchlist_command(watchlist_args: &ArgMatches) -> StockResult<()> {
+    let mut store = storage::open_migrated_store()?;
+    match watchlist_args.subcommand() {
+        ("add", Some(add_args)) => {
+            let watchlist_name = add_args.value_of("name").unwrap();
+            let company_codes: Vec<_> = add_args.values_of("code").unwrap().collect();
+            let added = store.add_to_watchlist(watchlist_name, &company_codes)?;
+            println!("Added {} codes to {}", added, watchlist_name);
+        }
+        ("remove", Some(remove_args)) => {
+            let watchlist_name = remove_args.value_of("name").unwrap();
+            let company_codes: Vec<_> = remove_args.values_of("code").map(|codes| codes.collect()).unwrap_or_default();
+            let removed = store.remove_from_watchlist(watchlist_name, &company_codes)?;
+            println!("Removed {} codes from {}", removed, watchlist_name);
+        }
+        ("list", _) => print_watchlists(store.as_mut())?,
+        ("show", Some(show_args)) => print_watchlist(store.as_mut(), show_args.value_of("name").unwrap())?,
+        ("describe", Some(describe_args)) => {
+            let company_code = describe_args.value_of("code").unwrap();
+            let mut ticker_info = store.load_ticker_info(company_code)?.unwrap_or_else(|| TickerInfo {
+                company_code: company_code.to_string(),
+                ..TickerInfo::default()
+            });
+            //only what was given changes, and giving an empty value clears it
+            let read_field = |field: &str, current: Option<String>| match describe_args.value_of(field) {
+                Some("") => None,
+                Some(value) => Some(value.to_string()),
+                None => current,
+            };
+            ticker_info.display_name = read_field("display_name", ticker_info.display_name);
+            ticker_info.exchange = read_field("exchange", ticker_info.exchange);
+            ticker_info.notes = read_field("notes", ticker_info.notes);
+            store.save_ticker_info(&ticker_info)?;
+            print_ticker_infos(&[(company_code.to_string(), Some(ticker_info))]);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn run_portfolio_command(args: &ArgMatches, portfolio_args: &ArgMatches) -> StockResult<()> {
+    let mut store = storage::open_migrated_store()?;
+    match portfolio_args.subcommand() {
+        ("add", Some(add_args)) => {
+            //all validated by clap
+            let holding = Holding {
+                id: None,
+                company_code: add_args.value_of("code").unwrap().to_string(),
+                quantity: Decimal::from_str(add_args.value_of("quantity").unwrap()).unwrap(),
+                buy_price: Decimal::from_str(add_args.value_of("buy_price").unwrap()).unwrap(),
+                buy_date: add_args.value_of("buy_date")
+                    .map(|buy_date| NaiveDate::parse_from_str(buy_date, "%Y-%m-%d").unwrap())
+                    .unwrap_or_else(|| Local::now().date_naive()),
+                fees: Decimal::from_str(add_args.value_of("fees").unwrap()).unwrap(),
+            };
+            let holding_id = store.add_holding(&holding)?;
+            println!("Added lot {}: {} {} at {}", holding_id, holding.quantity, holding.company_code, holding.buy_price);
+        }
+        ("remove", Some(remove_args)) => {
+            let holding_id: i64 = remove_args.value_of_t_or_exit("id");
+            if !store.remove_holding(holding_id)? {
+                return Err(StockError::Config(format!("There's no lot {}, see `stockchecker portfolio lots`", holding_id)));
+            }
+            println!("Removed lot {}", holding_id);
+        }
+        ("lots", _) => print_holdings(&load_all_holdings(store.as_mut())?),
+        _ => {
+            let holdings = load_all_holdings(store.as_mut())?;
+            let mut company_codes: Vec<&str> = Vec::new();
+            for holding in &holdings {
+                if !company_codes.contains(&holding.company_code.as_str()) {
+                    company_codes.push(&holding.company_code);
+                }
+            }
+            let source = price_source::source_for_name(args.value_of("source").unwrap(), args.value_of("fixtures").unwrap())?;
+            let current_prices = get_current_shares(source.as_ref(), store.as_mut(), &company_codes, read_fetch_options(args)).await?;
+            let todays_prices: Vec<_> = current_prices.values().map(|share| &share.price).collect();
+            let (converter, fx_problems) = load_converter(args, store.as_mut(), &todays_prices).await?;
+            let (holdings, current_prices, missing_rates) = match &converter {
+                Some(converter) => converter.convert_portfolio(&holdings, current_prices),
+                None => (holdings, current_prices, Vec::new()),
+            };
+            print_portfolio(&Portfolio::value(&holdings, current_prices));
+            for problem in fx_problems.iter().map(ToString::to_string).chain(missing_rates.iter().map(ToString::to_string)) {
+                eprintln!("{}", problem);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The lots added with `portfolio add`, then whatever the ledger still holds. The ledger's are matched first in
+/// first out, with fees and splits folded into the buy price, and have no id since they're changed through the ledger.
+fn load_all_holdings(store: &mut dyn ShareStore) -> StockResult<Vec<Holding>> {
+    let mut holdings = store.load_holdings()?;
+    holdings.extend(ledger::realize(&store.load_transactions()?, LotMethod::Fifo, TaxYearStart::default())?.open_lots);
+    Ok(holdings)
+}
+
+/// Today's price for each code, falling back on the last one we saved when the source lets us down
+async fn get_current_shares(source: &dyn PriceSource, store: &mut dyn ShareStore, company_codes: &[&str], fetch_options: FetchOptions) -> StockResult<HashMap<String, Share>> {
+    let quotes = price_source::fetch_quotes(source, company_codes, fetch_options).await;
+    let mut current_shares = HashMap::new();
+    for (company_code, quote) in company_codes.iter().zip(quotes) {
+        let share = match quote_to_share(company_code, quote) {
+            Ok(share) => Some(share),
+            Err(outcome) => {
+                warn!("{}: {}, using the last saved price", company_code, outcome.failure().map(|(_, reason)| reason).unwrap_or_default());
+                store.load_latest_before(company_code, Utc::now().naive_utc())?
+            }
+        };
+        if let Some(share) = share {
+            current_shares.insert(company_code.to_string(), share);
+        }
+    }
+    Ok(current_shares)
+}
+
+fn run_ledger_command(ledger_args: &ArgMatches) -> StockResult<()> {
+    let mut store = storage::open_migrated_store()?;
+    match ledger_args.subcommand() {
+        ("import", Some(import_args)) => {
+            let file_name = import_args.value_of("file").unwrap();
+            let file = std::fs::File::open(file_name)
+                .map_err(|e| StockError::Config(format!("Couldn't open {}: {}", file_name, e)))?;
+            let transactions = ledger::read_transactions_csv(file)?;
+            //play the whole lot through first so a sale of shares we never bought doesn't get in
+            let mut all_transactions = store.load_transactions()?;
+            all_transactions.extend(transactions.iter().cloned());
+            ledger::realize(&all_transactions, LotMethod::Fifo, TaxYearStart::default())?;
+            println!("Imported {} transactions", store.add_transactions(&transactions)?);
+        }
+        ("list", Some(list_args)) => {
+            let company_codes: Vec<&str> = list_args.values_of("code").map(|codes| codes.collect()).unwrap_or_default();
+            let transactions: Vec<Transaction> = store.load_transactions()?.into_iter()
+                .filter(|transaction| company_codes.is_empty() || company_codes.contains(&transaction.company_code.as_str()))
+                .collect();
+            print_transactions(&transactions);
+        }
+        ("report", Some(report_args)) => {
+            //both validated by clap
+            let method: LotMethod = report_args.value_of("method").unwrap().parse().unwrap();
+            let tax_year_start: TaxYearStart = report_args.value_of("tax_year_start").unwrap().parse().unwrap();
+            let report = ledger::realize(&store.load_transactions()?, method, tax_year_start)?;
+            print_ledger_report(&report);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn run_alert_command(alert_args: &ArgMatches) -> StockResult<()> {
+    let mut store = storage::open_migrated_store()?;
+    match alert_args.subcommand() {
+        ("add", Some(add_args)) => {
+            let condition = add_args.values_of("condition").unwrap().collect::<Vec<_>>().join(" ");
+            let rule = AlertRule {
+                id: None,
+                company_code: add_args.value_of("code").unwrap().to_string(),
+                condition: condition.parse().map_err(StockError::Config)?,
+                //validated by clap
+                cooldown: alerts::parse_cooldown(add_args.value_of("cooldown").unwrap()).unwrap(),
+                last_triggered: None,
+            };
+            let rule_id = store.add_alert_rule(&rule)?;
+            println!("Added rule {}: {} {}", rule_id, rule.company_code, rule.condition);
+        }
+        ("remove", Some(remove_args)) => {
+            let rule_id: i64 = remove_args.value_of_t_or_exit("id");
+            if !store.remove_alert_rule(rule_id)? {
+                return Err(StockError::Config(format!("There's no rule {}, see `stockchecker alert list`", rule_id)));
+            }
+            println!("Removed rule {}", rule_id);
+        }
+        ("list", _) => print_alert_rules(&store.load_alert_rules()?),
+        ("history", Some(history_args)) => print_alert_history(&store.load_alert_history(history_args.value_of_t_or_exit("limit"))?),
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Rates come from the same place as prices, so `stockchecker -s replay fx fetch USD --to ZAR`
+async fn run_fx_command(args: &ArgMatches, fx_args: &ArgMatches) -> StockResult<()> {
+    let mut store = storage::open_migrated_store()?;
+    match fx_args.subcommand() {
+        ("fetch", Some(fetch_args)) => {
+            //all validated by clap
+            let to: CurrencyCode = fetch_args.value_of("to").unwrap().parse().unwrap();
+            let rate_source = fx::rate_source_for_name(args.value_of("source").unwrap(), args.value_of("fixtures").unwrap())?;
+            let mut fetched = Vec::new();
+            let mut failed = None;
+            for base in fetch_args.values_of("base").unwrap() {
+                match rate_source.fetch_rate(&base.parse().unwrap(), &to).await {
+                    Ok(fx_rate) => fetched.push(fx_rate),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        failed = Some(e);
+                    }
+                }
+            }
+            store.save_fx_rates(&fetched)?;
+            print_fx_rates(&fetched);
+            if let Some(e) = failed {
+                return Err(e);
+            }
+        }
+        ("import", Some(import_args)) => {
+            let file_name = import_args.value_of("file").unwrap();
+            let file = std::fs::File::open(file_name)
+                .map_err(|e| StockError::Config(format!("Couldn't open {}: {}", file_name, e)))?;
+            let fx_rates = fx::read_rates_csv(file)?;
+            println!("Imported {} rates", store.save_fx_rates(&fx_rates)?);
+        }
+        ("list", Some(list_args)) => {
+            let currencies: Vec<CurrencyCode> = list_args.values_of("currency")
+                .map(|currencies| currencies.map(|currency| currency.parse().unwrap()).collect())
+                .unwrap_or_default();
+            let fx_rates: Vec<FxRate> = store.load_fx_rates()?.into_iter()
+                .filter(|fx_rate| currencies.is_empty() || currencies.contains(&fx_rate.base) || currencies.contains(&fx_rate.quote))
+                .collect();
+            print_fx_rates(&fx_rates);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn run_notify_command(notify_args: &ArgMatches) -> StockResult<()> {
+    let notify_config = config_options::read_notify_config()?;
+    if notify_config.targets.is_empty() {
+        println!("No targets, add some to notify.json");
+        return Ok(());
+    }
+    match notify_args.subcommand() {
+        ("list", _) => {
+            let mut tbl = Table::new();
+            tbl.add_row(Row::new(vec![
+                make_header("TYPE", color::BLUE),
+                make_header("TARGET", color::YELLOW),
+                make_header("RETRIES", color::YELLOW),
+                make_header("DRY RUN", color::YELLOW),
+            ]));
+            for target_config in &notify_config.targets {
+                tbl.add_row(Row::new(vec![
+                    Cell::new(target_config.target.kind()),
+                    Cell::new(&NotifyTarget::from_config(target_config).describe()),
+                    Cell::new(&target_config.retries.to_string()),
+                    Cell::new(if target_config.dry_run { "yes" } else { "no" }),
+                ]));
+            }
+            tbl.printstd();
+        }
+        ("test", Some(test_args)) => {
+            let mut notify_targets = notifier::load_targets(&notify_config);
+            if test_args.is_present("dry-run") {
+                notify_targets.iter_mut().for_each(|notify_target| notify_target.set_dry_run(true));
+            }
+            let alert = notifier::test_alert(test_args.value_of("code").unwrap());
+            print_deliveries(&notifier::notify_all(&notify_targets, &[alert]).await);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn run_db_command(db_args: &ArgMatches) -> StockResult<()> {
+    let mut store = storage::open_store()?;
+    match db_args.subcommand() {
+        ("migrate", _) => {
+            let applied = migrations::migrate(store.as_mut())?;
+            if applied.is_empty() {
+                println!("Schema already at version {}", migrations::current_version(store.as_mut())?);
+            }
+            for migration in applied {
+                println!("Applied {}: {}", migration.version, migration.description);
+            }
+        }
+        ("status", _) => print_migration_status(store.as_mut())?,
+        _ => {}
+    }
+    Ok(())
+}
+
+async fn get_historical_price_data(store: &mut dyn ShareStore, company_codes: Vec<&str>) -> StockResult<HashMap<String, Vec<Share>>> {
     let mut hist_data = HashMap::new();
     for code in company_codes {
-        hist_data.insert(code.to_string(), load_complete_share_history(code));
+        hist_data.insert(code.to_string(), store.load_complete_share_history(code)?);
     }
-    hist_data
+    Ok(hist_data)
 }
 
 /**
-Will return a vector of a map of a company
+Will return a vector of what we found for each company, in the order the codes came in
 */
-// #[tokio::main]
-async fn get_company_prices(company_codes: Vec<&str>) -> Result<Vec<ShareTimeline>, Box<dyn Error>> {
+async fn get_company_prices(source: &dyn PriceSource, store: &mut dyn ShareStore, company_codes: Vec<&str>, moments: &[ShareMoment], fetch_options: FetchOptions) -> StockResult<Vec<FetchOutcome>> {
+    debug!("Fetching {} codes from {}", company_codes.len(), source.name());
+    let quotes = price_source::fetch_quotes(source, &company_codes, fetch_options).await;
+
     let mut company_prices = Vec::new();
-    for company_code in company_codes {
-        let res = reqwest::get(&format!("https://www.google.com/search?hl=en&q=share+price+{}", company_code)).await?;
-        let body = res.text().await?;
-        // println!("Body:\n{}", body);
-        let search_doc = Document::from(body.borrow());
-        // let spans = search_doc.find(Name("span")).collect();
-        let starts_with_digits = Regex::new(r"(^[\d+\s]*\d+,.\d+\s)").unwrap();
-
-        let mut price = String::new();
-
-        for span in search_doc.find(Name("div")) {
-            let txt = span.text();
-            if txt.contains("Currency in ")
-                && starts_with_digits.is_match(&txt) {
-                let captures = starts_with_digits.captures(&txt).unwrap();
-                price = str::replace(&captures[1], ",", ".");
-                // println!("div=={}",  txt);
-                break;
+    for (company_code, quote) in company_codes.into_iter().zip(quotes) {
+        let company_curr = match quote_to_share(company_code, quote) {
+            Ok(share) => share,
+            Err(outcome) => {
+                warn!("{}: {}", company_code, outcome.failure().map(|(_, reason)| reason).unwrap_or_default());
+                company_prices.push(*outcome);
+                continue;
             }
-        }
-        //create share object from whence we just loaded
-        let now = Utc::now().naive_utc();
-        let company_curr = Share {
-            company_code: company_code.to_string(),
-            price: price.clone(),
-            price_date: now,
         };
 
-        //we should save the above share here?
-        //NO! But we also shouldn't load history below
-
-
-        //todo, we need to move this into its own method and out of here
         let mut share_history = HashMap::new();
 
-        if let Some(share) = load_share_history_segments(company_code, 1) {
-            share_history.insert(ShareMoment::Yesterday, share);
-        }
-        if let Some(share) = load_share_history_segments(company_code, 7) {
-            share_history.insert(ShareMoment::LastWeek, share);
-        }
-        if let Some(share) = load_share_history_segments(company_code, 30) {
-            share_history.insert(ShareMoment::LastMonth, share);
+        let as_of = company_curr.price_date;
+        let exchange_today = company_curr.exchange_date();
+        for moment in moments {
+            if let Some(share) = store.load_share_history_segments(company_code, moment.days_ago(exchange_today), as_of)? {
+                share_history.insert(*moment, share);
+            }
         }
 
         let share_timeline = ShareTimeline {
             share: company_curr,
             share_history,
         };
-        company_prices.push(share_timeline);
+        company_prices.push(FetchOutcome::Found(share_timeline));
     }
     Ok(company_prices)
 }
 
-fn load_complete_share_history(company_code: &str) -> Vec<Share> {
-    let mut conn = get_db_connection().unwrap();
-    let share_history = conn.exec_map(r"select company_code, price, price_date
-                                                            from stock_prices WHERE company_code=:code
-                                                            order by id",
-                                      params! { "code"=>company_code, },
-                                      |(company_code, price, price_date)| Share {
-                                          company_code,
-                                          price,
-                                          price_date,
-                                      });
-
-    match share_history {
-        Ok(share_hist) => share_hist,
-        Err(e) => panic!("Error getting share history for {}: {}", company_code, e.to_string()),
-    }
-}
-
-fn load_share_history_segments(company_code: &str, days_ago_upper_limit: i32) -> Option<Share> {
-    let mut conn = get_db_connection().unwrap();
-    let base_select = r"SELECT company_code as code, price, price_date
-                         FROM stock_prices WHERE company_code = :code
-                         AND date(price_date) <= curdate() ";
-    let yest_select = format!("{} - INTERVAL {} DAY ORDER BY id DESC ", base_select, days_ago_upper_limit);
-    match conn.exec_first(yest_select,
-                          params! {"code"=>company_code}) {
-        Ok(Some((code, price, price_date))) => Some(Share { company_code: code, price, price_date }),
-        Ok(None) => None,
-        Err(e) => panic!("Unable to get previous company info: {}", e.to_string()),
-    }
+/// Turn whatever the source gave us into a share, or the reason we couldn't
+/// The failure comes boxed, a whole `FetchOutcome` is a lot to hand back as an error
+fn quote_to_share(company_code: &str, quote: StockResult<price_source::Quote>) -> Result<Share, Box<FetchOutcome>> {
+    let quote = match quote {
+        Ok(quote) => quote,
+        Err(StockError::Parse(reason)) => return Err(Box::new(FetchOutcome::ParseFailure { company_code: company_code.to_string(), reason })),
+        Err(StockError::Network(reason)) => return Err(Box::new(FetchOutcome::Unreachable { company_code: company_code.to_string(), reason })),
+        Err(e) => return Err(Box::new(FetchOutcome::NotFound { company_code: company_code.to_string(), reason: e.to_string() })),
+    };
+    let price = match quote.price {
+        Some(price) => price,
+        None => return Err(Box::new(FetchOutcome::NotFound { company_code: company_code.to_string(), reason: "No price on the page".to_string() })),
+    };
+    Ok(Share {
+        company_code: quote.company_code,
+        price,
+        price_date: quote.quote_date,
+        exchange_tz: quote.exchange_tz,
+        source: Some(quote.source.to_string()),
+    })
 }
 
 fn construct_current_moment_share_columns(share: &share_price_model::Share) -> Vec<Cell> {
     vec![
         Cell::new(&share.company_code),
-        Cell::new(&share.pretty_price()).with_style(Attr::ForegroundColor(color::BRIGHT_BLUE)),
+        Cell::new(&share.price.to_string()).with_style(Attr::ForegroundColor(color::BRIGHT_BLUE)),
+        Cell::new(&share.price.currency_label()),
         Cell::new(&share.display_date()),
     ]
 }
@@ -186,62 +1076,119 @@ fn construct_historic_moment_share_columns(share_history_optional: Option<&share
 }
 
 fn construct_non_default_historic_row_section(share_history: &Share, share: &Share) -> Vec<Cell> {
-//calculate the price difference
-    let curr_price = share.price_as_decimal();
-    let historic_price = share_history.price_as_decimal();
-    let movement = curr_price - historic_price;
-
-    //calculate percentage price change
-    let percentage_movement = (curr_price / historic_price) * Decimal::from(100) - Decimal::from(100);
-    let (movement_style, percentage_string) = if movement < Decimal::zero() {
-        (Attr::ForegroundColor(color::RED), format!("{:.2}%", percentage_movement))
+    let movement = match Movement::between(share_history, share) {
+        Some(movement) => movement,
+        None => return vec![
+            Cell::new(&format!("{} {}", share_history.price, share_history.price.currency_label())).with_style(Attr::ForegroundColor(color::BRIGHT_BLUE)),
+            Cell::new(&share_history.display_date()),
+            Cell::new(&format!("{} vs {}", share_history.price.currency_label(), share.price.currency_label()))
+                .with_style(Attr::ForegroundColor(color::RED))
+                .with_hspan(2),
+        ],
+    };
+    let movement_style = if movement.is_down() {
+        Attr::ForegroundColor(color::RED)
     } else {
-        (Attr::ForegroundColor(color::GREEN), format!("+{:.2}%", percentage_movement))
+        Attr::ForegroundColor(color::GREEN)
     };
 
     vec![
-        Cell::new(&share_history.pretty_price()).with_style(Attr::ForegroundColor(color::BRIGHT_BLUE)),
+        Cell::new(&share_history.price.to_string()).with_style(Attr::ForegroundColor(color::BRIGHT_BLUE)),
         Cell::new(&share_history.display_date()),
-        Cell::new(&movement.to_string())
+        Cell::new(&share.price.shown(movement.absolute).to_string())
             .with_style(Attr::Bold)
             .with_style(movement_style),
-        Cell::new(&percentage_string)
+        Cell::new(&movement.percent_string())
             .with_style(Attr::Bold)
             .with_style(movement_style),
     ]
 }
 
-fn print_price_chart(company_prices: &HashMap<String, Vec<Share>>) {
-    ChartGrapher::draw_graph(company_prices).unwrap()
+fn print_price_chart(company_prices: &HashMap<String, Vec<Share>>, compare: bool) -> StockResult<()> {
+    ChartGrapher::draw_graph(company_prices, compare)
 }
 
 
-fn print_price_table(company_prices: &Vec<ShareTimeline>) {
+fn print_price_table(company_prices: &[FetchOutcome], moments: &[ShareMoment], alerts: &[TriggeredAlert]) {
     let mut tbl = Table::new();
-    let header_vec = construct_table_header();
+    let header_vec = construct_table_header(moments);
+    let column_count = header_vec.len();
     tbl.add_row(Row::new(header_vec));
 
-    for share_timeline in company_prices {
+    for share_timeline in company_prices.iter().filter_map(FetchOutcome::found) {
         let mut share_row: Vec<Cell> = Vec::new();
         share_row.append(&mut construct_current_moment_share_columns(&share_timeline.share));
+        if alerts.iter().any(|alert| alert.company_code == share_timeline.share.company_code) {
+            share_row[0].style(Attr::BackgroundColor(color::RED));
+            share_row[0].style(Attr::Bold);
+        }
 
-        share_row.append(&mut construct_historic_moment_share_columns(share_timeline.share_history.get(&ShareMoment::Yesterday), &share_timeline.share));
-        share_row.append(&mut construct_historic_moment_share_columns(share_timeline.share_history.get(&ShareMoment::LastWeek), &share_timeline.share));
-        share_row.append(&mut construct_historic_moment_share_columns(share_timeline.share_history.get(&ShareMoment::LastMonth), &share_timeline.share));
-        share_row.append(&mut construct_historic_moment_share_columns(share_timeline.share_history.get(&ShareMoment::LastYear), &share_timeline.share));
+        for moment in moments {
+            share_row.append(&mut construct_historic_moment_share_columns(share_timeline.share_history.get(moment), &share_timeline.share));
+        }
 
         tbl.add_row(Row::new(share_row));
     }
 
+    if !alerts.is_empty() {
+        tbl.add_row(Row::new(vec![
+            make_header("ALERT", color::RED),
+            make_header("TRIGGERED", color::RED).with_hspan(column_count - 1),
+        ]));
+        for alert in alerts {
+            tbl.add_row(Row::new(vec![
+                Cell::new(&alert.company_code).with_style(Attr::BackgroundColor(color::RED)).with_style(Attr::Bold),
+                Cell::new(&format!("{}: {}", alert.condition, alert.message)).with_hspan(column_count - 1),
+            ]));
+        }
+    }
+
+    for failure_kind in &FAILURE_KINDS {
+        let failures: Vec<_> = company_prices.iter()
+            .filter(|outcome| outcome.failure_kind() == Some(failure_kind))
+            .filter_map(FetchOutcome::failure)
+            .collect();
+        if failures.is_empty() {
+            continue;
+        }
+        tbl.add_row(Row::new(vec![
+            make_header(failure_kind, color::RED),
+            make_header("PROBLEM", color::RED).with_hspan(column_count - 1),
+        ]));
+        for (company_code, reason) in failures {
+            tbl.add_row(Row::new(vec![
+                Cell::new(company_code).with_style(Attr::ForegroundColor(color::RED)),
+                Cell::new(reason).with_hspan(column_count - 1),
+            ]));
+        }
+    }
+
     tbl.printstd();
 }
 
-fn construct_table_header() -> Vec<Cell> {
+/// Only the quotes go to stdout, so scripts can pipe it straight on. Alerts and failures go to stderr.
+fn print_quotes(format: OutputFormat, company_prices: &[FetchOutcome], moments: &[ShareMoment], alerts: &[TriggeredAlert]) -> StockResult<()> {
+    let quote_rows: Vec<QuoteRow> = company_prices.iter()
+        .filter_map(FetchOutcome::found)
+        .map(|share_timeline| QuoteRow::new(share_timeline, moments))
+        .collect();
+    print!("{}", quote_format::render(format, &quote_rows, moments)?);
+    for alert in alerts {
+        eprintln!("ALERT {} {}: {}", alert.company_code, alert.condition, alert.message);
+    }
+    for outcome in company_prices {
+        if let (Some(failure_kind), Some((company_code, reason))) = (outcome.failure_kind(), outcome.failure()) {
+            eprintln!("{} {}: {}", failure_kind, company_code, reason);
+        }
+    }
+    Ok(())
+}
+
+fn construct_table_header(moments: &[ShareMoment]) -> Vec<Cell> {
     let mut header_vec = construct_default_headers();
-    header_vec.append(&mut construct_price_cell_headers(&ShareMoment::Yesterday));
-    header_vec.append(&mut construct_price_cell_headers(&ShareMoment::LastWeek));
-    header_vec.append(&mut construct_price_cell_headers(&ShareMoment::LastMonth));
-    header_vec.append(&mut construct_price_cell_headers(&ShareMoment::LastYear));
+    for moment in moments {
+        header_vec.append(&mut construct_price_cell_headers(moment));
+    }
     header_vec
 }
 
@@ -256,6 +1203,10 @@ fn construct_default_headers() -> Vec<Cell> {
             .with_style(Attr::Bold)
             .with_style(Attr::ForegroundColor(color::YELLOW))
         ,
+        Cell::new("CCY")
+            .with_style(Attr::Bold)
+            .with_style(Attr::ForegroundColor(color::YELLOW))
+        ,
         Cell::new("CURR \nTIME")
             .with_style(Attr::Bold)
             .with_style(Attr::ForegroundColor(color::YELLOW))
@@ -280,54 +1231,350 @@ fn make_header(col_name: &str, color: color::Color) -> Cell {
         .with_style(Attr::ForegroundColor(color))
 }
 
+fn print_watchlists(store: &mut dyn ShareStore) -> StockResult<()> {
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("WATCHLIST", color::BLUE),
+        make_header("CODES", color::YELLOW),
+    ]));
+    for (watchlist_name, code_count) in store.watchlist_names()? {
+        tbl.add_row(Row::new(vec![
+            Cell::new(&watchlist_name),
+            Cell::new(&code_count.to_string()),
+        ]));
+    }
+    tbl.printstd();
+    Ok(())
+}
 
-//Save the  current prices
-fn save_prices(company_prices: Vec<ShareTimeline>) -> Result<(), Box<dyn std::error::Error>> {
-    let mut conn = get_db_connection()?;
-    //db connection
-    // let mut conn = get_db_connection();
-
-    //create table if needed
-    conn.query_drop(
-        r"CREATE TABLE IF NOT EXISTS stock_prices
-                 ( id bigint auto_increment,
-                   company_code varchar(255),
-                   price decimal(15,2),
-                   price_date datetime,
-                   primary key(id)
-                 );
-                   "
-    )?;
-    // for ctl in &company_prices{
-    //     println!("Code:{}", &ctl.share.company_code);
-    //     println!("price:{}", &ctl.share.price.split_whitespace().collect::<String>());
-    // }
-    //insert into table
-    conn.exec_batch(
-        r"INSERT INTO stock_prices(company_code, price, price_date)
-                VALUES (:code, :price, now())",
-        company_prices
-            .iter()
-            .map(|company_time_line| params! {
-                        "code" => &company_time_line.share.company_code,
-                        "price" => company_time_line.share.price.split_whitespace().collect::<String>(),
-                    }
-            ))?;
-
+fn print_watchlist(store: &mut dyn ShareStore, watchlist_name: &str) -> StockResult<()> {
+    let company_codes = store.watchlist_codes(watchlist_name)?;
+    if company_codes.is_empty() {
+        return Err(StockError::Config(format!("There's no watchlist called '{}'", watchlist_name)));
+    }
+    let mut ticker_infos = Vec::new();
+    for company_code in company_codes {
+        let ticker_info = store.load_ticker_info(&company_code)?;
+        ticker_infos.push((company_code, ticker_info));
+    }
+    print_ticker_infos(&ticker_infos);
     Ok(())
 }
 
-fn get_db_connection() -> Result<PooledConn, Box<dyn std::error::Error>> {
-    let conn_details = match config_options::read_db_config() {
-        Ok(connection) => connection,
-        Err(e) => panic!("TIME TO DIE, CONNECTION! {}", e.to_string()),
+fn print_ticker_infos(ticker_infos: &[(String, Option<TickerInfo>)]) {
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("CODE", color::BLUE),
+        make_header("NAME", color::YELLOW),
+        make_header("EXCHANGE", color::YELLOW),
+        make_header("NOTES", color::YELLOW),
+    ]));
+    for (company_code, ticker_info) in ticker_infos {
+        let field = |value: Option<&String>| Cell::new(value.map(String::as_str).unwrap_or("---"));
+        let ticker_info = ticker_info.as_ref();
+        tbl.add_row(Row::new(vec![
+            Cell::new(company_code),
+            field(ticker_info.and_then(|info| info.display_name.as_ref())),
+            field(ticker_info.and_then(|info| info.exchange.as_ref())),
+            field(ticker_info.and_then(|info| info.notes.as_ref())),
+        ]));
+    }
+    tbl.printstd();
+}
+
+fn print_holdings(holdings: &[Holding]) {
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("ID", color::BLUE),
+        make_header("CODE", color::BLUE),
+        make_header("QUANTITY", color::YELLOW),
+        make_header("BUY PRICE", color::YELLOW),
+        make_header("BUY DATE", color::YELLOW),
+        make_header("FEES", color::YELLOW),
+        make_header("COST", color::YELLOW),
+    ]));
+    for holding in holdings {
+        tbl.add_row(Row::new(vec![
+            Cell::new(&holding.id.map(|id| id.to_string()).unwrap_or_default()),
+            Cell::new(&holding.company_code),
+            Cell::new(&holding.quantity.to_string()),
+            Cell::new(&holding.buy_price.to_string()),
+            Cell::new(&holding.buy_date.to_string()),
+            Cell::new(&holding.fees.to_string()),
+            Cell::new(&format!("{:.2}", holding.cost_basis())),
+        ]));
+    }
+    tbl.printstd();
+}
+
+fn print_portfolio(portfolio: &Portfolio) {
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("CODE", color::BLUE),
+        make_header("QUANTITY", color::YELLOW),
+        make_header("AVG \nCOST", color::YELLOW),
+        make_header("COST \nBASIS", color::YELLOW),
+        make_header("CURRENT \nPRICE", color::BRIGHT_BLUE),
+        make_header("PRICE \nDATE", color::BRIGHT_BLUE),
+        make_header("MARKET \nVALUE", color::BRIGHT_BLUE),
+        make_header("GAIN", color::BRIGHT_YELLOW),
+        make_header("", color::BRIGHT_YELLOW),
+        make_header("WEIGHT", color::BRIGHT_YELLOW),
+    ]));
+
+    let money = |value: Option<Decimal>| Cell::new(&value.map(|value| format!("{:.2}", value)).unwrap_or_else(|| "---".to_string()));
+    for position in &portfolio.positions {
+        let mut row = vec![
+            Cell::new(&position.company_code),
+            Cell::new(&position.quantity.to_string()),
+            money(Some(position.average_cost())),
+            money(Some(position.cost_basis)),
+        ];
+        match &position.current {
+            Some(share) => {
+                row.push(Cell::new(&share.price.to_string()).with_style(Attr::ForegroundColor(color::BRIGHT_BLUE)));
+                row.push(Cell::new(&share.display_date()));
+            }
+            None => row.extend(vec![Cell::new("---"), Cell::new("---")]),
+        }
+        row.push(money(position.market_value()));
+        row.append(&mut gain_cells(position.gain(), position.gain_percent()));
+        row.push(Cell::new(&portfolio.weight(position).map(|weight| format!("{:.2}%", weight)).unwrap_or_else(|| "---".to_string())));
+        tbl.add_row(Row::new(row));
+    }
+
+    //a total per currency, money in different ones can't be added up
+    let totals = portfolio.totals();
+    if totals.is_empty() {
+        let mut no_total = vec![make_header("TOTAL", color::BLUE)];
+        no_total.extend((0..9).map(|_| Cell::new("---")));
+        tbl.add_row(Row::new(no_total));
+    }
+    for total in &totals {
+        let label = match &total.currency {
+            Some(currency) => format!("TOTAL {}", currency),
+            None => "TOTAL".to_string(),
+        };
+        let mut total_row = vec![
+            make_header(&label, color::BLUE),
+            Cell::new(""),
+            Cell::new(""),
+            money(Some(total.priced_cost)).with_style(Attr::Bold),
+            Cell::new(""),
+            Cell::new(""),
+            money(Some(total.market_value)).with_style(Attr::Bold),
+        ];
+        total_row.append(&mut gain_cells(Some(total.gain()), Some(total.gain_percent())));
+        total_row.push(Cell::new("100.00%"));
+        tbl.add_row(Row::new(total_row));
+    }
+    tbl.printstd();
+}
+
+/// Money and percent gain, green when it's up and red when it's down
+fn gain_cells(gain: Option<Decimal>, gain_percent: Option<Decimal>) -> Vec<Cell> {
+    match (gain, gain_percent) {
+        (Some(gain), Some(gain_percent)) => {
+            let (gain_style, sign) = if gain < Decimal::zero() {
+                (Attr::ForegroundColor(color::RED), "")
+            } else {
+                (Attr::ForegroundColor(color::GREEN), "+")
+            };
+            vec![
+                Cell::new(&format!("{}{:.2}", sign, gain)).with_style(Attr::Bold).with_style(gain_style),
+                Cell::new(&format!("{}{:.2}%", sign, gain_percent)).with_style(Attr::Bold).with_style(gain_style),
+            ]
+        }
+        _ => vec![Cell::new("---"), Cell::new("---")],
+    }
+}
+
+fn print_transactions(transactions: &[Transaction]) {
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("ID", color::BLUE),
+        make_header("DATE", color::BLUE),
+        make_header("CODE", color::BLUE),
+        make_header("TYPE", color::YELLOW),
+        make_header("QUANTITY", color::YELLOW),
+        make_header("PRICE", color::YELLOW),
+        make_header("FEES", color::YELLOW),
+    ]));
+    for transaction in transactions {
+        tbl.add_row(Row::new(vec![
+            Cell::new(&transaction.id.map(|id| id.to_string()).unwrap_or_default()),
+            Cell::new(&transaction.trade_date.to_string()),
+            Cell::new(&transaction.company_code),
+            Cell::new(&transaction.kind.to_string()),
+            Cell::new(&transaction.quantity.to_string()),
+            Cell::new(&transaction.price.to_string()),
+            Cell::new(&transaction.fees.to_string()),
+        ]));
+    }
+    tbl.printstd();
+}
+
+fn print_ledger_report(report: &LedgerReport) {
+    let mut by_ticker = Table::new();
+    by_ticker.add_row(Row::new(realized_headers("CODE", vec![make_header("HELD", color::BRIGHT_BLUE)])));
+    for (company_code, totals) in &report.by_ticker {
+        let held = report.held.get(company_code).copied().unwrap_or_default();
+        by_ticker.add_row(Row::new(realized_cells(Cell::new(company_code), totals, vec![Cell::new(&held.to_string())])));
+    }
+    by_ticker.add_row(Row::new(realized_cells(make_header("TOTAL", color::BLUE), &report.total(), vec![Cell::new("")])));
+    by_ticker.printstd();
+
+    let mut by_tax_year = Table::new();
+    by_tax_year.add_row(Row::new(realized_headers("TAX YEAR", Vec::new())));
+    for (tax_year, totals) in &report.by_tax_year {
+        by_tax_year.add_row(Row::new(realized_cells(Cell::new(tax_year), totals, Vec::new())));
+    }
+    by_tax_year.printstd();
+}
+
+fn realized_headers(first: &str, mut extra: Vec<Cell>) -> Vec<Cell> {
+    let mut headers = vec![
+        make_header(first, color::BLUE),
+        make_header("SOLD", color::YELLOW),
+        make_header("PROCEEDS", color::YELLOW),
+        make_header("COST", color::YELLOW),
+        make_header("REALIZED", color::BRIGHT_YELLOW),
+        make_header("DIVIDENDS", color::BRIGHT_YELLOW),
+    ];
+    headers.append(&mut extra);
+    headers
+}
+
+fn realized_cells(first: Cell, totals: &RealizedTotals, mut extra: Vec<Cell>) -> Vec<Cell> {
+    let gain = totals.gain();
+    let (gain_style, sign) = if gain < Decimal::zero() {
+        (Attr::ForegroundColor(color::RED), "")
+    } else {
+        (Attr::ForegroundColor(color::GREEN), "+")
     };
-    //read db connection stuff from database
-    let builder = OptsBuilder::new()
-        .user(Some(conn_details.username))
-        .pass(Some(conn_details.password))
-        .db_name(Some(conn_details.database));
-
-    let pool = Pool::new(builder)?;
-    Ok(pool.get_conn()?)
-}
\ No newline at end of file
+    let mut cells = vec![
+        first,
+        Cell::new(&totals.quantity_sold.to_string()),
+        Cell::new(&format!("{:.2}", totals.proceeds)),
+        Cell::new(&format!("{:.2}", totals.cost)),
+        Cell::new(&format!("{}{:.2}", sign, gain)).with_style(Attr::Bold).with_style(gain_style),
+        Cell::new(&format!("{:.2}", totals.dividends)),
+    ];
+    cells.append(&mut extra);
+    cells
+}
+
+fn print_alert_rules(rules: &[AlertRule]) {
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("ID", color::BLUE),
+        make_header("CODE", color::BLUE),
+        make_header("CONDITION", color::YELLOW),
+        make_header("COOLDOWN", color::YELLOW),
+        make_header("LAST \nTRIGGERED", color::YELLOW),
+    ]));
+    for rule in rules {
+        tbl.add_row(Row::new(vec![
+            Cell::new(&rule.id.map(|id| id.to_string()).unwrap_or_default()),
+            Cell::new(&rule.company_code),
+            Cell::new(&rule.condition.to_string()),
+            Cell::new(&alerts::format_cooldown(rule.cooldown)),
+            Cell::new(&rule.last_triggered.map(|last_triggered| last_triggered.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_else(|| "never".to_string())),
+        ]));
+    }
+    tbl.printstd();
+}
+
+fn print_alert_history(history: &[TriggeredAlert]) {
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("TRIGGERED", color::BLUE),
+        make_header("CODE", color::BLUE),
+        make_header("RULE", color::YELLOW),
+        make_header("PRICE", color::BRIGHT_BLUE),
+        make_header("WHAT HAPPENED", color::YELLOW),
+    ]));
+    for alert in history {
+        tbl.add_row(Row::new(vec![
+            Cell::new(&alert.triggered_at.format("%Y-%m-%d %H:%M:%S").to_string()),
+            Cell::new(&alert.company_code).with_style(Attr::ForegroundColor(color::RED)),
+            Cell::new(&format!("{} ({})", alert.condition, alert.rule_id)),
+            Cell::new(&alert.price.to_string()).with_style(Attr::ForegroundColor(color::BRIGHT_BLUE)),
+            Cell::new(&alert.message),
+        ]));
+    }
+    tbl.printstd();
+}
+
+fn print_fx_rates(fx_rates: &[FxRate]) {
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("DATE", color::BLUE),
+        make_header("BASE", color::BLUE),
+        make_header("QUOTE", color::BLUE),
+        make_header("RATE", color::BRIGHT_BLUE),
+        make_header("SOURCE", color::YELLOW),
+    ]));
+    for fx_rate in fx_rates {
+        tbl.add_row(Row::new(vec![
+            Cell::new(&fx_rate.rate_date.to_string()),
+            Cell::new(&fx_rate.base.to_string()),
+            Cell::new(&fx_rate.quote.to_string()),
+            Cell::new(&fx_rate.rate.to_string()).with_style(Attr::ForegroundColor(color::BRIGHT_BLUE)),
+            Cell::new(&fx_rate.source),
+        ]));
+    }
+    tbl.printstd();
+}
+
+fn print_deliveries(deliveries: &[Delivery]) {
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("SENT TO", color::BLUE),
+        make_header("CODE", color::BLUE),
+        make_header("RESULT", color::YELLOW),
+    ]));
+    for delivery in deliveries {
+        let result = match &delivery.outcome {
+            DeliveryOutcome::Sent { attempts: 1 } => Cell::new("sent").with_style(Attr::ForegroundColor(color::GREEN)),
+            DeliveryOutcome::Sent { attempts } => Cell::new(&format!("sent after {} tries", attempts)).with_style(Attr::ForegroundColor(color::GREEN)),
+            DeliveryOutcome::DryRun(preview) => Cell::new(&format!("dry run, would send:\n{}", preview)).with_style(Attr::ForegroundColor(color::YELLOW)),
+            DeliveryOutcome::Failed { attempts, error } => Cell::new(&format!("gave up after {} tries: {}", attempts, error)).with_style(Attr::ForegroundColor(color::RED)),
+        };
+        tbl.add_row(Row::new(vec![
+            Cell::new(&delivery.target),
+            Cell::new(&delivery.company_code),
+            result,
+        ]));
+    }
+    tbl.printstd();
+}
+
+fn print_migration_status(store: &mut dyn ShareStore) -> StockResult<()> {
+    let applied = store.applied_migrations()?;
+    let mut tbl = Table::new();
+    tbl.add_row(Row::new(vec![
+        make_header("VERSION", color::BLUE),
+        make_header("DESCRIPTION", color::YELLOW),
+        make_header("APPLIED", color::YELLOW),
+    ]));
+    for migration in migrations::MIGRATIONS {
+        let row = match applied.iter().find(|applied| applied.version == migration.version) {
+            Some(applied) => vec![
+                Cell::new(&migration.version.to_string()),
+                Cell::new(&applied.description),
+                Cell::new(&applied.applied_at.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .with_style(Attr::ForegroundColor(color::GREEN)),
+            ],
+            None => vec![
+                Cell::new(&migration.version.to_string()),
+                Cell::new(migration.description),
+                Cell::new("pending").with_style(Attr::ForegroundColor(color::RED)),
+            ],
+        };
+        tbl.add_row(Row::new(row));
+    }
+    tbl.printstd();
+    println!("Schema version {} of {}", migrations::current_version(store)?, migrations::latest_version());
+    Ok(())
+}
@@ -0,0 +1,348 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use log::{info, warn};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::alerts::TriggeredAlert;
+use crate::error::{StockError, StockResult};
+use crate::quote_format::utc_string;
+
+pub mod command;
+pub mod email;
+pub mod webhook;
+
+/// Where alerts get sent, as written in notify.json
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Target {
+    /// POSTs the alert as JSON
+    Webhook { url: String },
+    /// Plain SMTP to a relay that doesn't want a login, e.g. "localhost:25"
+    Email { server: String, from: String, to: Vec<String> },
+    /// Runs the program with the alert in STOCKCHECKER_* environment variables
+    Command {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+impl Target {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Target::Webhook { .. } => "webhook",
+            Target::Email { .. } => "email",
+            Target::Command { .. } => "command",
+        }
+    }
+}
+
+/// One target and how hard to try it
+#[derive(Debug, Clone, Deserialize)]
+pub struct TargetConfig {
+    #[serde(flatten)]
+    pub target: Target,
+    /// Tries after the first one fails
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Doubles after every failed try
+    #[serde(default = "default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Say what would have been sent instead of sending it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_retry_delay_ms() -> u64 {
+    1000
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
+
+/// Without a notify.json alerts only show up in the terminal
+#[derive(Debug, Default, Deserialize)]
+pub struct NotifyConfig {
+    #[serde(default)]
+    pub targets: Vec<TargetConfig>,
+}
+
+/// Somewhere an alert can be sent
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Where it goes, for the logs and the delivery table
+    fn describe(&self) -> String;
+    /// What `send` would send
+    fn preview(&self, alert: &TriggeredAlert) -> String;
+    async fn send(&self, alert: &TriggeredAlert) -> StockResult<()>;
+}
+
+pub fn notifier_for_target(target: &Target) -> Box<dyn Notifier> {
+    match target {
+        Target::Webhook { url } => Box::new(webhook::WebhookNotifier::new(url)),
+        Target::Email { server, from, to } => Box::new(email::EmailNotifier::new(server, from, to)),
+        Target::Command { program, args } => Box::new(command::CommandNotifier::new(program, args)),
+    }
+}
+
+/// A notifier with its retry policy wrapped round it
+pub struct NotifyTarget {
+    notifier: Box<dyn Notifier>,
+    retries: u32,
+    retry_delay: Duration,
+    timeout: Duration,
+    dry_run: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeliveryOutcome {
+    Sent { attempts: u32 },
+    /// What would have been sent
+    DryRun(String),
+    Failed { attempts: u32, error: String },
+}
+
+/// How one alert got on with one target
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub target: String,
+    pub company_code: String,
+    pub outcome: DeliveryOutcome,
+}
+
+impl NotifyTarget {
+    pub fn new(notifier: Box<dyn Notifier>, target_config: &TargetConfig) -> NotifyTarget {
+        NotifyTarget {
+            notifier,
+            retries: target_config.retries,
+            retry_delay: Duration::from_millis(target_config.retry_delay_ms),
+            timeout: Duration::from_secs(target_config.timeout_secs),
+            dry_run: target_config.dry_run,
+        }
+    }
+
+    pub fn from_config(target_config: &TargetConfig) -> NotifyTarget {
+        NotifyTarget::new(notifier_for_target(&target_config.target), target_config)
+    }
+
+    pub fn describe(&self) -> String {
+        self.notifier.describe()
+    }
+
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    /// Keeps trying until it goes through or the retries run out, waiting longer each time
+    pub async fn deliver(&self, alert: &TriggeredAlert) -> DeliveryOutcome {
+        if self.dry_run {
+            let preview = self.notifier.preview(alert);
+            info!("Dry run, not sending to {}: {}", self.describe(), preview);
+            return DeliveryOutcome::DryRun(preview);
+        }
+        let mut delay = self.retry_delay;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let sent = match tokio::time::timeout(self.timeout, self.notifier.send(alert)).await {
+                Ok(sent) => sent,
+                Err(_) => Err(StockError::Network(format!("Gave up after {}s", self.timeout.as_secs()))),
+            };
+            match sent {
+                Ok(()) => return DeliveryOutcome::Sent { attempts },
+                Err(e) if attempts > self.retries => {
+                    warn!("Couldn't send the {} alert to {}: {}", alert.company_code, self.describe(), e);
+                    return DeliveryOutcome::Failed { attempts, error: e.to_string() };
+                }
+                Err(e) => {
+                    warn!("Try {} sending to {} failed, trying again in {}ms: {}", attempts, self.describe(), delay.as_millis(), e);
+                    tokio::time::delay_for(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+    }
+}
+
+/// Every target from the config, none if there isn't one
+pub fn load_targets(notify_config: &NotifyConfig) -> Vec<NotifyTarget> {
+    notify_config.targets.iter().map(NotifyTarget::from_config).collect()
+}
+
+/// Sends every alert to every target. A target that can't be reached doesn't stop the others.
+pub async fn notify_all(targets: &[NotifyTarget], alerts: &[TriggeredAlert]) -> Vec<Delivery> {
+    let mut deliveries = Vec::new();
+    for alert in alerts {
+        for target in targets {
+            deliveries.push(Delivery {
+                target: target.describe(),
+                company_code: alert.company_code.clone(),
+                outcome: target.deliver(alert).await,
+            });
+        }
+    }
+    deliveries
+}
+
+/// What `notify test` sends, so a target can be tried out without waiting for a real alert
+pub fn test_alert(company_code: &str) -> TriggeredAlert {
+    TriggeredAlert {
+        rule_id: 0,
+        company_code: company_code.to_string(),
+        condition: "test".to_string(),
+        price: Decimal::new(0, 2),
+        triggered_at: Utc::now().naive_utc(),
+        message: "This is a test alert from stockchecker".to_string(),
+        movement: None,
+    }
+}
+
+/// The JSON the webhook posts
+pub fn alert_payload(alert: &TriggeredAlert) -> Value {
+    json!({
+        "code": alert.company_code,
+        "price": alert.price.to_string(),
+        "rule": alert.condition,
+        "rule_id": alert.rule_id,
+        "message": alert.message,
+        "triggered_at": utc_string(alert.triggered_at),
+        "movement": alert.movement.map(|(moment, movement)| json!({
+            "since": moment.to_string(),
+            "absolute": movement.absolute.to_string(),
+            "percent": movement.percent.round_dp(2).to_string(),
+        })),
+    })
+}
+
+/// The same fields for hooks that can't read JSON easily; movement ones are empty when there's no movement
+pub fn alert_env(alert: &TriggeredAlert) -> Vec<(&'static str, String)> {
+    let (since, absolute, percent) = match alert.movement {
+        Some((moment, movement)) => (moment.to_string(), movement.absolute.to_string(), movement.percent.round_dp(2).to_string()),
+        None => (String::new(), String::new(), String::new()),
+    };
+    vec![
+        ("STOCKCHECKER_CODE", alert.company_code.clone()),
+        ("STOCKCHECKER_PRICE", alert.price.to_string()),
+        ("STOCKCHECKER_RULE", alert.condition.clone()),
+        ("STOCKCHECKER_RULE_ID", alert.rule_id.to_string()),
+        ("STOCKCHECKER_MESSAGE", alert.message.clone()),
+        ("STOCKCHECKER_TRIGGERED_AT", utc_string(alert.triggered_at)),
+        ("STOCKCHECKER_MOVEMENT_SINCE", since),
+        ("STOCKCHECKER_MOVEMENT", absolute),
+        ("STOCKCHECKER_MOVEMENT_PERCENT", percent),
+        ("STOCKCHECKER_JSON", alert_payload(alert).to_string()),
+    ]
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use chrono::NaiveDate;
+
+    use crate::share_price_model::{Movement, ShareMoment};
+
+    use super::*;
+
+    pub fn sample_alert() -> TriggeredAlert {
+        TriggeredAlert {
+            rule_id: 7,
+            company_code: "NPN".to_string(),
+            condition: "moves 5% yesterday".to_string(),
+            price: "2834.50".parse().unwrap(),
+            triggered_at: NaiveDate::from_ymd_opt(2020, 10, 16).unwrap().and_hms_opt(9, 30, 0).unwrap(),
+            message: "moved +6.12% since Yesterday, more than 5%".to_string(),
+            movement: Some((ShareMoment::Yesterday, Movement {
+                absolute: "163.50".parse().unwrap(),
+                percent: "6.1213".parse().unwrap(),
+            })),
+        }
+    }
+
+    pub fn target_config(target: Target, retries: u32) -> TargetConfig {
+        TargetConfig { target, retries, retry_delay_ms: 1, timeout_secs: 5, dry_run: false }
+    }
+
+    /// Fails until it has been tried `fails` times
+    struct FlakyNotifier {
+        fails: u32,
+        tries: AtomicU32,
+    }
+
+    #[async_trait]
+    impl Notifier for FlakyNotifier {
+        fn describe(&self) -> String {
+            "flaky".to_string()
+        }
+
+        fn preview(&self, alert: &TriggeredAlert) -> String {
+            alert.message.clone()
+        }
+
+        async fn send(&self, _alert: &TriggeredAlert) -> StockResult<()> {
+            if self.tries.fetch_add(1, Ordering::SeqCst) < self.fails {
+                Err(StockError::Network("not today".to_string()))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn flaky_target(fails: u32, retries: u32) -> NotifyTarget {
+        let config = target_config(Target::Command { program: "unused".to_string(), args: Vec::new() }, retries);
+        NotifyTarget::new(Box::new(FlakyNotifier { fails, tries: AtomicU32::new(0) }), &config)
+    }
+
+    #[test]
+    fn targets_read_from_json() {
+        let notify_config: NotifyConfig = serde_json::from_str(r#"{"targets": [
+            {"type": "webhook", "url": "http://localhost:8080/hook", "retries": 5},
+            {"type": "email", "server": "localhost:25", "from": "stocks@localhost", "to": ["me@localhost"], "dry_run": true},
+            {"type": "command", "program": "notify-send"}
+        ]}"#).unwrap();
+        assert_eq!(notify_config.targets[0].retries, 5);
+        assert_eq!(notify_config.targets[1].target.kind(), "email");
+        assert!(notify_config.targets[1].dry_run);
+        assert_eq!(notify_config.targets[2].target, Target::Command { program: "notify-send".to_string(), args: Vec::new() });
+        assert_eq!(notify_config.targets[2].retries, default_retries());
+    }
+
+    #[test]
+    fn payload_has_the_movement() {
+        let payload = alert_payload(&sample_alert());
+        assert_eq!(payload["code"], "NPN");
+        assert_eq!(payload["price"], "2834.50");
+        assert_eq!(payload["rule"], "moves 5% yesterday");
+        assert_eq!(payload["triggered_at"], "2020-10-16T09:30:00Z");
+        assert_eq!(payload["movement"]["since"], "Yesterday");
+        assert_eq!(payload["movement"]["percent"], "6.12");
+        assert!(alert_payload(&test_alert("NPN"))["movement"].is_null());
+    }
+
+    #[tokio::test]
+    async fn retries_until_it_goes_through() {
+        assert_eq!(flaky_target(2, 2).deliver(&sample_alert()).await, DeliveryOutcome::Sent { attempts: 3 });
+        match flaky_target(3, 2).deliver(&sample_alert()).await {
+            DeliveryOutcome::Failed { attempts, .. } => assert_eq!(attempts, 3),
+            outcome => panic!("expected a failure, got {:?}", outcome),
+        }
+    }
+
+    #[tokio::test]
+    async fn dry_run_sends_nothing() {
+        let mut target = flaky_target(100, 0);
+        target.set_dry_run(true);
+        assert_eq!(target.deliver(&sample_alert()).await, DeliveryOutcome::DryRun(sample_alert().message));
+    }
+}
@@ -0,0 +1,89 @@
+use std::process::Stdio;
+
+use async_trait::async_trait;
+use tokio::process::Command;
+
+use crate::alerts::TriggeredAlert;
+use crate::error::{StockError, StockResult};
+use crate::notifier::{alert_env, Notifier};
+
+/// Runs a program for each alert, passing it along in STOCKCHECKER_* environment variables.
+/// A non-zero exit counts as a failure.
+pub struct CommandNotifier {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandNotifier {
+    pub fn new(program: &str, args: &[String]) -> CommandNotifier {
+        CommandNotifier {
+            program: program.to_string(),
+            args: args.to_vec(),
+        }
+    }
+
+    fn command_line(&self) -> String {
+        std::iter::once(self.program.as_str())
+            .chain(self.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[async_trait]
+impl Notifier for CommandNotifier {
+    fn describe(&self) -> String {
+        format!("command {}", self.command_line())
+    }
+
+    fn preview(&self, alert: &TriggeredAlert) -> String {
+        let env: Vec<String> = alert_env(alert).into_iter()
+            .filter(|(name, _)| *name != "STOCKCHECKER_JSON")
+            .map(|(name, value)| format!("{}={:?}", name, value))
+            .collect();
+        format!("{} {}", env.join(" "), self.command_line())
+    }
+
+    async fn send(&self, alert: &TriggeredAlert) -> StockResult<()> {
+        //output is kept off the terminal so it can't scribble over watch mode
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .envs(alert_env(alert))
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output().await
+            .map_err(|e| StockError::Config(format!("Couldn't run {}: {}", self.program, e)))?;
+        if !output.status.success() {
+            return Err(StockError::Config(format!("{} exited with {}: {}",
+                                                  self.program, output.status, String::from_utf8_lossy(&output.stderr).trim())));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::notifier::tests::sample_alert;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn alert_is_in_the_environment() {
+        let out_path = std::env::temp_dir().join(format!("stockchecker-hook-{}.txt", std::process::id()));
+        let script = r#"printf '%s|%s|%s|%s' "$STOCKCHECKER_CODE" "$STOCKCHECKER_PRICE" "$STOCKCHECKER_RULE" "$STOCKCHECKER_MOVEMENT_PERCENT" > "$0""#;
+        let args = vec!["-c".to_string(), script.to_string(), out_path.display().to_string()];
+        CommandNotifier::new("sh", &args).send(&sample_alert()).await.unwrap();
+
+        let written = std::fs::read_to_string(&out_path).unwrap();
+        std::fs::remove_file(&out_path).unwrap();
+        assert_eq!(written, "NPN|2834.50|moves 5% yesterday|6.12");
+    }
+
+    #[tokio::test]
+    async fn failing_command_is_an_error() {
+        let args = vec!["-c".to_string(), "echo nope >&2; exit 3".to_string()];
+        let e = CommandNotifier::new("sh", &args).send(&sample_alert()).await.unwrap_err();
+        assert!(e.to_string().contains("nope"));
+        assert!(CommandNotifier::new("no-such-program-here", &[]).send(&sample_alert()).await.is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::alerts::TriggeredAlert;
+use crate::error::{StockError, StockResult};
+use crate::notifier::Notifier;
+
+/// Mails each alert through an SMTP relay. Just the plain protocol, no TLS or login,
+/// so point it at something local like postfix or an ssh tunnel.
+pub struct EmailNotifier {
+    server: String,
+    from: String,
+    to: Vec<String>,
+}
+
+impl EmailNotifier {
+    pub fn new(server: &str, from: &str, to: &[String]) -> EmailNotifier {
+        EmailNotifier {
+            server: server.to_string(),
+            from: from.to_string(),
+            to: to.to_vec(),
+        }
+    }
+
+    fn subject(&self, alert: &TriggeredAlert) -> String {
+        format!("stockchecker: {} {}", alert.company_code, alert.condition)
+    }
+
+    fn body(&self, alert: &TriggeredAlert) -> String {
+        let mut body = vec![
+            alert.message.clone(),
+            String::new(),
+            format!("Code:      {}", alert.company_code),
+            format!("Price:     {}", alert.price),
+            format!("Rule:      {} ({})", alert.condition, alert.rule_id),
+            format!("Triggered: {} UTC", alert.triggered_at.format("%Y-%m-%d %H:%M:%S")),
+        ];
+        if let Some((moment, movement)) = alert.movement {
+            body.push(format!("Movement:  {} ({}%) since {}", movement.absolute, movement.percent.round_dp(2), moment));
+        }
+        body.join("\r\n")
+    }
+
+    /// Headers and body, lines starting with a dot doubled up so they don't end the DATA early
+    fn message(&self, alert: &TriggeredAlert) -> String {
+        let headers = [
+            format!("From: <{}>", self.from),
+            format!("To: {}", self.to.iter().map(|to| format!("<{}>", to)).collect::<Vec<_>>().join(", ")),
+            format!("Subject: {}", self.subject(alert)),
+            format!("Date: {}", Utc::now().to_rfc2822()),
+            "Content-Type: text/plain; charset=utf-8".to_string(),
+        ];
+        let body: Vec<String> = self.body(alert).split("\r\n")
+            .map(|line| if line.starts_with('.') { format!(".{}", line) } else { line.to_string() })
+            .collect();
+        format!("{}\r\n\r\n{}\r\n", headers.join("\r\n"), body.join("\r\n"))
+    }
+}
+
+/// One SMTP conversation, every command checked against the reply code it should get
+struct SmtpSession<'a> {
+    server: &'a str,
+    stream: BufReader<TcpStream>,
+}
+
+impl<'a> SmtpSession<'a> {
+    async fn connect(server: &'a str) -> StockResult<SmtpSession<'a>> {
+        let stream = TcpStream::connect(server).await
+            .map_err(|e| StockError::Network(format!("Couldn't reach mail server {}: {}", server, e)))?;
+        let mut session = SmtpSession { server, stream: BufReader::new(stream) };
+        session.expect(220).await?;
+        Ok(session)
+    }
+
+    async fn command(&mut self, line: &str, expected: u16) -> StockResult<()> {
+        self.stream.get_mut().write_all(format!("{}\r\n", line).as_bytes()).await
+            .map_err(|e| StockError::Network(format!("Lost mail server {}: {}", self.server, e)))?;
+        self.expect(expected).await
+    }
+
+    /// Replies can run over several lines, "250-" on all but the last
+    async fn expect(&mut self, expected: u16) -> StockResult<()> {
+        loop {
+            let mut reply = String::new();
+            let read = self.stream.read_line(&mut reply).await
+                .map_err(|e| StockError::Network(format!("Lost mail server {}: {}", self.server, e)))?;
+            if read == 0 {
+                return Err(StockError::Network(format!("Mail server {} hung up", self.server)));
+            }
+            if reply.get(3..4) == Some("-") {
+                continue;
+            }
+            return match reply.get(..3).and_then(|code| code.parse::<u16>().ok()) {
+                Some(code) if code == expected => Ok(()),
+                _ => Err(StockError::Network(format!("Mail server {} said {}", self.server, reply.trim()))),
+            };
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    fn describe(&self) -> String {
+        format!("email {} via {}", self.to.join(","), self.server)
+    }
+
+    fn preview(&self, alert: &TriggeredAlert) -> String {
+        format!("mail to {}: {}", self.to.join(","), self.subject(alert))
+    }
+
+    async fn send(&self, alert: &TriggeredAlert) -> StockResult<()> {
+        let mut session = SmtpSession::connect(&self.server).await?;
+        session.command("EHLO stockchecker", 250).await?;
+        session.command(&format!("MAIL FROM:<{}>", self.from), 250).await?;
+        for to in &self.to {
+            session.command(&format!("RCPT TO:<{}>", to), 250).await?;
+        }
+        session.command("DATA", 354).await?;
+        session.command(&format!("{}.", self.message(alert)), 250).await?;
+        session.command("QUIT", 221).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    use crate::notifier::tests::sample_alert;
+
+    use super::*;
+
+    /// Just enough of a mail server to take one message, handing back everything it was told.
+    /// `reject_rcpt` turns recipients away.
+    async fn stub_mail_server(reject_rcpt: bool) -> (String, oneshot::Receiver<Vec<String>>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let (conversation_tx, conversation) = oneshot::channel();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut socket = BufReader::new(socket);
+            let mut heard = Vec::new();
+            let mut in_data = false;
+            socket.get_mut().write_all(b"220 stub ready\r\n").await.unwrap();
+            loop {
+                let mut line = String::new();
+                if socket.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                let line = line.trim_end_matches("\r\n").to_string();
+                heard.push(line.clone());
+                let reply: &[u8] = if in_data {
+                    if line != "." {
+                        continue;
+                    }
+                    in_data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-stub\r\n250 8BITMIME\r\n"
+                } else if line.starts_with("RCPT") && reject_rcpt {
+                    b"550 no such user\r\n"
+                } else if line == "DATA" {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                } else if line == "QUIT" {
+                    socket.get_mut().write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                socket.get_mut().write_all(reply).await.unwrap();
+            }
+            conversation_tx.send(heard).unwrap();
+        });
+        (server, conversation)
+    }
+
+    #[tokio::test]
+    async fn mails_the_alert() {
+        let (server, conversation) = stub_mail_server(false).await;
+        let to = vec!["me@localhost".to_string(), "you@localhost".to_string()];
+        EmailNotifier::new(&server, "stocks@localhost", &to).send(&sample_alert()).await.unwrap();
+
+        let heard = conversation.await.unwrap();
+        assert!(heard.contains(&"MAIL FROM:<stocks@localhost>".to_string()));
+        assert!(heard.contains(&"RCPT TO:<you@localhost>".to_string()));
+        assert!(heard.contains(&"Subject: stockchecker: NPN moves 5% yesterday".to_string()));
+        assert!(heard.contains(&"Price:     2834.50".to_string()));
+        assert!(heard.contains(&"Movement:  163.50 (6.12%) since Yesterday".to_string()));
+        assert_eq!(heard.last().unwrap(), "QUIT");
+    }
+
+    #[tokio::test]
+    async fn rejected_recipient_is_an_error() {
+        let (server, _conversation) = stub_mail_server(true).await;
+        let e = EmailNotifier::new(&server, "stocks@localhost", &["nobody@localhost".to_string()])
+            .send(&sample_alert()).await
+            .unwrap_err();
+        assert!(e.to_string().contains("550 no such user"));
+    }
+}
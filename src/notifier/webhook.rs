@@ -0,0 +1,117 @@
+use async_trait::async_trait;
+
+use crate::alerts::TriggeredAlert;
+use crate::error::{StockError, StockResult};
+use crate::notifier::{alert_payload, Notifier};
+
+/// POSTs each alert as JSON, anything but a 2xx counts as a failure
+pub struct WebhookNotifier {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str) -> WebhookNotifier {
+        WebhookNotifier {
+            url: url.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn describe(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn preview(&self, alert: &TriggeredAlert) -> String {
+        format!("POST {} {}", self.url, alert_payload(alert))
+    }
+
+    async fn send(&self, alert: &TriggeredAlert) -> StockResult<()> {
+        let res = self.client.post(&self.url)
+            .json(&alert_payload(alert))
+            .send().await
+            .map_err(|e| StockError::Network(format!("Couldn't post to {}: {}", self.url, e)))?;
+        if !res.status().is_success() {
+            return Err(StockError::Network(format!("{} answered {}", self.url, res.status())));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use crate::notifier::{DeliveryOutcome, NotifyTarget, Target};
+    use crate::notifier::tests::{sample_alert, target_config};
+
+    use super::*;
+
+    /// Answers each request with the next status, handing back the bodies it was sent
+    async fn stub_server(statuses: Vec<u16>) -> (String, mpsc::UnboundedReceiver<String>) {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let (bodies, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let request = read_request(&mut socket).await;
+                let body = request.split_once("\r\n\r\n").map(|(_, body)| body.to_string()).unwrap_or_default();
+                bodies.send(body).unwrap();
+                let response = format!("HTTP/1.1 {} Stub\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    /// Headers, then as much body as Content-Length says
+    async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let read = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..read]);
+            let text = String::from_utf8_lossy(&request).to_string();
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let content_length = text[..header_end].lines()
+                    .find_map(|line| {
+                        let lowered = line.to_lowercase();
+                        lowered.strip_prefix("content-length:").map(|length| length.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if request.len() >= header_end + 4 + content_length || read == 0 {
+                    return text;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn posts_the_alert_as_json() {
+        let (url, mut received) = stub_server(vec![200]).await;
+        WebhookNotifier::new(&url).send(&sample_alert()).await.unwrap();
+
+        let payload: Value = serde_json::from_str(&received.recv().await.unwrap()).unwrap();
+        assert_eq!(payload["code"], "NPN");
+        assert_eq!(payload["price"], "2834.50");
+        assert_eq!(payload["rule"], "moves 5% yesterday");
+        assert_eq!(payload["movement"]["absolute"], "163.50");
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_retried() {
+        let (url, mut received) = stub_server(vec![500, 503, 200]).await;
+        let target = NotifyTarget::from_config(&target_config(Target::Webhook { url }, 2));
+        assert_eq!(target.deliver(&sample_alert()).await, DeliveryOutcome::Sent { attempts: 3 });
+        for _ in 0..3 {
+            assert!(received.recv().await.unwrap().contains("NPN"));
+        }
+    }
+}
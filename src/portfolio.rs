@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::Zero;
+
+use crate::share_price_model::{CurrencyCode, Share};
+
+/// One purchase of a ticker, kept as bought
+#[derive(Debug, Clone, PartialEq)]
+pub struct Holding {
+    /// Nothing until the store has given it one
+    pub id: Option<i64>,
+    pub company_code: String,
+    pub quantity: Decimal,
+    pub buy_price: Decimal,
+    pub buy_date: NaiveDate,
+    pub fees: Decimal,
+}
+
+impl Holding {
+    /// What it cost to get in, fees included
+    pub fn cost_basis(&self) -> Decimal {
+        self.quantity * self.buy_price + self.fees
+    }
+}
+
+/// Everything held in one ticker, valued at the latest price we could find for it.
+/// The buy prices are taken to be in the currency the ticker is quoted in.
+pub struct Position {
+    pub company_code: String,
+    pub quantity: Decimal,
+    pub cost_basis: Decimal,
+    pub current: Option<Share>,
+}
+
+impl Position {
+    /// What the current price was quoted in, none without a price or when the quote never said
+    pub fn currency(&self) -> Option<&CurrencyCode> {
+        self.current.as_ref().and_then(|share| share.price.currency.as_ref())
+    }
+
+    pub fn market_value(&self) -> Option<Decimal> {
+        self.current.as_ref().map(|share| share.price.amount * self.quantity)
+    }
+
+    pub fn gain(&self) -> Option<Decimal> {
+        self.market_value().map(|market_value| market_value - self.cost_basis)
+    }
+
+    pub fn gain_percent(&self) -> Option<Decimal> {
+        self.gain().map(|gain| percent_of(gain, self.cost_basis))
+    }
+
+    pub fn average_cost(&self) -> Decimal {
+        if self.quantity.is_zero() {
+            Decimal::zero()
+        } else {
+            self.cost_basis / self.quantity
+        }
+    }
+}
+
+/// What the priced positions in one currency add up to
+#[derive(Debug, PartialEq)]
+pub struct CurrencyTotal {
+    pub currency: Option<CurrencyCode>,
+    /// Only counts what we could price, so the gain isn't thrown out by a missing quote
+    pub priced_cost: Decimal,
+    pub market_value: Decimal,
+}
+
+impl CurrencyTotal {
+    pub fn gain(&self) -> Decimal {
+        self.market_value - self.priced_cost
+    }
+
+    pub fn gain_percent(&self) -> Decimal {
+        percent_of(self.gain(), self.priced_cost)
+    }
+}
+
+/// Positions by ticker, with totals over the ones we have a price for
+pub struct Portfolio {
+    pub positions: Vec<Position>,
+}
+
+impl Portfolio {
+    /// Lots in the same ticker are rolled up into one position, in the order the tickers first appear
+    pub fn value(holdings: &[Holding], mut current_prices: HashMap<String, Share>) -> Portfolio {
+        let mut positions: Vec<Position> = Vec::new();
+        for holding in holdings {
+            match positions.iter_mut().find(|position| position.company_code == holding.company_code) {
+                Some(position) => {
+                    position.quantity += holding.quantity;
+                    position.cost_basis += holding.cost_basis();
+                }
+                None => positions.push(Position {
+                    company_code: holding.company_code.clone(),
+                    quantity: holding.quantity,
+                    cost_basis: holding.cost_basis(),
+                    current: current_prices.remove(&holding.company_code),
+                }),
+            }
+        }
+        Portfolio { positions }
+    }
+
+    /// A total for each currency the prices came in, in the order they first appear.
+    /// Money in different currencies is never added together.
+    pub fn totals(&self) -> Vec<CurrencyTotal> {
+        let mut totals: Vec<CurrencyTotal> = Vec::new();
+        for position in &self.positions {
+            let market_value = match position.market_value() {
+                Some(market_value) => market_value,
+                None => continue,
+            };
+            let currency = position.currency();
+            match totals.iter_mut().find(|total| total.currency.as_ref() == currency) {
+                Some(total) => {
+                    total.priced_cost += position.cost_basis;
+                    total.market_value += market_value;
+                }
+                None => totals.push(CurrencyTotal {
+                    currency: currency.cloned(),
+                    priced_cost: position.cost_basis,
+                    market_value,
+                }),
+            }
+        }
+        totals
+    }
+
+    /// Share of the market value of everything priced in the same currency, as a percent
+    pub fn weight(&self, position: &Position) -> Option<Decimal> {
+        let market_value = position.market_value()?;
+        self.totals().iter()
+            .find(|total| total.currency.as_ref() == position.currency())
+            .map(|total| percent_of(market_value, total.market_value))
+    }
+}
+
+fn percent_of(part: Decimal, whole: Decimal) -> Decimal {
+    if whole.is_zero() {
+        Decimal::zero()
+    } else {
+        part / whole * Decimal::from(100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use chrono::Utc;
+    use chrono_tz::Tz;
+
+    use crate::share_price_model::Price;
+
+    use super::*;
+
+    fn holding(company_code: &str, quantity: &str, buy_price: &str, fees: &str) -> Holding {
+        Holding {
+            id: None,
+            company_code: company_code.to_string(),
+            quantity: Decimal::from_str(quantity).unwrap(),
+            buy_price: Decimal::from_str(buy_price).unwrap(),
+            buy_date: NaiveDate::from_ymd_opt(2020, 1, 2).unwrap(),
+            fees: Decimal::from_str(fees).unwrap(),
+        }
+    }
+
+    fn share(company_code: &str, price: &str) -> (String, Share) {
+        priced_share(company_code, price, None)
+    }
+
+    fn priced_share(company_code: &str, price: &str, currency: Option<&str>) -> (String, Share) {
+        (company_code.to_string(), Share {
+            company_code: company_code.to_string(),
+            price: Price::in_currency(Decimal::from_str(price).unwrap(), currency.map(|currency| currency.parse().unwrap())),
+            price_date: Utc::now().naive_utc(),
+            exchange_tz: Tz::UTC,
+            source: None,
+        })
+    }
+
+    #[test]
+    fn lots_roll_up_into_positions() {
+        let holdings = [
+            holding("NPN", "10", "100.00", "5.00"),
+            holding("SOL", "4", "50.00", "0"),
+            holding("NPN", "10", "120.00", "5.00"),
+        ];
+        let portfolio = Portfolio::value(&holdings, vec![share("NPN", "150.00"), share("SOL", "25.00")].into_iter().collect());
+
+        let npn = &portfolio.positions[0];
+        assert_eq!(npn.quantity, Decimal::from(20));
+        assert_eq!(npn.cost_basis, Decimal::from(2210));
+        assert_eq!(npn.average_cost(), Decimal::from_str("110.5").unwrap());
+        assert_eq!(npn.market_value(), Some(Decimal::from(3000)));
+        assert_eq!(npn.gain(), Some(Decimal::from(790)));
+
+        let sol = &portfolio.positions[1];
+        assert_eq!(sol.gain_percent(), Some(Decimal::from(-50)));
+
+        let totals = portfolio.totals();
+        assert_eq!(totals.len(), 1);
+        assert_eq!(totals[0].market_value, Decimal::from(3100));
+        assert_eq!(totals[0].gain(), Decimal::from(690));
+        assert_eq!(portfolio.weight(sol).unwrap().round_dp(2), Decimal::from_str("3.23").unwrap());
+    }
+
+    #[test]
+    fn unpriced_positions_stay_out_of_the_totals() {
+        let holdings = [holding("NPN", "1", "100", "0"), holding("GONE", "1", "100", "0")];
+        let portfolio = Portfolio::value(&holdings, vec![share("NPN", "110")].into_iter().collect());
+
+        assert_eq!(portfolio.positions[1].market_value(), None);
+        assert_eq!(portfolio.weight(&portfolio.positions[1]), None);
+        let totals = portfolio.totals();
+        assert_eq!(totals[0].priced_cost, Decimal::from(100));
+        assert_eq!(totals[0].gain_percent(), Decimal::from(10));
+    }
+
+    #[test]
+    fn currencies_are_totalled_apart() {
+        let holdings = [holding("NPN", "10", "100", "0"), holding("AAPL", "2", "100", "0"), holding("VOD", "100", "120", "0"), holding("SOL", "10", "50", "0")];
+        let current_prices = vec![priced_share("NPN", "150", Some("ZAR")), priced_share("AAPL", "110", Some("USD")),
+                                  priced_share("VOD", "110", Some("GBX")), priced_share("SOL", "50", Some("ZAR"))];
+        let portfolio = Portfolio::value(&holdings, current_prices.into_iter().collect());
+
+        let totals: Vec<_> = portfolio.totals().into_iter()
+            .map(|total| (total.currency.as_ref().unwrap().to_string(), total.market_value, total.gain()))
+            .collect();
+        assert_eq!(totals, vec![
+            ("ZAR".to_string(), Decimal::from(2000), Decimal::from(500)),
+            ("USD".to_string(), Decimal::from(220), Decimal::from(20)),
+            ("GBX".to_string(), Decimal::from(11000), Decimal::from(-1000)),
+        ]);
+        assert_eq!(portfolio.weight(&portfolio.positions[0]), Some(Decimal::from(75)));
+        assert_eq!(portfolio.weight(&portfolio.positions[1]), Some(Decimal::from(100)));
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use chrono_tz::Tz;
+use futures::stream::{self, StreamExt};
+
+use crate::error::{StockError, StockResult};
+use crate::share_price_model::Price;
+
+pub mod google;
+pub mod replay;
+
+pub const SOURCE_NAMES: [&str; 2] = [google::SOURCE_NAME, replay::SOURCE_NAME];
+
+/// A single price as handed back by a `PriceSource`, before it gets turned into a `Share`.
+/// No price means the source had nothing for that code, one it couldn't read is an error instead.
+#[derive(Debug, Clone)]
+pub struct Quote {
+    pub company_code: String,
+    pub price: Option<Price>,
+    /// UTC
+    pub quote_date: NaiveDateTime,
+    pub exchange_tz: Tz,
+    /// Name of the source that gave it
+    pub source: &'static str,
+}
+
+/// Somewhere we can go and get the current price of a company
+#[async_trait]
+pub trait PriceSource: Send + Sync {
+    fn name(&self) -> &'static str;
+    async fn fetch_quote(&self, company_code: &str) -> StockResult<Quote>;
+}
+
+/// Pick the price source matching the name passed on the command line.
+/// Only the replay source cares about the fixtures directory.
+pub fn source_for_name(source_name: &str, fixtures_dir: &str) -> StockResult<Box<dyn PriceSource>> {
+    match source_name {
+        google::SOURCE_NAME => Ok(Box::new(google::GoogleSource::new())),
+        replay::SOURCE_NAME => Ok(Box::new(replay::ReplaySource::new(fixtures_dir))),
+        _ => Err(StockError::Config(format!("Unknown price source '{}'", source_name))),
+    }
+}
+
+/// How hard we lean on a source when fetching a whole watchlist
+#[derive(Debug, Clone, Copy)]
+pub struct FetchOptions {
+    pub concurrency: usize,
+    pub timeout: Duration,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        FetchOptions {
+            concurrency: 8,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Fetch all the codes at once, at most `concurrency` in flight, each one given up on after `timeout`.
+/// Results come back in the same order as the codes went in.
+pub async fn fetch_quotes(source: &dyn PriceSource, company_codes: &[&str], fetch_options: FetchOptions) -> Vec<StockResult<Quote>> {
+    let mut quotes = stream::iter(company_codes.iter().enumerate())
+        .map(|(idx, company_code)| async move {
+            let quote = match tokio::time::timeout(fetch_options.timeout, source.fetch_quote(company_code)).await {
+                Ok(quote) => quote,
+                Err(_) => Err(StockError::Network(format!("Timed out fetching {} from {} after {}s", company_code, source.name(), fetch_options.timeout.as_secs()))),
+            };
+            (idx, quote)
+        })
+        .buffer_unordered(fetch_options.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+    quotes.sort_by_key(|(idx, _)| *idx);
+    quotes.into_iter().map(|(_, quote)| quote).collect()
+}
@@ -0,0 +1,120 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use chrono_tz::Tz;
+use regex::Regex;
+use select::document::Document;
+use select::predicate::Name;
+
+use crate::error::{StockError, StockResult};
+use crate::price_source::{PriceSource, Quote};
+use crate::share_price_model::{plain_amount, CurrencyCode, Price};
+
+pub const SOURCE_NAME: &str = "google";
+
+/// Scrapes the price out of the little finance box google puts at the top of a search
+pub struct GoogleSource {
+    starts_with_digits: Regex,
+    zone_abbreviation: Regex,
+    currency: Regex,
+}
+
+impl GoogleSource {
+    pub fn new() -> GoogleSource {
+        GoogleSource {
+            starts_with_digits: Regex::new(r"^(\d[\d\s.,]*[.,]\d+)\s").unwrap(),
+            zone_abbreviation: Regex::new(r"\b((?:UTC|GMT)[+-]\d{1,2}(?::\d{2})?|SAST|EST|EDT|GMT|BST|CET|CEST|JST|HKT|AEST|AEDT|IST)\b").unwrap(),
+            currency: Regex::new(r"Currency in ([A-Z]{3})\b").unwrap(),
+        }
+    }
+
+    /// The text of the finance box, starting with the price
+    fn find_price_box(&self, body: &str) -> Option<String> {
+        let search_doc = Document::from(body);
+        for div in search_doc.find(Name("div")) {
+            let txt = div.text();
+            if txt.contains("Currency in ") && self.starts_with_digits.is_match(&txt) {
+                return Some(txt);
+            }
+        }
+        None
+    }
+
+    /// Dig the price and its "Currency in ..." out of the search result page, nothing if there's no price box on it
+    pub fn extract_price(&self, body: &str) -> StockResult<Option<Price>> {
+        let price_box = match self.find_price_box(body) {
+            Some(price_box) => price_box,
+            None => return Ok(None),
+        };
+        let raw_price = match self.starts_with_digits.captures(&price_box) {
+            Some(captures) => captures[1].to_string(),
+            None => return Ok(None),
+        };
+        let currency = self.currency.captures(&price_box)
+            .and_then(|captures| captures[1].parse::<CurrencyCode>().ok());
+        let amount = plain_amount(&raw_price)
+            .ok_or_else(|| StockError::Parse(format!("Couldn't read '{}' as a price", raw_price)))?;
+        Ok(Some(Price { currency, ..Price::parse(&amount)? }))
+    }
+
+    /// Work out the exchange's zone from the abbreviation on the quote time, UTC if there isn't one we know
+    pub fn extract_exchange_tz(&self, body: &str) -> Tz {
+        self.find_price_box(body)
+            .and_then(|price_box| self.zone_abbreviation.captures(&price_box)
+                .map(|captures| zone_for_abbreviation(&captures[1])))
+            .unwrap_or(Tz::UTC)
+    }
+}
+
+fn zone_for_abbreviation(abbreviation: &str) -> Tz {
+    match abbreviation {
+        "SAST" => Tz::Africa__Johannesburg,
+        "EST" | "EDT" => Tz::America__New_York,
+        "GMT" | "BST" => Tz::Europe__London,
+        "CET" | "CEST" => Tz::Europe__Paris,
+        "JST" => Tz::Asia__Tokyo,
+        "HKT" => Tz::Asia__Hong_Kong,
+        "AEST" | "AEDT" => Tz::Australia__Sydney,
+        "IST" => Tz::Asia__Kolkata,
+        offset => zone_for_offset(offset).unwrap_or(Tz::UTC),
+    }
+}
+
+/// "UTC+2" or "GMT-5" as a fixed zone. The Etc zones have the sign the other way round, Etc/GMT-2 is two hours ahead.
+/// Only whole hours have one, "UTC+5:30" doesn't.
+fn zone_for_offset(offset: &str) -> Option<Tz> {
+    let hours = offset.strip_prefix("UTC").or_else(|| offset.strip_prefix("GMT"))?;
+    let hours = hours.strip_suffix(":00").unwrap_or(hours);
+    let (sign, hours) = hours.split_at(1);
+    let hours: u32 = hours.parse().ok()?;
+    let etc_sign = if sign == "+" { "-" } else { "+" };
+    match hours {
+        0 => Some(Tz::UTC),
+        _ => format!("Etc/GMT{}{}", etc_sign, hours).parse().ok(),
+    }
+}
+
+impl Default for GoogleSource {
+    fn default() -> Self {
+        GoogleSource::new()
+    }
+}
+
+#[async_trait]
+impl PriceSource for GoogleSource {
+    fn name(&self) -> &'static str {
+        SOURCE_NAME
+    }
+
+    async fn fetch_quote(&self, company_code: &str) -> StockResult<Quote> {
+        let res = reqwest::get(&format!("https://www.google.com/search?hl=en&q=share+price+{}", company_code)).await
+            .map_err(|e| StockError::Network(format!("Couldn't fetch {} from google: {}", company_code, e)))?;
+        let body = res.text().await?;
+        Ok(Quote {
+            company_code: company_code.to_string(),
+            price: self.extract_price(&body)?,
+            quote_date: Utc::now().naive_utc(),
+            source: SOURCE_NAME,
+            exchange_tz: self.extract_exchange_tz(&body),
+        })
+    }
+}
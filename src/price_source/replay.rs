@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use crate::error::{StockError, StockResult};
+use crate::price_source::{PriceSource, Quote};
+use crate::price_source::google::GoogleSource;
+
+pub const SOURCE_NAME: &str = "replay";
+
+/// Plays back saved google search pages from a fixtures directory, one `<CODE>.html` per company,
+/// so we can scrape without going anywhere near the network
+pub struct ReplaySource {
+    fixtures_dir: PathBuf,
+    scraper: GoogleSource,
+}
+
+impl ReplaySource {
+    pub fn new(fixtures_dir: &str) -> ReplaySource {
+        ReplaySource {
+            fixtures_dir: PathBuf::from(fixtures_dir),
+            scraper: GoogleSource::new(),
+        }
+    }
+
+    fn fixture_path(&self, company_code: &str) -> PathBuf {
+        self.fixtures_dir.join(format!("{}.html", company_code))
+    }
+}
+
+#[async_trait]
+impl PriceSource for ReplaySource {
+    fn name(&self) -> &'static str {
+        SOURCE_NAME
+    }
+
+    async fn fetch_quote(&self, company_code: &str) -> StockResult<Quote> {
+        let fixture_path = self.fixture_path(company_code);
+        let body = tokio::fs::read_to_string(&fixture_path).await
+            .map_err(|e| StockError::Config(format!("No fixture at {}: {}", fixture_path.display(), e)))?;
+        Ok(Quote {
+            company_code: company_code.to_string(),
+            price: self.scraper.extract_price(&body)?,
+            quote_date: Utc::now().naive_utc(),
+            source: SOURCE_NAME,
+            exchange_tz: self.scraper.extract_exchange_tz(&body),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono_tz::Tz;
+
+    use crate::price_source::{fetch_quotes, FetchOptions};
+
+    use super::*;
+
+    fn fixture_source() -> ReplaySource {
+        ReplaySource::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures"))
+    }
+
+    async fn replay_price(company_code: &str) -> Option<String> {
+        fixture_source().fetch_quote(company_code).await.unwrap().price.map(|price| price.to_string())
+    }
+
+    #[tokio::test]
+    async fn comma_decimal_with_space_thousands() {
+        assert_eq!(replay_price("NPN").await.as_deref(), Some("2834.50"));
+    }
+
+    #[tokio::test]
+    async fn comma_decimal_with_narrow_no_break_space_thousands() {
+        assert_eq!(replay_price("MC").await.as_deref(), Some("1234.80"));
+    }
+
+    #[tokio::test]
+    async fn comma_decimal_without_thousands() {
+        assert_eq!(replay_price("SOL").await.as_deref(), Some("118.02"));
+    }
+
+    #[tokio::test]
+    async fn dot_decimal_with_comma_thousands() {
+        assert_eq!(replay_price("BKNG").await.as_deref(), Some("2345.67"));
+    }
+
+    #[tokio::test]
+    async fn dot_decimal_without_thousands() {
+        assert_eq!(replay_price("AAPL").await.as_deref(), Some("119.02"));
+    }
+
+    #[tokio::test]
+    async fn comma_thousands_without_decimal() {
+        assert_eq!(replay_price("7203").await.as_deref(), Some("2876"));
+    }
+
+    #[tokio::test]
+    async fn page_without_price_box_gives_no_price() {
+        assert_eq!(replay_price("NOPRICE").await, None);
+    }
+
+    #[tokio::test]
+    async fn currency_comes_from_the_price_box() {
+        let source = fixture_source();
+        let currency = |quote: Quote| quote.price.and_then(|price| price.currency).map(|currency| currency.to_string());
+        assert_eq!(currency(source.fetch_quote("NPN").await.unwrap()).as_deref(), Some("ZAR"));
+        assert_eq!(currency(source.fetch_quote("AAPL").await.unwrap()).as_deref(), Some("USD"));
+        assert_eq!(currency(source.fetch_quote("MC").await.unwrap()).as_deref(), Some("EUR"));
+    }
+
+    #[tokio::test]
+    async fn exchange_zone_comes_from_quote_time() {
+        let source = fixture_source();
+        assert_eq!(source.fetch_quote("NPN").await.unwrap().exchange_tz, Tz::Africa__Johannesburg);
+        assert_eq!(source.fetch_quote("AAPL").await.unwrap().exchange_tz, Tz::America__New_York);
+        assert_eq!(source.fetch_quote("MC").await.unwrap().exchange_tz, Tz::Etc__GMTMinus2);
+    }
+
+    #[tokio::test]
+    async fn missing_fixture_is_an_error() {
+        assert!(fixture_source().fetch_quote("NOSUCHCODE").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fetch_quotes_keeps_input_order() {
+        let source = fixture_source();
+        let fetch_options = FetchOptions { concurrency: 2, timeout: Duration::from_secs(5) };
+        let quotes = fetch_quotes(&source, &["SOL", "NOSUCHCODE", "NPN", "AAPL"], fetch_options).await;
+        let codes: Vec<_> = quotes.iter()
+            .map(|quote| quote.as_ref().map(|q| q.company_code.as_str()).ok())
+            .collect();
+        assert_eq!(codes, vec![Some("SOL"), None, Some("NPN"), Some("AAPL")]);
+    }
+}
@@ -0,0 +1,368 @@
+use std::str::FromStr;
+
+use chrono::NaiveDateTime;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::Zero;
+use serde::{Serialize, Serializer};
+use serde::ser::Error;
+use serde_json::value::RawValue;
+
+use crate::error::{StockError, StockResult};
+use crate::share_price_model::{Movement, ShareMoment, ShareTimeline};
+
+pub const FORMAT_NAMES: [&str; 6] = ["table", "json", "csv", "tsv", "markdown", "html"];
+
+/// Percent movements are kept to this many places, plenty to tell two prices apart
+const PERCENT_PLACES: u32 = 4;
+const READABLE_DATE_FMT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// How the quote table gets written out. Everything but `Table` is for other programs to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+    Tsv,
+    Markdown,
+    Html,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "html" => Ok(OutputFormat::Html),
+            _ => Err(format!("'{}' isn't a format, try one of {}", s, FORMAT_NAMES.join(", "))),
+        }
+    }
+}
+
+/// One line of the quote table, numbers left as numbers
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QuoteRow {
+    pub code: String,
+    #[serde(serialize_with = "decimal_number")]
+    pub price: Decimal,
+    pub currency: Option<String>,
+    /// UTC
+    #[serde(serialize_with = "utc_timestamp")]
+    pub time: NaiveDateTime,
+    pub history: Vec<HistoricQuote>,
+}
+
+/// The price at one of the moments we compare against, all empty if there's nothing saved that far back.
+/// The movement is empty too when the old price was in another currency.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoricQuote {
+    #[serde(serialize_with = "display_string")]
+    pub moment: ShareMoment,
+    #[serde(serialize_with = "optional_decimal_number")]
+    pub price: Option<Decimal>,
+    pub currency: Option<String>,
+    #[serde(serialize_with = "optional_utc_timestamp")]
+    pub date: Option<NaiveDateTime>,
+    #[serde(serialize_with = "optional_decimal_number")]
+    pub movement: Option<Decimal>,
+    #[serde(serialize_with = "optional_decimal_number")]
+    pub movement_percent: Option<Decimal>,
+}
+
+impl QuoteRow {
+    pub fn new(share_timeline: &ShareTimeline, moments: &[ShareMoment]) -> QuoteRow {
+        let share = &share_timeline.share;
+        let history = moments.iter()
+            .map(|moment| match share_timeline.share_history.get(moment) {
+                Some(share_history) => {
+                    let movement = Movement::between(share_history, share);
+                    HistoricQuote {
+                        moment: *moment,
+                        price: Some(share_history.price.shown_amount()),
+                        currency: share_history.price.currency.as_ref().map(ToString::to_string),
+                        date: Some(share_history.price_date),
+                        movement: movement.map(|movement| share.price.shown(movement.absolute)),
+                        movement_percent: movement.map(|movement| movement.percent.round_dp(PERCENT_PLACES)),
+                    }
+                }
+                None => HistoricQuote { moment: *moment, price: None, currency: None, date: None, movement: None, movement_percent: None },
+            })
+            .collect();
+        QuoteRow {
+            code: share.company_code.clone(),
+            price: share.price.shown_amount(),
+            currency: share.price.currency.as_ref().map(ToString::to_string),
+            time: share.price_date,
+            history,
+        }
+    }
+}
+
+/// Everything but `OutputFormat::Table`, which keeps its colours and goes through prettytable
+pub fn render(format: OutputFormat, quote_rows: &[QuoteRow], moments: &[ShareMoment]) -> StockResult<String> {
+    match format {
+        OutputFormat::Json => Ok(serde_json::to_string_pretty(quote_rows)?),
+        OutputFormat::Csv => render_delimited(quote_rows, moments, b','),
+        OutputFormat::Tsv => render_delimited(quote_rows, moments, b'\t'),
+        OutputFormat::Markdown => Ok(render_markdown(quote_rows, moments)),
+        OutputFormat::Html => Ok(render_html(quote_rows, moments)),
+        OutputFormat::Table => Err(StockError::Render("The table is printed, not rendered".to_string())),
+    }
+}
+
+/// One column per field, e.g. `yesterday_price`, empty where there's no history
+fn render_delimited(quote_rows: &[QuoteRow], moments: &[ShareMoment], delimiter: u8) -> StockResult<String> {
+    let render_error = |e: csv::Error| StockError::Render(e.to_string());
+    let mut writer = csv::WriterBuilder::new().delimiter(delimiter).from_writer(Vec::new());
+    let mut header = vec!["code".to_string(), "price".to_string(), "currency".to_string(), "time".to_string()];
+    for moment in moments {
+        let prefix = moment.to_string().to_lowercase();
+        header.extend(["price", "currency", "date", "movement", "movement_percent"].iter().map(|field| format!("{}_{}", prefix, field)));
+    }
+    writer.write_record(&header).map_err(render_error)?;
+    for quote_row in quote_rows {
+        let mut record = vec![quote_row.code.clone(), quote_row.price.to_string(), quote_row.currency.clone().unwrap_or_default(), utc_string(quote_row.time)];
+        for historic in &quote_row.history {
+            record.push(optional_string(historic.price));
+            record.push(historic.currency.clone().unwrap_or_default());
+            record.push(historic.date.map(utc_string).unwrap_or_default());
+            record.push(optional_string(historic.movement));
+            record.push(optional_string(historic.movement_percent));
+        }
+        writer.write_record(&record).map_err(render_error)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| StockError::Render(e.to_string()))?;
+    String::from_utf8(bytes).map_err(|e| StockError::Render(e.to_string()))
+}
+
+/// The same headings as the table
+fn readable_header(moments: &[ShareMoment]) -> Vec<String> {
+    let mut header = vec!["CODE".to_string(), "CURRENT PRICE".to_string(), "CCY".to_string(), "CURR TIME".to_string()];
+    for moment in moments {
+        header.push(format!("{} PRICE", moment));
+        header.push(format!("{} DATE", moment));
+        header.push(format!("{} MOVEMENT", moment));
+        header.push("%".to_string());
+    }
+    header
+}
+
+/// Cells for a person to read, with "---" where there's no history like the table has
+fn readable_cells(quote_row: &QuoteRow) -> Vec<String> {
+    let currency = quote_row.currency.clone().unwrap_or_default();
+    let mut cells = vec![quote_row.code.clone(), quote_row.price.to_string(), currency.clone(), quote_row.time.format(READABLE_DATE_FMT).to_string()];
+    for historic in &quote_row.history {
+        let historic_currency = historic.currency.clone().unwrap_or_default();
+        match (historic.price, historic.date, historic.movement, historic.movement_percent) {
+            (Some(price), Some(date), Some(movement), Some(percent)) => {
+                cells.push(price.to_string());
+                cells.push(date.format(READABLE_DATE_FMT).to_string());
+                cells.push(movement.to_string());
+                cells.push(signed_percent(percent));
+            }
+            //priced in something else, so the currency goes with it and there's no movement
+            (Some(price), Some(date), _, _) => {
+                cells.push(format!("{} {}", price, historic_currency));
+                cells.push(date.format(READABLE_DATE_FMT).to_string());
+                cells.push(format!("{} vs {}", historic_currency, currency));
+                cells.push("---".to_string());
+            }
+            _ => cells.extend((0..4).map(|_| "---".to_string())),
+        }
+    }
+    cells
+}
+
+fn render_markdown(quote_rows: &[QuoteRow], moments: &[ShareMoment]) -> String {
+    let header = readable_header(moments);
+    let mut lines = vec![
+        markdown_row(&header),
+        markdown_row(&header.iter().map(|_| "---".to_string()).collect::<Vec<_>>()),
+    ];
+    lines.extend(quote_rows.iter().map(|quote_row| markdown_row(&readable_cells(quote_row))));
+    lines.join("\n") + "\n"
+}
+
+fn markdown_row(cells: &[String]) -> String {
+    format!("| {} |", cells.iter().map(|cell| cell.replace('|', "\\|")).collect::<Vec<_>>().join(" | "))
+}
+
+/// Just the table, for dropping into a page. Movement cells get an "up" or "down" class to hang colours off.
+fn render_html(quote_rows: &[QuoteRow], moments: &[ShareMoment]) -> String {
+    let mut html = vec!["<table class=\"quotes\">".to_string(), "  <thead>".to_string()];
+    let header_cells: Vec<String> = readable_header(moments).iter()
+        .map(|title| format!("<th>{}</th>", escape_html(title)))
+        .collect();
+    html.push(format!("    <tr>{}</tr>", header_cells.concat()));
+    html.push("  </thead>".to_string());
+    html.push("  <tbody>".to_string());
+    for quote_row in quote_rows {
+        let cells: Vec<String> = readable_cells(quote_row).iter()
+            .enumerate()
+            .map(|(column, cell)| {
+                //the movement and percent of every moment, after the four current columns
+                let direction = match column.checked_sub(4).map(|historic_column| (historic_column / 4, historic_column % 4)) {
+                    Some((moment_idx, 2)) | Some((moment_idx, 3)) => quote_row.history[moment_idx].movement
+                        .map(|movement| if movement < Decimal::zero() { " class=\"down\"" } else { " class=\"up\"" }),
+                    _ => None,
+                };
+                format!("<td{}>{}</td>", direction.unwrap_or_default(), escape_html(cell))
+            })
+            .collect();
+        html.push(format!("    <tr>{}</tr>", cells.concat()));
+    }
+    html.push("  </tbody>".to_string());
+    html.push("</table>".to_string());
+    html.join("\n") + "\n"
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn signed_percent(percent: Decimal) -> String {
+    let percent = percent.round_dp(2);
+    if percent < Decimal::zero() {
+        format!("{}%", percent)
+    } else {
+        format!("+{}%", percent)
+    }
+}
+
+fn optional_string(decimal: Option<Decimal>) -> String {
+    decimal.map(|decimal| decimal.to_string()).unwrap_or_default()
+}
+
+/// ISO 8601 with the Z, so nothing reading it has to guess the zone
+pub fn utc_string(date: NaiveDateTime) -> String {
+    date.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// Written out digit for digit as raw JSON, a float would round the money. Only for JSON, nothing else takes it raw.
+pub fn decimal_number<S: Serializer>(decimal: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    let number = RawValue::from_string(decimal.to_string()).map_err(S::Error::custom)?;
+    number.serialize(serializer)
+}
+
+fn optional_decimal_number<S: Serializer>(decimal: &Option<Decimal>, serializer: S) -> Result<S::Ok, S::Error> {
+    match decimal {
+        Some(decimal) => decimal_number(decimal, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub fn utc_timestamp<S: Serializer>(date: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&utc_string(*date))
+}
+
+fn optional_utc_timestamp<S: Serializer>(date: &Option<NaiveDateTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    match date {
+        Some(date) => utc_timestamp(date, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn display_string<S: Serializer>(moment: &ShareMoment, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&moment.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::NaiveDate;
+    use chrono_tz::Tz;
+    use serde_json::Value;
+
+    use crate::share_price_model::{Price, Share};
+
+    use super::*;
+
+    fn share(price: &str, currency: &str, day: u32) -> Share {
+        Share {
+            company_code: "NPN".to_string(),
+            price: Price::in_currency(Price::parse(price).unwrap().amount, Some(currency.parse().unwrap())),
+            price_date: NaiveDate::from_ymd_opt(2020, 10, day).unwrap().and_hms_opt(15, 0, 0).unwrap(),
+            exchange_tz: Tz::UTC,
+            source: None,
+        }
+    }
+
+    fn quote_rows() -> (Vec<QuoteRow>, Vec<ShareMoment>) {
+        quote_rows_from("ZAR")
+    }
+
+    /// Today's price in ZAR, yesterday's in `historic_currency`, nothing from last week
+    fn quote_rows_from(historic_currency: &str) -> (Vec<QuoteRow>, Vec<ShareMoment>) {
+        let moments = vec![ShareMoment::Yesterday, ShareMoment::LastWeek];
+        let mut share_history = HashMap::new();
+        share_history.insert(ShareMoment::Yesterday, share("2750.00", historic_currency, 15));
+        let share_timeline = ShareTimeline { share: share("2834.50", "ZAR", 16), share_history };
+        (vec![QuoteRow::new(&share_timeline, &moments)], moments)
+    }
+
+    #[test]
+    fn json_keeps_numbers_as_numbers() {
+        let (quote_rows, moments) = quote_rows();
+        let rendered = render(OutputFormat::Json, &quote_rows, &moments).unwrap();
+        //exactly as saved, not rounded through a float
+        assert!(rendered.contains(r#""price": 2834.50,"#));
+        assert!(rendered.contains(r#""movement_percent": 3.0727"#));
+        let json: Value = serde_json::from_str(&rendered).unwrap();
+        let quote = &json[0];
+        assert_eq!(quote["code"], "NPN");
+        assert_eq!(quote["price"], 2834.5);
+        assert_eq!(quote["currency"], "ZAR");
+        assert_eq!(quote["time"], "2020-10-16T15:00:00Z");
+        assert_eq!(quote["history"][0]["moment"], "Yesterday");
+        assert_eq!(quote["history"][0]["price"], 2750.0);
+        assert_eq!(quote["history"][0]["movement"], 84.5);
+        assert_eq!(quote["history"][0]["movement_percent"], 3.0727);
+        assert!(quote["history"][1]["price"].is_null());
+    }
+
+    #[test]
+    fn json_numbers_too_big_for_a_float_stay_exact() {
+        #[derive(Serialize)]
+        struct Amount(#[serde(serialize_with = "decimal_number")] Decimal);
+        assert_eq!(serde_json::to_string(&Amount(Decimal::max_value())).unwrap(), "79228162514264337593543950335");
+        assert_eq!(serde_json::to_string(&Amount(Decimal::from_str("-0.000000001").unwrap())).unwrap(), "-0.000000001");
+    }
+
+    #[test]
+    fn delimited_has_a_column_per_field() {
+        let (quote_rows, moments) = quote_rows();
+        let csv = render(OutputFormat::Csv, &quote_rows, &moments).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "code,price,currency,time,yesterday_price,yesterday_currency,yesterday_date,yesterday_movement,yesterday_movement_percent,\
+                              lastweek_price,lastweek_currency,lastweek_date,lastweek_movement,lastweek_movement_percent");
+        assert_eq!(lines[1], "NPN,2834.50,ZAR,2020-10-16T15:00:00Z,2750.00,ZAR,2020-10-15T15:00:00Z,84.50,3.0727,,,,,");
+        let tsv = render(OutputFormat::Tsv, &quote_rows, &moments).unwrap();
+        assert!(tsv.lines().nth(1).unwrap().starts_with("NPN\t2834.50\t"));
+    }
+
+    #[test]
+    fn readable_formats_match_the_table() {
+        let (quote_rows, moments) = quote_rows();
+        let markdown = render(OutputFormat::Markdown, &quote_rows, &moments).unwrap();
+        assert_eq!(markdown.lines().nth(2).unwrap(),
+                   "| NPN | 2834.50 | ZAR | 2020-10-16 15:00:00 | 2750.00 | 2020-10-15 15:00:00 | 84.50 | +3.07% | --- | --- | --- | --- |");
+        let html = render(OutputFormat::Html, &quote_rows, &moments).unwrap();
+        assert!(html.contains("<td class=\"up\">+3.07%</td>"));
+        assert!(html.contains("<th>Yesterday PRICE</th>"));
+    }
+
+    #[test]
+    fn no_movement_across_currencies() {
+        let (quote_rows, moments) = quote_rows_from("USD");
+        assert_eq!(quote_rows[0].history[0].price, Some(Decimal::new(275000, 2)));
+        assert_eq!(quote_rows[0].history[0].movement, None);
+        let markdown = render(OutputFormat::Markdown, &quote_rows, &moments).unwrap();
+        assert!(markdown.lines().nth(2).unwrap()
+            .starts_with("| NPN | 2834.50 | ZAR | 2020-10-16 15:00:00 | 2750.00 USD | 2020-10-15 15:00:00 | USD vs ZAR | --- |"));
+    }
+}
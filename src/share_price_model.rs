@@ -1,14 +1,142 @@
 use chrono::prelude::*;
+use chrono_tz::Tz;
 use std::collections::HashMap;
-use rust_decimal::Decimal;
+use std::fmt;
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal::prelude::Zero;
 use std::str::FromStr;
-use std::string::ToString;
-use strum_macros::Display;
 
+use crate::error::{StockError, StockResult};
+
+/// A three letter code like "ZAR" or "USD"
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CurrencyCode(String);
+
+impl FromStr for CurrencyCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim().to_uppercase();
+        if code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()) {
+            Ok(CurrencyCode(code))
+        } else {
+            Err(format!("'{}' isn't a currency code like ZAR or USD", s))
+        }
+    }
+}
+
+impl fmt::Display for CurrencyCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Thousands grouping that isn't a ',' or '.', like "1 234,80" or "1'234.80"
+pub const GROUPING_MARKS: [char; 4] = [' ', '\u{a0}', '\u{202f}', '\''];
+
+/// Digits with ',' and '.' in them, and maybe grouped by spaces, down to "1234.80". Whichever of ',' or '.'
+/// comes last is the decimal point, unless it also groups earlier digits, or it's a lone ',' after one to three
+/// digits and before three more, so "1,234" is 1234 but "0,125" is still 0.125. Nothing when it reads both ways.
+pub fn plain_amount(amount: &str) -> Option<String> {
+    let amount: String = amount.chars().filter(|c| !GROUPING_MARKS.contains(c)).collect();
+    let decimal_idx = match amount.rfind(&[',', '.'][..]) {
+        Some(decimal_idx) => decimal_idx,
+        None => return Some(amount),
+    };
+    let (whole, fraction) = (&amount[..decimal_idx], &amount[decimal_idx + 1..]);
+    let separator = if amount[decimal_idx..].starts_with(',') { ',' } else { '.' };
+    let other_separator = if separator == ',' { '.' } else { ',' };
+    let grouped_only = if whole.contains(separator) {
+        //"1,234,567", but "1.234,567,8" is neither
+        if whole.contains(other_separator) {
+            return None;
+        }
+        true
+    } else {
+        !whole.contains(other_separator) && separator == ',' && fraction.len() == 3
+            && (1..=3).contains(&whole.len()) && !whole.starts_with('0')
+    };
+    let whole_digits: String = whole.chars().filter(char::is_ascii_digit).collect();
+    match grouped_only {
+        true => Some(format!("{}{}", whole_digits, fraction)),
+        false if fraction.is_empty() => None,
+        false => Some(format!("{}.{}", whole_digits, fraction)),
+    }
+}
+
+/// What a share cost, checked once where it comes in so nothing after that has to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Price {
+    pub amount: Decimal,
+    /// Nothing when the source didn't say
+    pub currency: Option<CurrencyCode>,
+    /// How many places it's shown to. Only converted prices have one, they keep every place the rate gave them.
+    pub display_places: Option<u32>,
+}
+
+impl Price {
+    pub fn new(amount: Decimal) -> Price {
+        Price { amount, currency: None, display_places: None }
+    }
+
+    pub fn in_currency(amount: Decimal, currency: Option<CurrencyCode>) -> Price {
+        Price { amount, currency, display_places: None }
+    }
+
+    /// The amount as it goes on screen
+    pub fn shown_amount(&self) -> Decimal {
+        self.shown(self.amount)
+    }
+
+    /// Something worked out from this price, like a movement, rounded the way the price is shown
+    pub fn shown(&self, amount: Decimal) -> Decimal {
+        match self.display_places {
+            Some(places) => {
+                let mut rounded = amount.round_dp_with_strategy(places, RoundingStrategy::RoundHalfUp);
+                rounded.rescale(places);
+                rounded
+            }
+            None => amount,
+        }
+    }
+
+    /// Only a known mismatch counts, rows saved before we kept the currency could be anything
+    pub fn same_currency(&self, other: &Price) -> bool {
+        match (&self.currency, &other.currency) {
+            (Some(currency), Some(other_currency)) => currency == other_currency,
+            _ => true,
+        }
+    }
+
+    /// The code, or nothing if we don't know it
+    pub fn currency_label(&self) -> String {
+        self.currency.as_ref().map(ToString::to_string).unwrap_or_default()
+    }
+
+    /// Only a plain "2834.50", locale fix-ups belong to the source
+    pub fn parse(amount: &str) -> StockResult<Price> {
+        Decimal::from_str(amount.trim())
+            .map(Price::new)
+            .map_err(|e| StockError::Parse(format!("Couldn't read '{}' as a price: {}", amount, e)))
+    }
+}
+
+/// Just the amount, as many places as it came with or as it's shown once converted
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.shown_amount())
+    }
+}
+
+#[derive(Clone)]
 pub struct Share{
     pub company_code: String,
-    pub price: String,
+    pub price: Price,
+    /// Always UTC, `exchange_tz` says where the exchange that priced it keeps its clocks
     pub price_date: NaiveDateTime,
+    pub exchange_tz: Tz,
+    /// The price source that found it, or "import". Nothing for prices saved before we kept track.
+    pub source: Option<String>,
 }
 
 const DATE_FMT:  &str = "%Y-%m-%d \n%H:%M:%S";
@@ -17,26 +145,252 @@ impl Share{
     pub fn display_date(&self)->String{
         self.price_date.format(DATE_FMT).to_string()
     }
-    pub fn pretty_price(&self) -> String {
-        self.price.trim().replace(",", ".")
-    }
-    pub fn price_as_decimal(&self) -> Decimal {
-        Decimal::from_str(&self.pretty_price()).unwrap_or_default()
+    /// The calendar day on the exchange when this price was taken
+    pub fn exchange_date(&self) -> NaiveDate {
+        self.exchange_tz.from_utc_datetime(&self.price_date).naive_local().date()
     }
 }
 
+/// Time zone names we store, anything we can't make sense of is treated as UTC
+pub fn parse_exchange_tz(tz_name: &str) -> Tz {
+    tz_name.parse().unwrap_or(Tz::UTC)
+}
+
 
-#[derive(Debug, PartialEq, Eq, Hash, Display)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ShareMoment {
     Yesterday,
     LastWeek,
     LastMonth,
+    LastQuarter,
+    YearToDate,
     LastYear,
+    FiveYears,
+    Days(u32),
+}
+
+pub const DEFAULT_MOMENTS: &str = "yesterday,week,month,year";
+
+/// A hundred years back is as far as an N days moment goes
+pub const MAX_MOMENT_DAYS: u32 = 36500;
+
+impl ShareMoment {
+    /// How many days back from `exchange_today` to go looking for a price
+    pub fn days_ago(&self, exchange_today: NaiveDate) -> i32 {
+        match self {
+            ShareMoment::Yesterday => 1,
+            ShareMoment::LastWeek => 7,
+            ShareMoment::LastMonth => 30,
+            ShareMoment::LastQuarter => 91,
+            //back to the last day of the previous year
+            ShareMoment::YearToDate => exchange_today.ordinal() as i32,
+            ShareMoment::LastYear => 365,
+            ShareMoment::FiveYears => 5 * 365 + 1,
+            ShareMoment::Days(days) => *days as i32,
+        }
+    }
 }
 
+impl FromStr for ShareMoment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "yesterday" | "1d" => Ok(ShareMoment::Yesterday),
+            "week" | "1w" => Ok(ShareMoment::LastWeek),
+            "month" | "1m" => Ok(ShareMoment::LastMonth),
+            "quarter" | "3m" => Ok(ShareMoment::LastQuarter),
+            "ytd" => Ok(ShareMoment::YearToDate),
+            "year" | "1y" => Ok(ShareMoment::LastYear),
+            "5y" => Ok(ShareMoment::FiveYears),
+            other => match other.trim_end_matches('d').parse::<u32>() {
+                Ok(days) if days > 0 && days <= MAX_MOMENT_DAYS => Ok(ShareMoment::Days(days)),
+                Ok(_) => Err(format!("'{}' needs to be between 1 and {} days", s, MAX_MOMENT_DAYS)),
+                Err(_) => Err(format!("'{}' isn't a moment, try yesterday, week, month, quarter, ytd, year, 5y or a number of days like 45d", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ShareMoment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShareMoment::Yesterday => write!(f, "Yesterday"),
+            ShareMoment::LastWeek => write!(f, "LastWeek"),
+            ShareMoment::LastMonth => write!(f, "LastMonth"),
+            ShareMoment::LastQuarter => write!(f, "LastQuarter"),
+            ShareMoment::YearToDate => write!(f, "YearToDate"),
+            ShareMoment::LastYear => write!(f, "LastYear"),
+            ShareMoment::FiveYears => write!(f, "FiveYears"),
+            ShareMoment::Days(days) => write!(f, "Last{}Days", days),
+        }
+    }
+}
+
+/// What we know about a ticker besides its prices, all of it optional
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TickerInfo {
+    pub company_code: String,
+    pub display_name: Option<String>,
+    pub exchange: Option<String>,
+    pub notes: Option<String>,
+}
+
+#[derive(Clone)]
 pub struct ShareTimeline {
     pub share: Share,
     pub share_history:HashMap<ShareMoment, Share>
 }
 
-impl ShareTimeline{}
\ No newline at end of file
+impl ShareTimeline{}
+
+/// How far the price has come since an earlier one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Movement {
+    pub absolute: Decimal,
+    pub percent: Decimal,
+}
+
+impl Movement {
+    /// Nothing if the prices are in different currencies, there's no subtracting those
+    pub fn between(historic: &Share, current: &Share) -> Option<Movement> {
+        if !historic.price.same_currency(&current.price) {
+            return None;
+        }
+        let curr_price = current.price.amount;
+        let historic_price = historic.price.amount;
+        let percent = if historic_price.is_zero() {
+            Decimal::zero()
+        } else {
+            (curr_price / historic_price) * Decimal::from(100) - Decimal::from(100)
+        };
+        Some(Movement {
+            absolute: curr_price - historic_price,
+            percent,
+        })
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.absolute < Decimal::zero()
+    }
+
+    /// Always signed, like "+1.25%" or "-0.40%"
+    pub fn percent_string(&self) -> String {
+        if self.is_down() {
+            format!("{:.2}%", self.percent)
+        } else {
+            format!("+{:.2}%", self.percent)
+        }
+    }
+}
+
+/// What happened when we went looking for a company's current price
+#[derive(Clone)]
+pub enum FetchOutcome {
+    Found(ShareTimeline),
+    NotFound { company_code: String, reason: String },
+    ParseFailure { company_code: String, reason: String },
+    /// Timed out, or the source couldn't be reached at all
+    Unreachable { company_code: String, reason: String },
+}
+
+/// The headings failures are reported under, in the order they're shown
+pub const FAILURE_KINDS: [&str; 3] = ["NOT FOUND", "UNPARSEABLE", "UNREACHABLE"];
+
+impl FetchOutcome {
+    pub fn found(&self) -> Option<&ShareTimeline> {
+        match self {
+            FetchOutcome::Found(share_timeline) => Some(share_timeline),
+            _ => None,
+        }
+    }
+
+    pub fn into_found(self) -> Option<ShareTimeline> {
+        match self {
+            FetchOutcome::Found(share_timeline) => Some(share_timeline),
+            _ => None,
+        }
+    }
+
+    /// The code and what went wrong, if it did
+    pub fn failure(&self) -> Option<(&str, &str)> {
+        match self {
+            FetchOutcome::Found(_) => None,
+            FetchOutcome::NotFound { company_code, reason } => Some((company_code, reason)),
+            FetchOutcome::ParseFailure { company_code, reason } => Some((company_code, reason)),
+            FetchOutcome::Unreachable { company_code, reason } => Some((company_code, reason)),
+        }
+    }
+
+    /// Which of `FAILURE_KINDS` it is, if it failed
+    pub fn failure_kind(&self) -> Option<&'static str> {
+        match self {
+            FetchOutcome::Found(_) => None,
+            FetchOutcome::NotFound { .. } => Some(FAILURE_KINDS[0]),
+            FetchOutcome::ParseFailure { .. } => Some(FAILURE_KINDS[1]),
+            FetchOutcome::Unreachable { .. } => Some(FAILURE_KINDS[2]),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn amounts_in_any_locale() {
+        for (raw, expected) in [("2 834,50", "2834.50"), ("1\u{202f}234,80", "1234.80"), ("2,345.67", "2345.67"), ("98.10", "98.10"),
+                                ("1,234", "1234"), ("1.234,5", "1234.5"), ("0,125", "0.125"), ("1234,567", "1234.567"), ("18", "18")] {
+            assert_eq!(plain_amount(raw).as_deref(), Some(expected), "{}", raw);
+        }
+        assert_eq!(plain_amount("1.234,567,8"), None);
+    }
+
+    #[test]
+    fn moments_by_name_and_days() {
+        let moment = |s: &str| s.parse::<ShareMoment>();
+        assert_eq!(moment(" Yesterday "), Ok(ShareMoment::Yesterday));
+        assert_eq!(moment("1d"), Ok(ShareMoment::Yesterday));
+        assert_eq!(moment("3m"), Ok(ShareMoment::LastQuarter));
+        assert_eq!(moment("YTD"), Ok(ShareMoment::YearToDate));
+        assert_eq!(moment("5y"), Ok(ShareMoment::FiveYears));
+        assert_eq!(moment("45d"), Ok(ShareMoment::Days(45)));
+        assert_eq!(moment("45"), Ok(ShareMoment::Days(45)));
+        assert_eq!(moment("36500d"), Ok(ShareMoment::Days(MAX_MOMENT_DAYS)));
+    }
+
+    #[test]
+    fn day_counts_out_of_range_are_refused() {
+        for too_far in &["0d", "36501d", "99999999d", "4000000000d", "-5d", "fortnight"] {
+            assert!(too_far.parse::<ShareMoment>().is_err(), "{} parsed", too_far);
+        }
+    }
+
+    #[test]
+    fn days_ago_for_each_moment() {
+        let today = NaiveDate::from_ymd_opt(2021, 6, 15).unwrap();
+        assert_eq!(ShareMoment::Yesterday.days_ago(today), 1);
+        assert_eq!(ShareMoment::LastQuarter.days_ago(today), 91);
+        assert_eq!(ShareMoment::FiveYears.days_ago(today), 1826);
+        assert_eq!(ShareMoment::Days(MAX_MOMENT_DAYS).days_ago(today), 36500);
+    }
+
+    #[test]
+    fn year_to_date_goes_back_to_the_last_day_of_last_year() {
+        let ytd = |month, day| ShareMoment::YearToDate.days_ago(NaiveDate::from_ymd_opt(2020, month, day).unwrap());
+        assert_eq!(ytd(1, 1), 1);
+        //2020 is a leap year
+        assert_eq!(ytd(3, 1), 61);
+        assert_eq!(ytd(12, 31), 366);
+        let today = NaiveDate::from_ymd_opt(2020, 3, 1).unwrap();
+        assert_eq!(today - chrono::Duration::days(i64::from(ytd(3, 1))), NaiveDate::from_ymd_opt(2019, 12, 31).unwrap());
+    }
+
+    #[test]
+    fn failures_keep_their_kind() {
+        let failed = |outcome: FetchOutcome| (outcome.failure_kind(), outcome.failure().map(|(company_code, _)| company_code.to_string()));
+        let reason = "it broke".to_string();
+        assert_eq!(failed(FetchOutcome::NotFound { company_code: "NPN".to_string(), reason: reason.clone() }), (Some("NOT FOUND"), Some("NPN".to_string())));
+        assert_eq!(failed(FetchOutcome::ParseFailure { company_code: "NPN".to_string(), reason: reason.clone() }), (Some("UNPARSEABLE"), Some("NPN".to_string())));
+        assert_eq!(failed(FetchOutcome::Unreachable { company_code: "NPN".to_string(), reason }), (Some("UNREACHABLE"), Some("NPN".to_string())));
+    }
+}
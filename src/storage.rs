@@ -0,0 +1,424 @@
+use chrono::{Duration, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+
+use crate::config_options;
+use crate::db_model::{Backend, ConnectionDetails};
+use crate::alerts::{AlertRule, TriggeredAlert};
+use crate::error::StockResult;
+use crate::fx::FxRate;
+use crate::ledger::Transaction;
+use crate::portfolio::Holding;
+use crate::share_price_model::{Share, ShareTimeline, TickerInfo};
+use crate::storage::migrations::{AppliedMigration, Migration};
+
+pub mod migrations;
+pub mod mysql_store;
+pub mod sqlite_store;
+
+/// Somewhere to keep the prices we've scraped so we can compare against them later
+pub trait ShareStore {
+    fn save_prices(&mut self, company_prices: &[ShareTimeline]) -> StockResult<()>;
+    /// Oldest first, whatever order they were saved in
+    fn load_complete_share_history(&mut self, company_code: &str) -> StockResult<Vec<Share>>;
+    /// The most recent price stamped strictly before `before` (UTC)
+    fn load_latest_before(&mut self, company_code: &str, before: NaiveDateTime) -> StockResult<Option<Share>>;
+    /// The latest price we have that is at least `days_ago_upper_limit` days older than `as_of` (UTC),
+    /// with days counted on the calendar of the exchange the code was last priced on
+    fn load_share_history_segments(&mut self, company_code: &str, days_ago_upper_limit: i32, as_of: NaiveDateTime) -> StockResult<Option<Share>> {
+        let exchange_tz = match self.load_latest_before(company_code, as_of)? {
+            Some(latest) => latest.exchange_tz,
+            None => return Ok(None),
+        };
+        self.load_latest_before(company_code, history_cutoff(as_of, exchange_tz, days_ago_upper_limit))
+    }
+    /// Every code there's a saved price for, alphabetically
+    fn priced_codes(&mut self) -> StockResult<Vec<String>>;
+    /// Sets up the schema version table if it isn't there yet
+    fn applied_migrations(&mut self) -> StockResult<Vec<AppliedMigration>>;
+    fn apply_migration(&mut self, migration: &Migration) -> StockResult<()>;
+
+    /// Every watchlist with how many codes are on it, by name
+    fn watchlist_names(&mut self) -> StockResult<Vec<(String, usize)>>;
+    /// The codes on a watchlist in the order they were added, empty if there's no such list
+    fn watchlist_codes(&mut self, watchlist_name: &str) -> StockResult<Vec<String>>;
+    /// Adds whichever codes aren't already on the list, handing back how many that was
+    fn add_to_watchlist(&mut self, watchlist_name: &str, company_codes: &[&str]) -> StockResult<usize>;
+    /// Takes the codes off the list, or the whole list when no codes are given
+    fn remove_from_watchlist(&mut self, watchlist_name: &str, company_codes: &[&str]) -> StockResult<usize>;
+    fn load_ticker_info(&mut self, company_code: &str) -> StockResult<Option<TickerInfo>>;
+    fn save_ticker_info(&mut self, ticker_info: &TickerInfo) -> StockResult<()>;
+
+    /// Stores a new lot, handing back the id it was given
+    fn add_holding(&mut self, holding: &Holding) -> StockResult<i64>;
+    /// Every lot, oldest purchase first
+    fn load_holdings(&mut self) -> StockResult<Vec<Holding>>;
+    /// False if there was no lot with that id
+    fn remove_holding(&mut self, holding_id: i64) -> StockResult<bool>;
+
+    /// All or nothing, handing back how many went in
+    fn add_transactions(&mut self, transactions: &[Transaction]) -> StockResult<usize>;
+    /// Every transaction in the ledger by trade date
+    fn load_transactions(&mut self) -> StockResult<Vec<Transaction>>;
+
+    fn add_alert_rule(&mut self, rule: &AlertRule) -> StockResult<i64>;
+    fn load_alert_rules(&mut self) -> StockResult<Vec<AlertRule>>;
+    /// False if there was no rule with that id
+    fn remove_alert_rule(&mut self, rule_id: i64) -> StockResult<bool>;
+    /// Writes the alert to the history and starts its rule's cooldown
+    fn record_alert(&mut self, alert: &TriggeredAlert) -> StockResult<()>;
+    /// Most recent first
+    fn load_alert_history(&mut self, limit: usize) -> StockResult<Vec<TriggeredAlert>>;
+
+    /// A rate for a pair and day we already have replaces the old one, hands back how many went in
+    fn save_fx_rates(&mut self, fx_rates: &[FxRate]) -> StockResult<usize>;
+    /// Every rate, by pair and then by day
+    fn load_fx_rates(&mut self) -> StockResult<Vec<FxRate>>;
+}
+
+/// The first UTC instant that is too recent to count as `days_ago` days before `as_of`,
+/// i.e. midnight at the start of local day (today - days_ago + 1) on the exchange
+pub fn history_cutoff(as_of: NaiveDateTime, exchange_tz: Tz, days_ago: i32) -> NaiveDateTime {
+    let local_today = exchange_tz.from_utc_datetime(&as_of).naive_local().date();
+    let local_cutoff = (local_today - Duration::days(i64::from(days_ago) - 1)).and_hms_opt(0, 0, 0).unwrap();
+    match exchange_tz.from_local_datetime(&local_cutoff).earliest() {
+        Some(cutoff) => cutoff.naive_utc(),
+        //midnight skipped by a DST jump, an hour either way doesn't matter for day buckets
+        None => local_cutoff - Duration::hours(1),
+    }
+}
+
+/// Open whichever store db.json points at, sqlite if there's no db.json
+pub fn open_store() -> StockResult<Box<dyn ShareStore>> {
+    let conn_details = config_options::read_db_config()?;
+    open_store_with(&conn_details)
+}
+
+/// Open the store and make sure its schema is what this build expects
+pub fn open_migrated_store() -> StockResult<Box<dyn ShareStore>> {
+    let mut store = open_store()?;
+    migrations::ensure_current(store.as_mut())?;
+    Ok(store)
+}
+
+pub fn open_store_with(conn_details: &ConnectionDetails) -> StockResult<Box<dyn ShareStore>> {
+    match conn_details.backend() {
+        Backend::Sqlite => Ok(Box::new(sqlite_store::SqliteStore::open(&conn_details.sqlite_path)?)),
+        Backend::Mysql => Ok(Box::new(mysql_store::MySqlStore::connect(conn_details)?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{NaiveDate, NaiveTime, Utc};
+    use rust_decimal::Decimal;
+
+    use crate::backfill;
+
+    use crate::ledger::TransactionKind;
+    use crate::share_price_model::Price;
+
+    use super::*;
+
+    fn timeline_at(company_code: &str, price: &str, price_date: NaiveDateTime, exchange_tz: Tz) -> ShareTimeline {
+        ShareTimeline {
+            share: Share {
+                company_code: company_code.to_string(),
+                price: Price::parse(price).unwrap(),
+                price_date,
+                exchange_tz,
+                source: Some("replay".to_string()),
+            },
+            share_history: HashMap::new(),
+        }
+    }
+
+    fn timeline(company_code: &str, price: &str) -> ShareTimeline {
+        timeline_at(company_code, price, Utc::now().naive_utc() - Duration::seconds(5), Tz::UTC)
+    }
+
+    /// Every backend has to behave the same way for these
+    fn check_store_behaviour(store: &mut dyn ShareStore, company_code: &str) {
+        assert!(store.load_complete_share_history(company_code).unwrap().is_empty());
+        assert!(store.load_share_history_segments(company_code, 0, Utc::now().naive_utc()).unwrap().is_none());
+
+        store.save_prices(&[timeline(company_code, "101.50"), timeline("OTHER", "3.00")]).unwrap();
+        store.save_prices(&[timeline(company_code, "102.25")]).unwrap();
+
+        assert!(store.priced_codes().unwrap().contains(&company_code.to_string()));
+        let history = store.load_complete_share_history(company_code).unwrap();
+        let prices: Vec<_> = history.iter().map(|share| share.price.amount).collect();
+        assert_eq!(prices, vec![Decimal::new(10150, 2), Decimal::new(10225, 2)]);
+        assert!(history.iter().all(|share| share.company_code == company_code));
+        assert!(history.iter().all(|share| share.source.as_deref() == Some("replay")));
+
+        let now = Utc::now().naive_utc();
+        let latest = store.load_share_history_segments(company_code, 0, now).unwrap().unwrap();
+        assert_eq!(latest.price.amount, Decimal::new(10225, 2));
+        assert!(store.load_share_history_segments(company_code, 1, now).unwrap().is_none());
+    }
+
+    /// The currency comes back as saved, and older rows without one stay unknown
+    fn check_currency(store: &mut dyn ShareStore, company_code: &str) {
+        let unpriced = timeline(company_code, "2800.00");
+        let mut priced = timeline(company_code, "2834.50");
+        priced.share.price.currency = Some("ZAR".parse().unwrap());
+        store.save_prices(&[unpriced, priced]).unwrap();
+
+        let currencies: Vec<_> = store.load_complete_share_history(company_code).unwrap().iter()
+            .map(|share| share.price.currency_label())
+            .collect();
+        assert_eq!(currencies, vec!["", "ZAR"]);
+        let latest = store.load_share_history_segments(company_code, 0, Utc::now().naive_utc()).unwrap().unwrap();
+        assert_eq!(latest.price.currency_label(), "ZAR");
+    }
+
+    /// Closes imported after the scraped prices are older but get newer ids, and charts need them in time order
+    fn check_backfill_comes_back_in_time_order(store: &mut dyn ShareStore, company_code: &str) {
+        let jhb = Tz::Africa__Johannesburg;
+        store.save_prices(&[timeline_at(company_code, "105.00", NaiveDate::from_ymd_opt(2020, 10, 16).unwrap().and_hms_opt(8, 0, 0).unwrap(), jhb)]).unwrap();
+        let daily_closes = vec![
+            backfill::DailyClose { date: NaiveDate::from_ymd_opt(2020, 10, 15).unwrap(), close: Some(Decimal::from(104)) },
+            backfill::DailyClose { date: NaiveDate::from_ymd_opt(2020, 10, 14).unwrap(), close: Some(Decimal::from(103)) },
+        ];
+        let import_options = backfill::ImportOptions { exchange_tz: jhb, close_time: NaiveTime::from_hms_opt(17, 0, 0).unwrap(), currency: None };
+        let existing = store.load_complete_share_history(company_code).unwrap();
+        let plan = backfill::plan_import(company_code, &daily_closes, &existing, &import_options);
+        store.save_prices(&plan.timelines()).unwrap();
+
+        let history = store.load_complete_share_history(company_code).unwrap();
+        let days: Vec<_> = history.iter().map(Share::exchange_date).collect();
+        assert_eq!(days, vec![NaiveDate::from_ymd_opt(2020, 10, 14).unwrap(), NaiveDate::from_ymd_opt(2020, 10, 15).unwrap(), NaiveDate::from_ymd_opt(2020, 10, 16).unwrap()]);
+        //compared as numbers, MySQL drops the trailing zeros
+        let prices: Vec<_> = history.iter().map(|share| share.price.amount).collect();
+        assert_eq!(prices, vec![Decimal::from(103), Decimal::from(104), Decimal::from(105)]);
+    }
+
+    /// Day boundaries are the exchange's, not UTC's and not the database server's
+    fn check_segments_use_exchange_days(store: &mut dyn ShareStore, company_code: &str) {
+        let jhb = Tz::Africa__Johannesburg;
+        let as_of = NaiveDate::from_ymd_opt(2020, 10, 16).unwrap().and_hms_opt(10, 0, 0).unwrap();
+        store.save_prices(&[
+            timeline_at(company_code, "90.00", NaiveDate::from_ymd_opt(2020, 9, 1).unwrap().and_hms_opt(15, 0, 0).unwrap(), jhb),
+            timeline_at(company_code, "95.00", NaiveDate::from_ymd_opt(2020, 10, 8).unwrap().and_hms_opt(15, 0, 0).unwrap(), jhb),
+            timeline_at(company_code, "99.00", NaiveDate::from_ymd_opt(2020, 10, 14).unwrap().and_hms_opt(15, 0, 0).unwrap(), jhb),
+            //still the 15th in UTC, but already the 16th in Johannesburg
+            timeline_at(company_code, "100.00", NaiveDate::from_ymd_opt(2020, 10, 15).unwrap().and_hms_opt(23, 0, 0).unwrap(), jhb),
+        ]).unwrap();
+
+        let price_for = |store: &mut dyn ShareStore, days_ago| store.load_share_history_segments(company_code, days_ago, as_of)
+            .unwrap()
+            .map(|share| share.price.amount);
+        assert_eq!(price_for(store, 0), Some(Decimal::from(100)));
+        assert_eq!(price_for(store, 1), Some(Decimal::from(99)));
+        assert_eq!(price_for(store, 7), Some(Decimal::from(95)));
+        assert_eq!(price_for(store, 30), Some(Decimal::from(90)));
+        assert_eq!(price_for(store, 365), None);
+
+        let history = store.load_complete_share_history(company_code).unwrap();
+        assert!(history.iter().all(|share| share.exchange_tz == jhb));
+        assert_eq!(history[3].price_date, NaiveDate::from_ymd_opt(2020, 10, 15).unwrap().and_hms_opt(23, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn cutoff_is_local_midnight_in_utc() {
+        let as_of = NaiveDate::from_ymd_opt(2020, 10, 16).unwrap().and_hms_opt(10, 0, 0).unwrap();
+        assert_eq!(history_cutoff(as_of, Tz::UTC, 1), NaiveDate::from_ymd_opt(2020, 10, 16).unwrap().and_hms_opt(0, 0, 0).unwrap());
+        assert_eq!(history_cutoff(as_of, Tz::Africa__Johannesburg, 1), NaiveDate::from_ymd_opt(2020, 10, 15).unwrap().and_hms_opt(22, 0, 0).unwrap());
+        assert_eq!(history_cutoff(as_of, Tz::America__New_York, 7), NaiveDate::from_ymd_opt(2020, 10, 10).unwrap().and_hms_opt(4, 0, 0).unwrap());
+        let furthest = crate::share_price_model::ShareMoment::Days(crate::share_price_model::MAX_MOMENT_DAYS);
+        assert_eq!(history_cutoff(as_of, Tz::UTC, furthest.days_ago(as_of.date())), NaiveDate::from_ymd_opt(1920, 11, 11).unwrap().and_hms_opt(0, 0, 0).unwrap());
+    }
+
+    fn check_watchlists(store: &mut dyn ShareStore, watchlist_name: &str) {
+        assert!(store.watchlist_codes(watchlist_name).unwrap().is_empty());
+        assert_eq!(store.add_to_watchlist(watchlist_name, &["SOL", "NPN"]).unwrap(), 2);
+        assert_eq!(store.add_to_watchlist(watchlist_name, &["NPN", "MTN"]).unwrap(), 1);
+        assert_eq!(store.watchlist_codes(watchlist_name).unwrap(), vec!["SOL", "NPN", "MTN"]);
+        assert!(store.watchlist_names().unwrap().contains(&(watchlist_name.to_string(), 3)));
+
+        assert_eq!(store.remove_from_watchlist(watchlist_name, &["NPN", "AAPL"]).unwrap(), 1);
+        assert_eq!(store.watchlist_codes(watchlist_name).unwrap(), vec!["SOL", "MTN"]);
+        assert_eq!(store.remove_from_watchlist(watchlist_name, &[]).unwrap(), 2);
+        assert!(store.watchlist_codes(watchlist_name).unwrap().is_empty());
+
+        let company_code = format!("{}INFO", watchlist_name);
+        assert!(store.load_ticker_info(&company_code).unwrap().is_none());
+        let mut ticker_info = TickerInfo {
+            company_code: company_code.clone(),
+            display_name: Some("Naspers".to_string()),
+            exchange: Some("JSE".to_string()),
+            notes: None,
+        };
+        store.save_ticker_info(&ticker_info).unwrap();
+        ticker_info.notes = Some("Mostly Tencent".to_string());
+        store.save_ticker_info(&ticker_info).unwrap();
+        assert_eq!(store.load_ticker_info(&company_code).unwrap(), Some(ticker_info));
+    }
+
+    fn check_holdings(store: &mut dyn ShareStore, company_code: &str) {
+        let lot = Holding {
+            id: None,
+            company_code: company_code.to_string(),
+            quantity: "12.5".parse().unwrap(),
+            buy_price: "101.25".parse().unwrap(),
+            buy_date: NaiveDate::from_ymd_opt(2020, 3, 23).unwrap(),
+            fees: "7.50".parse().unwrap(),
+        };
+        let holding_id = store.add_holding(&lot).unwrap();
+        let saved = store.load_holdings().unwrap().into_iter()
+            .find(|holding| holding.id == Some(holding_id))
+            .unwrap();
+        assert_eq!(saved, Holding { id: Some(holding_id), ..lot });
+
+        assert!(store.remove_holding(holding_id).unwrap());
+        assert!(!store.remove_holding(holding_id).unwrap());
+        assert!(store.load_holdings().unwrap().iter().all(|holding| holding.id != Some(holding_id)));
+    }
+
+    fn check_transactions(store: &mut dyn ShareStore, company_code: &str) {
+        let transactions = vec![
+            Transaction {
+                id: None,
+                company_code: company_code.to_string(),
+                kind: TransactionKind::Sell,
+                trade_date: NaiveDate::from_ymd_opt(2020, 6, 1).unwrap(),
+                quantity: "4".parse().unwrap(),
+                price: "120.5".parse().unwrap(),
+                fees: "3.25".parse().unwrap(),
+            },
+            Transaction {
+                id: None,
+                company_code: company_code.to_string(),
+                kind: TransactionKind::Buy,
+                trade_date: NaiveDate::from_ymd_opt(2020, 1, 15).unwrap(),
+                quantity: "10".parse().unwrap(),
+                price: "100".parse().unwrap(),
+                fees: "0".parse().unwrap(),
+            },
+        ];
+        assert_eq!(store.add_transactions(&transactions).unwrap(), 2);
+
+        let saved: Vec<Transaction> = store.load_transactions().unwrap().into_iter()
+            .filter(|transaction| transaction.company_code == company_code)
+            .collect();
+        assert_eq!(saved.len(), 2);
+        assert!(saved.iter().all(|transaction| transaction.id.is_some()));
+        assert_eq!(saved[0], Transaction { id: saved[0].id, ..transactions[1].clone() });
+        assert_eq!(saved[1], Transaction { id: saved[1].id, ..transactions[0].clone() });
+    }
+
+    fn check_alert_rules(store: &mut dyn ShareStore, company_code: &str) {
+        let rule = AlertRule {
+            id: None,
+            company_code: company_code.to_string(),
+            condition: "moves 5% week".parse().unwrap(),
+            cooldown: Duration::hours(2),
+            last_triggered: None,
+        };
+        let rule_id = store.add_alert_rule(&rule).unwrap();
+        let find_rule = |store: &mut dyn ShareStore| store.load_alert_rules().unwrap().into_iter()
+            .find(|saved| saved.id == Some(rule_id))
+            .unwrap();
+        assert_eq!(find_rule(store), AlertRule { id: Some(rule_id), ..rule.clone() });
+
+        let alert = TriggeredAlert {
+            rule_id,
+            company_code: company_code.to_string(),
+            condition: rule.condition.to_string(),
+            price: "99.5".parse().unwrap(),
+            triggered_at: NaiveDate::from_ymd_opt(2020, 10, 16).unwrap().and_hms_opt(9, 30, 0).unwrap(),
+            message: "moved +6.00% since LastWeek, more than 5%".to_string(),
+            movement: None,
+        };
+        store.record_alert(&alert).unwrap();
+        assert_eq!(find_rule(store).last_triggered, Some(alert.triggered_at));
+        assert!(store.load_alert_history(1000).unwrap().contains(&alert));
+
+        assert!(store.remove_alert_rule(rule_id).unwrap());
+        assert!(!store.remove_alert_rule(rule_id).unwrap());
+    }
+
+    /// Saving the same pair and day again replaces the rate rather than adding another
+    fn check_fx_rates(store: &mut dyn ShareStore) {
+        let fx_rate = |rate_date: NaiveDate, rate: &str| FxRate {
+            base: "XAU".parse().unwrap(),
+            quote: "ZAR".parse().unwrap(),
+            rate_date,
+            rate: rate.parse().unwrap(),
+            source: "csv".to_string(),
+        };
+        let first_day = NaiveDate::from_ymd_opt(2020, 10, 15).unwrap();
+        let second_day = NaiveDate::from_ymd_opt(2020, 10, 16).unwrap();
+        store.save_fx_rates(&[fx_rate(second_day, "31000.5"), fx_rate(first_day, "30500")]).unwrap();
+        store.save_fx_rates(&[fx_rate(second_day, "31100.25")]).unwrap();
+
+        let saved: Vec<FxRate> = store.load_fx_rates().unwrap().into_iter()
+            .filter(|saved| saved.base.to_string() == "XAU")
+            .collect();
+        assert_eq!(saved, vec![fx_rate(first_day, "30500"), fx_rate(second_day, "31100.25")]);
+    }
+
+    /// Migrating twice is the same as migrating once
+    fn check_migrations(store: &mut dyn ShareStore) {
+        migrations::migrate(store).unwrap();
+        assert_eq!(migrations::current_version(store).unwrap(), migrations::latest_version());
+        assert!(migrations::migrate(store).unwrap().is_empty());
+        assert!(migrations::pending(store).unwrap().is_empty());
+        migrations::ensure_current(store).unwrap();
+    }
+
+    #[test]
+    fn sqlite_store_behaviour() {
+        let mut store = sqlite_store::SqliteStore::open(":memory:").unwrap();
+        check_migrations(&mut store);
+        check_store_behaviour(&mut store, "NPN");
+        check_segments_use_exchange_days(&mut store, "SOL");
+        check_currency(&mut store, "MC");
+        check_backfill_comes_back_in_time_order(&mut store, "NED");
+        check_watchlists(&mut store, "mining");
+        check_holdings(&mut store, "NPN");
+        check_transactions(&mut store, "NPN");
+        check_alert_rules(&mut store, "NPN");
+        check_fx_rates(&mut store);
+    }
+
+    #[test]
+    fn fresh_sqlite_store_gets_migrated() {
+        let mut store = sqlite_store::SqliteStore::open(":memory:").unwrap();
+        assert_eq!(migrations::current_version(&mut store).unwrap(), 0);
+        migrations::ensure_current(&mut store).unwrap();
+        assert_eq!(migrations::current_version(&mut store).unwrap(), migrations::latest_version());
+        assert_eq!(store.applied_migrations().unwrap().len(), migrations::MIGRATIONS.len());
+    }
+
+    /// Only runs when STOCKCHECKER_TEST_MYSQL_DB names a scratch database on a local mysql,
+    /// with STOCKCHECKER_TEST_MYSQL_USER / STOCKCHECKER_TEST_MYSQL_PASSWORD to get in
+    #[test]
+    fn mysql_store_behaviour() {
+        let database = match std::env::var("STOCKCHECKER_TEST_MYSQL_DB") {
+            Ok(database) => database,
+            Err(_) => return,
+        };
+        let conn_details = ConnectionDetails {
+            backend: Some(Backend::Mysql),
+            database,
+            username: std::env::var("STOCKCHECKER_TEST_MYSQL_USER").unwrap_or_default(),
+            password: std::env::var("STOCKCHECKER_TEST_MYSQL_PASSWORD").unwrap_or_default(),
+            ..ConnectionDetails::default()
+        };
+        let mut store = mysql_store::MySqlStore::connect(&conn_details).unwrap();
+        check_migrations(&mut store);
+        let company_code = format!("TEST{}", Utc::now().timestamp_nanos_opt().unwrap());
+        check_store_behaviour(&mut store, &company_code);
+        check_segments_use_exchange_days(&mut store, &format!("{}TZ", company_code));
+        check_currency(&mut store, &format!("{}CCY", company_code));
+        check_backfill_comes_back_in_time_order(&mut store, &format!("{}OLD", company_code));
+        check_watchlists(&mut store, &company_code);
+        check_holdings(&mut store, &company_code);
+        check_transactions(&mut store, &company_code);
+        check_alert_rules(&mut store, &company_code);
+        check_fx_rates(&mut store);
+    }
+}
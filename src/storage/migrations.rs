@@ -0,0 +1,288 @@
+use chrono::NaiveDateTime;
+
+use crate::error::{StockError, StockResult};
+use crate::storage::ShareStore;
+
+/// One step forward for the schema. Each backend gets its own flavour of SQL,
+/// run in order, and the version is recorded once they've all gone through.
+/// The mysql statements have to be safe to run again, mysql can't roll back a migration cut short part way.
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sqlite: &'static [&'static str],
+    pub mysql: &'static [&'static str],
+}
+
+/// A migration the database says it has already had
+pub struct AppliedMigration {
+    pub version: i64,
+    pub description: String,
+    pub applied_at: NaiveDateTime,
+}
+
+/// Every migration there is, oldest first. Only ever add to the end of this.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create stock_prices",
+        sqlite: &[
+            r"CREATE TABLE IF NOT EXISTS stock_prices
+                 ( id integer primary key autoincrement,
+                   company_code text,
+                   price text,
+                   price_date text
+                 )",
+        ],
+        mysql: &[
+            r"CREATE TABLE IF NOT EXISTS stock_prices
+                 ( id bigint auto_increment,
+                   company_code varchar(255),
+                   price decimal(15,2),
+                   price_date datetime,
+                   primary key(id)
+                 )",
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "utc price_date and exchange_tz",
+        //sqlite has only ever been written with UTC timestamps
+        sqlite: &[
+            r"ALTER TABLE stock_prices ADD COLUMN exchange_tz text NOT NULL DEFAULT 'UTC'",
+        ],
+        //mysql rows came from now(), so shift them out of the server's zone. CONVERT_TZ gives NULL for a
+        //named zone when the tz tables aren't loaded, so fall back to the server's current offset from UTC.
+        //A NULL exchange_tz marks a row that hasn't been shifted yet, so running this twice shifts nothing twice.
+        mysql: &[
+            r"ALTER TABLE stock_prices ADD COLUMN exchange_tz varchar(64) NULL",
+            r"UPDATE stock_prices SET exchange_tz = 'UTC', price_date = COALESCE(
+                 CONVERT_TZ(price_date, @@session.time_zone, '+00:00'),
+                 DATE_ADD(price_date, INTERVAL TIMESTAMPDIFF(SECOND, NOW(), UTC_TIMESTAMP()) SECOND))
+              WHERE exchange_tz IS NULL",
+            r"ALTER TABLE stock_prices MODIFY exchange_tz varchar(64) NOT NULL DEFAULT 'UTC'",
+        ],
+    },
+    Migration {
+        version: 3,
+        description: "watchlists and ticker info",
+        sqlite: &[
+            r"CREATE TABLE watchlist_codes
+                 ( watchlist_name text NOT NULL,
+                   company_code text NOT NULL,
+                   position integer NOT NULL,
+                   primary key(watchlist_name, company_code)
+                 )",
+            r"CREATE TABLE ticker_info
+                 ( company_code text primary key,
+                   display_name text,
+                   exchange text,
+                   notes text
+                 )",
+        ],
+        mysql: &[
+            r"CREATE TABLE watchlist_codes
+                 ( watchlist_name varchar(255) NOT NULL,
+                   company_code varchar(255) NOT NULL,
+                   position int NOT NULL,
+                   primary key(watchlist_name, company_code)
+                 )",
+            r"CREATE TABLE ticker_info
+                 ( company_code varchar(255),
+                   display_name varchar(255),
+                   exchange varchar(255),
+                   notes text,
+                   primary key(company_code)
+                 )",
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "holdings",
+        sqlite: &[
+            r"CREATE TABLE holdings
+                 ( id integer primary key autoincrement,
+                   company_code text NOT NULL,
+                   quantity text NOT NULL,
+                   buy_price text NOT NULL,
+                   buy_date text NOT NULL,
+                   fees text NOT NULL
+                 )",
+        ],
+        mysql: &[
+            r"CREATE TABLE holdings
+                 ( id bigint auto_increment,
+                   company_code varchar(255) NOT NULL,
+                   quantity decimal(20,6) NOT NULL,
+                   buy_price decimal(15,4) NOT NULL,
+                   buy_date date NOT NULL,
+                   fees decimal(15,2) NOT NULL,
+                   primary key(id)
+                 )",
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "transactions",
+        sqlite: &[
+            r"CREATE TABLE transactions
+                 ( id integer primary key autoincrement,
+                   company_code text NOT NULL,
+                   kind text NOT NULL,
+                   trade_date text NOT NULL,
+                   quantity text NOT NULL,
+                   price text NOT NULL,
+                   fees text NOT NULL
+                 )",
+        ],
+        mysql: &[
+            r"CREATE TABLE transactions
+                 ( id bigint auto_increment,
+                   company_code varchar(255) NOT NULL,
+                   kind varchar(16) NOT NULL,
+                   trade_date date NOT NULL,
+                   quantity decimal(20,6) NOT NULL,
+                   price decimal(15,4) NOT NULL,
+                   fees decimal(15,2) NOT NULL,
+                   primary key(id)
+                 )",
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "alert rules and history",
+        sqlite: &[
+            r"CREATE TABLE alert_rules
+                 ( id integer primary key autoincrement,
+                   company_code text NOT NULL,
+                   condition text NOT NULL,
+                   cooldown_minutes integer NOT NULL,
+                   last_triggered_at text
+                 )",
+            r"CREATE TABLE alert_history
+                 ( id integer primary key autoincrement,
+                   rule_id integer NOT NULL,
+                   company_code text NOT NULL,
+                   condition text NOT NULL,
+                   price text NOT NULL,
+                   triggered_at text NOT NULL,
+                   message text NOT NULL
+                 )",
+        ],
+        mysql: &[
+            r"CREATE TABLE alert_rules
+                 ( id bigint auto_increment,
+                   company_code varchar(255) NOT NULL,
+                   `condition` varchar(255) NOT NULL,
+                   cooldown_minutes bigint NOT NULL,
+                   last_triggered_at datetime,
+                   primary key(id)
+                 )",
+            r"CREATE TABLE alert_history
+                 ( id bigint auto_increment,
+                   rule_id bigint NOT NULL,
+                   company_code varchar(255) NOT NULL,
+                   `condition` varchar(255) NOT NULL,
+                   price decimal(15,4) NOT NULL,
+                   triggered_at datetime NOT NULL,
+                   message text NOT NULL,
+                   primary key(id)
+                 )",
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "quote currency",
+        //prices saved before this never said, so they stay NULL
+        sqlite: &[
+            r"ALTER TABLE stock_prices ADD COLUMN currency text",
+        ],
+        mysql: &[
+            r"ALTER TABLE stock_prices ADD COLUMN currency varchar(3)",
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "fx rates",
+        sqlite: &[
+            r"CREATE TABLE fx_rates
+                 ( base text NOT NULL,
+                   quote text NOT NULL,
+                   rate_date text NOT NULL,
+                   rate text NOT NULL,
+                   source text NOT NULL,
+                   primary key(base, quote, rate_date)
+                 )",
+        ],
+        mysql: &[
+            r"CREATE TABLE fx_rates
+                 ( base varchar(3) NOT NULL,
+                   quote varchar(3) NOT NULL,
+                   rate_date date NOT NULL,
+                   rate decimal(20,10) NOT NULL,
+                   source varchar(64) NOT NULL,
+                   primary key(base, quote, rate_date)
+                 )",
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "price source",
+        //nobody wrote down where older prices came from, so they stay NULL
+        sqlite: &[
+            r"ALTER TABLE stock_prices ADD COLUMN source text",
+        ],
+        mysql: &[
+            r"ALTER TABLE stock_prices ADD COLUMN source varchar(64)",
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "wider stock_prices.price",
+        //sqlite keeps the price as text, exactly as it was given
+        sqlite: &[],
+        //two places rounded sub-cent closes and fractional pence on the way in
+        mysql: &[
+            r"ALTER TABLE stock_prices MODIFY price decimal(20,6)",
+        ],
+    },
+];
+
+pub fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|migration| migration.version).unwrap_or(0)
+}
+
+pub fn current_version(store: &mut dyn ShareStore) -> StockResult<i64> {
+    Ok(store.applied_migrations()?
+        .iter()
+        .map(|applied| applied.version)
+        .max()
+        .unwrap_or(0))
+}
+
+pub fn pending(store: &mut dyn ShareStore) -> StockResult<Vec<&'static Migration>> {
+    let current_version = current_version(store)?;
+    Ok(MIGRATIONS.iter().filter(|migration| migration.version > current_version).collect())
+}
+
+/// Run everything we haven't run yet, handing back what was applied
+pub fn migrate(store: &mut dyn ShareStore) -> StockResult<Vec<&'static Migration>> {
+    let pending = pending(store)?;
+    for migration in &pending {
+        store.apply_migration(migration)?;
+    }
+    Ok(pending)
+}
+
+/// A brand new database gets set up on the spot, but an existing one that is behind
+/// has to be moved forward on purpose with `db migrate`
+pub fn ensure_current(store: &mut dyn ShareStore) -> StockResult<()> {
+    let current_version = current_version(store)?;
+    if current_version == 0 {
+        migrate(store)?;
+    } else if current_version < latest_version() {
+        return Err(StockError::Config(format!(
+            "Database schema is at version {} but this build needs {}, run `stockchecker db migrate`",
+            current_version, latest_version())));
+    }
+    Ok(())
+}
@@ -0,0 +1,399 @@
+use std::str::FromStr;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use rust_decimal::Decimal;
+use mysql::{OptsBuilder, params, Pool, PooledConn, Row, TxOpts};
+use mysql::prelude::*;
+
+use crate::db_model::ConnectionDetails;
+use crate::alerts::{AlertRule, TriggeredAlert};
+use crate::error::{StockError, StockResult};
+use crate::fx::FxRate;
+use crate::ledger::Transaction;
+use crate::portfolio::Holding;
+use crate::share_price_model::{parse_exchange_tz, Price, Share, ShareTimeline, TickerInfo};
+use crate::storage::migrations::{AppliedMigration, Migration};
+use crate::storage::ShareStore;
+
+/// code, price, price_date, exchange_tz, currency, source
+type ShareColumns = (String, String, NaiveDateTime, String, Option<String>, Option<String>);
+/// What mysql says when a migration's table (1050), column (1060) or index (1061) is already there
+const ALREADY_DONE_CODES: [u16; 3] = [1050, 1060, 1061];
+
+fn share_from_columns((company_code, price, price_date, exchange_tz, currency, source): ShareColumns) -> StockResult<Share> {
+    Ok(Share {
+        company_code,
+        //decimal(20,6) pads it out, so drop the trailing zeros again
+        price: Price::in_currency(parse_mysql_decimal(&price)?.normalize(), currency.and_then(|currency| currency.parse().ok())),
+        price_date,
+        exchange_tz: parse_exchange_tz(&exchange_tz),
+        source,
+    })
+}
+
+pub struct MySqlStore {
+    conn: PooledConn,
+}
+
+impl MySqlStore {
+    pub fn connect(conn_details: &ConnectionDetails) -> StockResult<MySqlStore> {
+        let builder = OptsBuilder::new()
+            .user(Some(&conn_details.username))
+            .pass(Some(&conn_details.password))
+            .db_name(Some(&conn_details.database));
+
+        let pool = Pool::new(builder)?;
+        Ok(MySqlStore { conn: pool.get_conn()? })
+    }
+}
+
+impl ShareStore for MySqlStore {
+    //Save the  current prices
+    fn save_prices(&mut self, company_prices: &[ShareTimeline]) -> StockResult<()> {
+        //insert into table
+        self.conn.exec_batch(
+            r"INSERT INTO stock_prices(company_code, price, price_date, exchange_tz, currency, source)
+                VALUES (:code, :price, :price_date, :exchange_tz, :currency, :source)",
+            company_prices
+                .iter()
+                .map(|company_time_line| params! {
+                        "code" => &company_time_line.share.company_code,
+                        "price" => company_time_line.share.price.amount.to_string(),
+                        "price_date" => company_time_line.share.price_date,
+                        "exchange_tz" => company_time_line.share.exchange_tz.name(),
+                        "currency" => company_time_line.share.price.currency.as_ref().map(ToString::to_string),
+                        "source" => &company_time_line.share.source,
+                    }
+                ))?;
+
+        Ok(())
+    }
+
+    fn load_complete_share_history(&mut self, company_code: &str) -> StockResult<Vec<Share>> {
+        let share_history: Result<Vec<ShareColumns>, _> = self.conn.exec(r"select company_code, price, price_date, exchange_tz, currency, source
+                                                            from stock_prices WHERE company_code=:code
+                                                            order by price_date, id",
+                                               params! { "code"=>company_code, });
+
+        share_history.map_err(|e| StockError::Db(format!("Error getting share history for {}: {}", company_code, e)))?
+            .into_iter()
+            .map(share_from_columns)
+            .collect()
+    }
+
+    fn load_latest_before(&mut self, company_code: &str, before: NaiveDateTime) -> StockResult<Option<Share>> {
+        let latest: Result<Option<ShareColumns>, _> = self.conn.exec_first(
+            r"SELECT company_code as code, price, price_date, exchange_tz, currency, source
+              FROM stock_prices WHERE company_code = :code
+              AND price_date < :before
+              ORDER BY price_date DESC, id DESC",
+            params! {"code"=>company_code, "before"=>before});
+        match latest {
+            Ok(Some(columns)) => share_from_columns(columns).map(Some),
+            Ok(None) => Ok(None),
+            Err(e) => Err(StockError::Db(format!("Unable to get previous company info: {}", e))),
+        }
+    }
+
+    fn priced_codes(&mut self) -> StockResult<Vec<String>> {
+        Ok(self.conn.query(r"SELECT DISTINCT company_code FROM stock_prices ORDER BY company_code")?)
+    }
+
+    fn applied_migrations(&mut self) -> StockResult<Vec<AppliedMigration>> {
+        self.conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS schema_version
+                 ( version bigint,
+                   description varchar(255),
+                   applied_at datetime,
+                   primary key(version)
+                 )"
+        )?;
+        let applied = self.conn.query_map(
+            r"SELECT version, description, applied_at FROM schema_version ORDER BY version",
+            |(version, description, applied_at)| AppliedMigration { version, description, applied_at },
+        )?;
+        Ok(applied)
+    }
+
+    fn apply_migration(&mut self, migration: &Migration) -> StockResult<()> {
+        //mysql commits DDL as it goes, so there's no wrapping this in a transaction. If the last go was cut short
+        //the whole migration runs again, and whatever it had already added is skipped.
+        for statement in migration.mysql {
+            match self.conn.query_drop(*statement) {
+                Err(mysql::Error::MySqlError(e)) if ALREADY_DONE_CODES.contains(&e.code) => {}
+                result => result.map_err(|e| StockError::Db(format!("Migration {} ({}) failed: {}", migration.version, migration.description, e)))?,
+            }
+        }
+        self.conn.exec_drop(
+            r"INSERT INTO schema_version(version, description, applied_at) VALUES (:version, :description, utc_timestamp())",
+            params! { "version" => migration.version, "description" => migration.description },
+        )?;
+        Ok(())
+    }
+
+    fn watchlist_names(&mut self) -> StockResult<Vec<(String, usize)>> {
+        let names = self.conn.query_map(
+            r"SELECT watchlist_name, COUNT(*) FROM watchlist_codes
+              GROUP BY watchlist_name ORDER BY watchlist_name",
+            |(watchlist_name, code_count): (String, u64)| (watchlist_name, code_count as usize),
+        )?;
+        Ok(names)
+    }
+
+    fn watchlist_codes(&mut self, watchlist_name: &str) -> StockResult<Vec<String>> {
+        let codes = self.conn.exec(
+            r"SELECT company_code FROM watchlist_codes WHERE watchlist_name = :name ORDER BY position",
+            params! { "name" => watchlist_name },
+        )?;
+        Ok(codes)
+    }
+
+    fn add_to_watchlist(&mut self, watchlist_name: &str, company_codes: &[&str]) -> StockResult<usize> {
+        let mut added = 0;
+        for company_code in company_codes {
+            //mysql won't insert into a table it's selecting from, hence the derived table
+            self.conn.exec_drop(
+                r"INSERT IGNORE INTO watchlist_codes(watchlist_name, company_code, position)
+                  SELECT :name, :code, next_position FROM
+                    (SELECT COALESCE(MAX(position), 0) + 1 AS next_position FROM watchlist_codes WHERE watchlist_name = :name) AS positions",
+                params! { "name" => watchlist_name, "code" => company_code },
+            )?;
+            added += self.conn.affected_rows() as usize;
+        }
+        Ok(added)
+    }
+
+    fn remove_from_watchlist(&mut self, watchlist_name: &str, company_codes: &[&str]) -> StockResult<usize> {
+        if company_codes.is_empty() {
+            self.conn.exec_drop(r"DELETE FROM watchlist_codes WHERE watchlist_name = :name", params! { "name" => watchlist_name })?;
+            return Ok(self.conn.affected_rows() as usize);
+        }
+        let mut removed = 0;
+        for company_code in company_codes {
+            self.conn.exec_drop(
+                r"DELETE FROM watchlist_codes WHERE watchlist_name = :name AND company_code = :code",
+                params! { "name" => watchlist_name, "code" => company_code },
+            )?;
+            removed += self.conn.affected_rows() as usize;
+        }
+        Ok(removed)
+    }
+
+    fn load_ticker_info(&mut self, company_code: &str) -> StockResult<Option<TickerInfo>> {
+        let ticker_info = self.conn.exec_map(
+            r"SELECT company_code, display_name, exchange, notes FROM ticker_info WHERE company_code = :code",
+            params! { "code" => company_code },
+            |(company_code, display_name, exchange, notes)| TickerInfo { company_code, display_name, exchange, notes },
+        )?;
+        Ok(ticker_info.into_iter().next())
+    }
+
+    fn save_ticker_info(&mut self, ticker_info: &TickerInfo) -> StockResult<()> {
+        self.conn.exec_drop(
+            r"INSERT INTO ticker_info(company_code, display_name, exchange, notes) VALUES (:code, :display_name, :exchange, :notes)
+              ON DUPLICATE KEY UPDATE display_name = VALUES(display_name), exchange = VALUES(exchange), notes = VALUES(notes)",
+            params! {
+                "code" => &ticker_info.company_code,
+                "display_name" => &ticker_info.display_name,
+                "exchange" => &ticker_info.exchange,
+                "notes" => &ticker_info.notes,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn add_holding(&mut self, holding: &Holding) -> StockResult<i64> {
+        self.conn.exec_drop(
+            r"INSERT INTO holdings(company_code, quantity, buy_price, buy_date, fees)
+              VALUES (:code, :quantity, :buy_price, :buy_date, :fees)",
+            params! {
+                "code" => &holding.company_code,
+                "quantity" => holding.quantity.to_string(),
+                "buy_price" => holding.buy_price.to_string(),
+                "buy_date" => holding.buy_date,
+                "fees" => holding.fees.to_string(),
+            },
+        )?;
+        Ok(self.conn.last_insert_id() as i64)
+    }
+
+    fn load_holdings(&mut self) -> StockResult<Vec<Holding>> {
+        //decimals come back as strings so nothing gets rounded through a float on the way
+        let rows: Vec<(i64, String, String, String, NaiveDate, String)> = self.conn.query(
+            r"SELECT id, company_code, quantity, buy_price, buy_date, fees FROM holdings ORDER BY buy_date, id")?;
+        rows.into_iter()
+            .map(|(id, company_code, quantity, buy_price, buy_date, fees)| Ok(Holding {
+                id: Some(id),
+                company_code,
+                quantity: parse_mysql_decimal(&quantity)?,
+                buy_price: parse_mysql_decimal(&buy_price)?,
+                buy_date,
+                fees: parse_mysql_decimal(&fees)?,
+            }))
+            .collect()
+    }
+
+    fn remove_holding(&mut self, holding_id: i64) -> StockResult<bool> {
+        self.conn.exec_drop(r"DELETE FROM holdings WHERE id = :id", params! { "id" => holding_id })?;
+        Ok(self.conn.affected_rows() > 0)
+    }
+
+    fn add_transactions(&mut self, transactions: &[Transaction]) -> StockResult<usize> {
+        let mut tx = self.conn.start_transaction(TxOpts::default())?;
+        tx.exec_batch(
+            r"INSERT INTO transactions(company_code, kind, trade_date, quantity, price, fees)
+              VALUES (:code, :kind, :trade_date, :quantity, :price, :fees)",
+            transactions.iter().map(|transaction| params! {
+                "code" => &transaction.company_code,
+                "kind" => transaction.kind.to_string(),
+                "trade_date" => transaction.trade_date,
+                "quantity" => transaction.quantity.to_string(),
+                "price" => transaction.price.to_string(),
+                "fees" => transaction.fees.to_string(),
+            }),
+        )?;
+        tx.commit()?;
+        Ok(transactions.len())
+    }
+
+    fn load_transactions(&mut self) -> StockResult<Vec<Transaction>> {
+        let rows: Vec<Row> = self.conn.query(
+            r"SELECT id, company_code, kind, trade_date, quantity, price, fees FROM transactions ORDER BY trade_date, id")?;
+        rows.into_iter()
+            .map(|mut row| {
+                let kind: String = take_column(&mut row, 2)?;
+                Ok(Transaction {
+                    id: Some(take_column(&mut row, 0)?),
+                    company_code: take_column(&mut row, 1)?,
+                    kind: kind.parse().map_err(StockError::Db)?,
+                    trade_date: take_column(&mut row, 3)?,
+                    quantity: parse_mysql_decimal(&take_column::<String>(&mut row, 4)?)?,
+                    price: parse_mysql_decimal(&take_column::<String>(&mut row, 5)?)?,
+                    fees: parse_mysql_decimal(&take_column::<String>(&mut row, 6)?)?,
+                })
+            })
+            .collect()
+    }
+
+    fn add_alert_rule(&mut self, rule: &AlertRule) -> StockResult<i64> {
+        self.conn.exec_drop(
+            r"INSERT INTO alert_rules(company_code, `condition`, cooldown_minutes, last_triggered_at)
+              VALUES (:code, :condition, :cooldown_minutes, :last_triggered_at)",
+            params! {
+                "code" => &rule.company_code,
+                "condition" => rule.condition.to_string(),
+                "cooldown_minutes" => rule.cooldown.num_minutes(),
+                "last_triggered_at" => rule.last_triggered,
+            },
+        )?;
+        Ok(self.conn.last_insert_id() as i64)
+    }
+
+    fn load_alert_rules(&mut self) -> StockResult<Vec<AlertRule>> {
+        let rows: Vec<Row> = self.conn.query(
+            r"SELECT id, company_code, `condition`, cooldown_minutes, last_triggered_at FROM alert_rules ORDER BY id")?;
+        rows.into_iter()
+            .map(|mut row| {
+                let condition: String = take_column(&mut row, 2)?;
+                Ok(AlertRule {
+                    id: Some(take_column(&mut row, 0)?),
+                    company_code: take_column(&mut row, 1)?,
+                    condition: condition.parse().map_err(StockError::Db)?,
+                    cooldown: Duration::minutes(take_column(&mut row, 3)?),
+                    last_triggered: take_column(&mut row, 4)?,
+                })
+            })
+            .collect()
+    }
+
+    fn remove_alert_rule(&mut self, rule_id: i64) -> StockResult<bool> {
+        self.conn.exec_drop(r"DELETE FROM alert_rules WHERE id = :id", params! { "id" => rule_id })?;
+        Ok(self.conn.affected_rows() > 0)
+    }
+
+    fn record_alert(&mut self, alert: &TriggeredAlert) -> StockResult<()> {
+        let mut tx = self.conn.start_transaction(TxOpts::default())?;
+        tx.exec_drop(
+            r"INSERT INTO alert_history(rule_id, company_code, `condition`, price, triggered_at, message)
+              VALUES (:rule_id, :code, :condition, :price, :triggered_at, :message)",
+            params! {
+                "rule_id" => alert.rule_id,
+                "code" => &alert.company_code,
+                "condition" => &alert.condition,
+                "price" => alert.price.to_string(),
+                "triggered_at" => alert.triggered_at,
+                "message" => &alert.message,
+            },
+        )?;
+        tx.exec_drop(
+            r"UPDATE alert_rules SET last_triggered_at = :triggered_at WHERE id = :rule_id",
+            params! { "triggered_at" => alert.triggered_at, "rule_id" => alert.rule_id },
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_alert_history(&mut self, limit: usize) -> StockResult<Vec<TriggeredAlert>> {
+        let rows: Vec<Row> = self.conn.exec(
+            r"SELECT rule_id, company_code, `condition`, price, triggered_at, message
+              FROM alert_history ORDER BY triggered_at DESC, id DESC LIMIT :limit",
+            params! { "limit" => limit as u64 },
+        )?;
+        rows.into_iter()
+            .map(|mut row| Ok(TriggeredAlert {
+                rule_id: take_column(&mut row, 0)?,
+                company_code: take_column(&mut row, 1)?,
+                condition: take_column(&mut row, 2)?,
+                price: parse_mysql_decimal(&take_column::<String>(&mut row, 3)?)?,
+                triggered_at: take_column(&mut row, 4)?,
+                message: take_column(&mut row, 5)?,
+                movement: None,
+            }))
+            .collect()
+    }
+
+    fn save_fx_rates(&mut self, fx_rates: &[FxRate]) -> StockResult<usize> {
+        let mut tx = self.conn.start_transaction(TxOpts::default())?;
+        tx.exec_batch(
+            r"REPLACE INTO fx_rates(base, quote, rate_date, rate, source) VALUES (:base, :quote, :rate_date, :rate, :source)",
+            fx_rates.iter().map(|fx_rate| params! {
+                "base" => fx_rate.base.to_string(),
+                "quote" => fx_rate.quote.to_string(),
+                "rate_date" => fx_rate.rate_date,
+                "rate" => fx_rate.rate.to_string(),
+                "source" => &fx_rate.source,
+            }),
+        )?;
+        tx.commit()?;
+        Ok(fx_rates.len())
+    }
+
+    fn load_fx_rates(&mut self) -> StockResult<Vec<FxRate>> {
+        let rows: Vec<Row> = self.conn.query(
+            r"SELECT base, quote, rate_date, rate, source FROM fx_rates ORDER BY base, quote, rate_date")?;
+        rows.into_iter()
+            .map(|mut row| {
+                let base: String = take_column(&mut row, 0)?;
+                let quote: String = take_column(&mut row, 1)?;
+                Ok(FxRate {
+                    base: base.parse().map_err(StockError::Db)?,
+                    quote: quote.parse().map_err(StockError::Db)?,
+                    rate_date: take_column(&mut row, 2)?,
+                    //decimal(20,10) pads it out, so drop the trailing zeros again
+                    rate: parse_mysql_decimal(&take_column::<String>(&mut row, 3)?)?.normalize(),
+                    source: take_column(&mut row, 4)?,
+                })
+            })
+            .collect()
+    }
+}
+
+fn take_column<T: FromValue>(row: &mut Row, idx: usize) -> StockResult<T> {
+    row.take_opt(idx)
+        .ok_or_else(|| StockError::Db(format!("Missing column {}", idx)))?
+        .map_err(|e| StockError::Db(e.to_string()))
+}
+
+fn parse_mysql_decimal(value: &str) -> StockResult<Decimal> {
+    Decimal::from_str(value).map_err(|e| StockError::Db(format!("Couldn't read '{}' as a number: {}", value, e)))
+}
@@ -0,0 +1,395 @@
+use std::str::FromStr;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, Utc};
+use rust_decimal::Decimal;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::alerts::{AlertRule, TriggeredAlert};
+use crate::error::{StockError, StockResult};
+use crate::fx::FxRate;
+use crate::ledger::Transaction;
+use crate::portfolio::Holding;
+use crate::share_price_model::{parse_exchange_tz, Price, Share, ShareTimeline, TickerInfo};
+use crate::storage::migrations::{AppliedMigration, Migration};
+use crate::storage::ShareStore;
+
+const SQLITE_DATE_FMT: &str = "%Y-%m-%d %H:%M:%S";
+const SQLITE_DAY_FMT: &str = "%Y-%m-%d";
+
+/// Keeps everything in a single local file, no server needed
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &str) -> StockResult<SqliteStore> {
+        let conn = Connection::open(path)
+            .map_err(|e| StockError::Db(format!("Couldn't open sqlite database {}: {}", path, e)))?;
+        Ok(SqliteStore { conn })
+    }
+}
+
+fn parse_sqlite_date(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<NaiveDateTime> {
+    let date: String = row.get(idx)?;
+    NaiveDateTime::parse_from_str(&date, SQLITE_DATE_FMT)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+fn parse_sqlite_day(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<NaiveDate> {
+    let day: String = row.get(idx)?;
+    NaiveDate::parse_from_str(&day, SQLITE_DAY_FMT)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+/// Decimals live in sqlite as text so they come back exactly as they went in
+fn parse_sqlite_decimal(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<Decimal> {
+    let value: String = row.get(idx)?;
+    Decimal::from_str(&value)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+fn share_from_row(row: &rusqlite::Row) -> rusqlite::Result<Share> {
+    let exchange_tz: String = row.get(3)?;
+    let currency: Option<String> = row.get(4)?;
+    Ok(Share {
+        company_code: row.get(0)?,
+        price: Price::in_currency(parse_sqlite_decimal(row, 1)?, currency.and_then(|currency| currency.parse().ok())),
+        price_date: parse_sqlite_date(row, 2)?,
+        exchange_tz: parse_exchange_tz(&exchange_tz),
+        source: row.get(5)?,
+    })
+}
+
+impl ShareStore for SqliteStore {
+    fn save_prices(&mut self, company_prices: &[ShareTimeline]) -> StockResult<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                r"INSERT INTO stock_prices(company_code, price, price_date, exchange_tz, currency, source)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            for company_time_line in company_prices {
+                let share = &company_time_line.share;
+                insert.execute(params![
+                    share.company_code,
+                    share.price.amount.to_string(),
+                    share.price_date.format(SQLITE_DATE_FMT).to_string(),
+                    share.exchange_tz.name(),
+                    share.price.currency.as_ref().map(ToString::to_string),
+                    share.source,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_complete_share_history(&mut self, company_code: &str) -> StockResult<Vec<Share>> {
+        let mut select = self.conn.prepare(
+            r"SELECT company_code, price, price_date, exchange_tz, currency, source
+              FROM stock_prices WHERE company_code = ?1
+              ORDER BY price_date, id")?;
+        let share_history = select.query_map(params![company_code], share_from_row)?
+            .collect::<rusqlite::Result<Vec<Share>>>();
+        share_history.map_err(|e| StockError::Db(format!("Error getting share history for {}: {}", company_code, e)))
+    }
+
+    fn load_latest_before(&mut self, company_code: &str, before: NaiveDateTime) -> StockResult<Option<Share>> {
+        self.conn.query_row(
+            r"SELECT company_code, price, price_date, exchange_tz, currency, source
+              FROM stock_prices WHERE company_code = ?1
+              AND price_date < ?2
+              ORDER BY price_date DESC, id DESC LIMIT 1",
+            params![company_code, before.format(SQLITE_DATE_FMT).to_string()],
+            share_from_row,
+        ).optional()
+            .map_err(|e| StockError::Db(format!("Unable to get previous company info: {}", e)))
+    }
+
+    fn priced_codes(&mut self) -> StockResult<Vec<String>> {
+        let mut select = self.conn.prepare(r"SELECT DISTINCT company_code FROM stock_prices ORDER BY company_code")?;
+        let company_codes = select.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(company_codes)
+    }
+
+    fn applied_migrations(&mut self) -> StockResult<Vec<AppliedMigration>> {
+        self.conn.execute_batch(
+            r"CREATE TABLE IF NOT EXISTS schema_version
+                 ( version integer primary key,
+                   description text,
+                   applied_at text
+                 );"
+        )?;
+        let mut select = self.conn.prepare(
+            r"SELECT version, description, applied_at FROM schema_version ORDER BY version")?;
+        let applied = select.query_map([], |row| Ok(AppliedMigration {
+            version: row.get(0)?,
+            description: row.get(1)?,
+            applied_at: parse_sqlite_date(row, 2)?,
+        }))?.collect::<rusqlite::Result<Vec<AppliedMigration>>>()?;
+        Ok(applied)
+    }
+
+    fn apply_migration(&mut self, migration: &Migration) -> StockResult<()> {
+        let tx = self.conn.transaction()?;
+        for statement in migration.sqlite {
+            tx.execute_batch(statement)
+                .map_err(|e| StockError::Db(format!("Migration {} ({}) failed: {}", migration.version, migration.description, e)))?;
+        }
+        tx.execute(
+            r"INSERT INTO schema_version(version, description, applied_at) VALUES (?1, ?2, ?3)",
+            params![migration.version, migration.description, Utc::now().naive_utc().format(SQLITE_DATE_FMT).to_string()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn watchlist_names(&mut self) -> StockResult<Vec<(String, usize)>> {
+        let mut select = self.conn.prepare(
+            r"SELECT watchlist_name, COUNT(*) FROM watchlist_codes
+              GROUP BY watchlist_name ORDER BY watchlist_name")?;
+        let names = select.query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?
+            .collect::<rusqlite::Result<Vec<(String, usize)>>>()?;
+        Ok(names)
+    }
+
+    fn watchlist_codes(&mut self, watchlist_name: &str) -> StockResult<Vec<String>> {
+        let mut select = self.conn.prepare(
+            r"SELECT company_code FROM watchlist_codes WHERE watchlist_name = ?1 ORDER BY position")?;
+        let codes = select.query_map(params![watchlist_name], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(codes)
+    }
+
+    fn add_to_watchlist(&mut self, watchlist_name: &str, company_codes: &[&str]) -> StockResult<usize> {
+        let tx = self.conn.transaction()?;
+        let mut added = 0;
+        {
+            let mut insert = tx.prepare(
+                r"INSERT OR IGNORE INTO watchlist_codes(watchlist_name, company_code, position)
+                  SELECT ?1, ?2, COALESCE(MAX(position), 0) + 1 FROM watchlist_codes WHERE watchlist_name = ?1")?;
+            for company_code in company_codes {
+                added += insert.execute(params![watchlist_name, company_code])?;
+            }
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+
+    fn remove_from_watchlist(&mut self, watchlist_name: &str, company_codes: &[&str]) -> StockResult<usize> {
+        if company_codes.is_empty() {
+            return Ok(self.conn.execute(r"DELETE FROM watchlist_codes WHERE watchlist_name = ?1", params![watchlist_name])?);
+        }
+        let tx = self.conn.transaction()?;
+        let mut removed = 0;
+        for company_code in company_codes {
+            removed += tx.execute(
+                r"DELETE FROM watchlist_codes WHERE watchlist_name = ?1 AND company_code = ?2",
+                params![watchlist_name, company_code])?;
+        }
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    fn load_ticker_info(&mut self, company_code: &str) -> StockResult<Option<TickerInfo>> {
+        Ok(self.conn.query_row(
+            r"SELECT company_code, display_name, exchange, notes FROM ticker_info WHERE company_code = ?1",
+            params![company_code],
+            |row| Ok(TickerInfo {
+                company_code: row.get(0)?,
+                display_name: row.get(1)?,
+                exchange: row.get(2)?,
+                notes: row.get(3)?,
+            }),
+        ).optional()?)
+    }
+
+    fn save_ticker_info(&mut self, ticker_info: &TickerInfo) -> StockResult<()> {
+        self.conn.execute(
+            r"INSERT INTO ticker_info(company_code, display_name, exchange, notes) VALUES (?1, ?2, ?3, ?4)
+              ON CONFLICT(company_code) DO UPDATE SET
+                display_name = excluded.display_name, exchange = excluded.exchange, notes = excluded.notes",
+            params![ticker_info.company_code, ticker_info.display_name, ticker_info.exchange, ticker_info.notes],
+        )?;
+        Ok(())
+    }
+
+    fn add_holding(&mut self, holding: &Holding) -> StockResult<i64> {
+        self.conn.execute(
+            r"INSERT INTO holdings(company_code, quantity, buy_price, buy_date, fees) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                holding.company_code,
+                holding.quantity.to_string(),
+                holding.buy_price.to_string(),
+                holding.buy_date.format(SQLITE_DAY_FMT).to_string(),
+                holding.fees.to_string(),
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    fn load_holdings(&mut self) -> StockResult<Vec<Holding>> {
+        let mut select = self.conn.prepare(
+            r"SELECT id, company_code, quantity, buy_price, buy_date, fees
+              FROM holdings ORDER BY buy_date, id")?;
+        let holdings = select.query_map([], |row| Ok(Holding {
+            id: Some(row.get(0)?),
+            company_code: row.get(1)?,
+            quantity: parse_sqlite_decimal(row, 2)?,
+            buy_price: parse_sqlite_decimal(row, 3)?,
+            buy_date: parse_sqlite_day(row, 4)?,
+            fees: parse_sqlite_decimal(row, 5)?,
+        }))?.collect::<rusqlite::Result<Vec<Holding>>>()?;
+        Ok(holdings)
+    }
+
+    fn remove_holding(&mut self, holding_id: i64) -> StockResult<bool> {
+        Ok(self.conn.execute(r"DELETE FROM holdings WHERE id = ?1", params![holding_id])? > 0)
+    }
+
+    fn add_transactions(&mut self, transactions: &[Transaction]) -> StockResult<usize> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                r"INSERT INTO transactions(company_code, kind, trade_date, quantity, price, fees)
+                  VALUES (?1, ?2, ?3, ?4, ?5, ?6)")?;
+            for transaction in transactions {
+                insert.execute(params![
+                    transaction.company_code,
+                    transaction.kind.to_string(),
+                    transaction.trade_date.format(SQLITE_DAY_FMT).to_string(),
+                    transaction.quantity.to_string(),
+                    transaction.price.to_string(),
+                    transaction.fees.to_string(),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(transactions.len())
+    }
+
+    fn load_transactions(&mut self) -> StockResult<Vec<Transaction>> {
+        let mut select = self.conn.prepare(
+            r"SELECT id, company_code, kind, trade_date, quantity, price, fees
+              FROM transactions ORDER BY trade_date, id")?;
+        let transactions = select.query_map([], |row| {
+            let kind: String = row.get(2)?;
+            Ok(Transaction {
+                id: Some(row.get(0)?),
+                company_code: row.get(1)?,
+                kind: kind.parse()
+                    .map_err(|e: String| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, e.into()))?,
+                trade_date: parse_sqlite_day(row, 3)?,
+                quantity: parse_sqlite_decimal(row, 4)?,
+                price: parse_sqlite_decimal(row, 5)?,
+                fees: parse_sqlite_decimal(row, 6)?,
+            })
+        })?.collect::<rusqlite::Result<Vec<Transaction>>>()?;
+        Ok(transactions)
+    }
+
+    fn add_alert_rule(&mut self, rule: &AlertRule) -> StockResult<i64> {
+        self.conn.execute(
+            r"INSERT INTO alert_rules(company_code, condition, cooldown_minutes, last_triggered_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                rule.company_code,
+                rule.condition.to_string(),
+                rule.cooldown.num_minutes(),
+                rule.last_triggered.map(|last_triggered| last_triggered.format(SQLITE_DATE_FMT).to_string()),
+            ],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    fn load_alert_rules(&mut self) -> StockResult<Vec<AlertRule>> {
+        let mut select = self.conn.prepare(
+            r"SELECT id, company_code, condition, cooldown_minutes, last_triggered_at FROM alert_rules ORDER BY id")?;
+        let rules = select.query_map([], |row| {
+            let condition: String = row.get(2)?;
+            let last_triggered: Option<String> = row.get(4)?;
+            Ok(AlertRule {
+                id: Some(row.get(0)?),
+                company_code: row.get(1)?,
+                condition: condition.parse()
+                    .map_err(|e: String| rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, e.into()))?,
+                cooldown: Duration::minutes(row.get(3)?),
+                last_triggered: match last_triggered {
+                    Some(_) => Some(parse_sqlite_date(row, 4)?),
+                    None => None,
+                },
+            })
+        })?.collect::<rusqlite::Result<Vec<AlertRule>>>()?;
+        Ok(rules)
+    }
+
+    fn remove_alert_rule(&mut self, rule_id: i64) -> StockResult<bool> {
+        Ok(self.conn.execute(r"DELETE FROM alert_rules WHERE id = ?1", params![rule_id])? > 0)
+    }
+
+    fn record_alert(&mut self, alert: &TriggeredAlert) -> StockResult<()> {
+        let triggered_at = alert.triggered_at.format(SQLITE_DATE_FMT).to_string();
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            r"INSERT INTO alert_history(rule_id, company_code, condition, price, triggered_at, message)
+              VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![alert.rule_id, alert.company_code, alert.condition, alert.price.to_string(), triggered_at, alert.message],
+        )?;
+        tx.execute(r"UPDATE alert_rules SET last_triggered_at = ?1 WHERE id = ?2", params![triggered_at, alert.rule_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_alert_history(&mut self, limit: usize) -> StockResult<Vec<TriggeredAlert>> {
+        let mut select = self.conn.prepare(
+            r"SELECT rule_id, company_code, condition, price, triggered_at, message
+              FROM alert_history ORDER BY triggered_at DESC, id DESC LIMIT ?1")?;
+        let history = select.query_map(params![limit as i64], |row| Ok(TriggeredAlert {
+            rule_id: row.get(0)?,
+            company_code: row.get(1)?,
+            condition: row.get(2)?,
+            price: parse_sqlite_decimal(row, 3)?,
+            triggered_at: parse_sqlite_date(row, 4)?,
+            message: row.get(5)?,
+            movement: None,
+        }))?.collect::<rusqlite::Result<Vec<TriggeredAlert>>>()?;
+        Ok(history)
+    }
+
+    fn save_fx_rates(&mut self, fx_rates: &[FxRate]) -> StockResult<usize> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare(
+                r"INSERT OR REPLACE INTO fx_rates(base, quote, rate_date, rate, source) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+            for fx_rate in fx_rates {
+                insert.execute(params![
+                    fx_rate.base.to_string(),
+                    fx_rate.quote.to_string(),
+                    fx_rate.rate_date.format(SQLITE_DAY_FMT).to_string(),
+                    fx_rate.rate.to_string(),
+                    fx_rate.source,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(fx_rates.len())
+    }
+
+    fn load_fx_rates(&mut self) -> StockResult<Vec<FxRate>> {
+        let mut select = self.conn.prepare(
+            r"SELECT base, quote, rate_date, rate, source FROM fx_rates ORDER BY base, quote, rate_date")?;
+        let fx_rates = select.query_map([], |row| {
+            let currency = |idx: usize| -> rusqlite::Result<_> {
+                let currency: String = row.get(idx)?;
+                currency.parse()
+                    .map_err(|e: String| rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, e.into()))
+            };
+            Ok(FxRate {
+                base: currency(0)?,
+                quote: currency(1)?,
+                rate_date: parse_sqlite_day(row, 2)?,
+                rate: parse_sqlite_decimal(row, 3)?,
+                source: row.get(4)?,
+            })
+        })?.collect::<rusqlite::Result<Vec<FxRate>>>()?;
+        Ok(fx_rates)
+    }
+}
@@ -0,0 +1,493 @@
+use std::collections::HashMap;
+use std::io::{self, Stdout};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+use rust_decimal::Decimal;
+use termion::event::Key;
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::AlternateScreen;
+use tui::Terminal;
+use tui::backend::TermionBackend;
+use tui::layout::{Constraint, Direction, Layout};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Paragraph};
+
+use crate::alerts::TriggeredAlert;
+use crate::error::{StockError, StockResult};
+use crate::notifier::{Delivery, DeliveryOutcome};
+use crate::share_price_model::{FetchOutcome, Movement, ShareMoment, ShareTimeline};
+use crate::util::event::{Config, Event, Events};
+
+type WatchTerminal = Terminal<TermionBackend<AlternateScreen<RawTerminal<Stdout>>>>;
+
+/// How long a changed cell stays lit up after a refresh
+const FLASH_FOR: Duration = Duration::from_secs(2);
+const TICK_RATE: Duration = Duration::from_millis(100);
+const CELL_DATE_FMT: &str = "%Y-%m-%d %H:%M";
+
+/// What the caller should do next
+pub enum WatchCommand {
+    Refresh,
+    Quit,
+}
+
+/// One cell of the quote table. Cells with a value flash when it changes between refreshes.
+struct WatchCell {
+    text: String,
+    value: Option<Decimal>,
+    color: Color,
+}
+
+impl WatchCell {
+    fn plain(text: &str) -> WatchCell {
+        WatchCell { text: text.to_string(), value: None, color: Color::Reset }
+    }
+
+    fn number(text: String, value: Decimal, color: Color) -> WatchCell {
+        WatchCell { text, value: Some(value), color }
+    }
+}
+
+/// Which way a cell moved on the last refresh, and when
+#[derive(Debug, Clone, Copy)]
+struct Flash {
+    up: bool,
+    at: Instant,
+}
+
+/// Full screen table of quotes that the caller refreshes whenever `next_command` asks it to
+pub struct WatchScreen {
+    terminal: WatchTerminal,
+    events: Events,
+    table: WatchTable,
+    /// Alerts are delivered in the background, so a slow target doesn't hold up the screen
+    delivery_tx: mpsc::Sender<Vec<Delivery>>,
+    delivery_rx: mpsc::Receiver<Vec<Delivery>>,
+}
+
+/// What the screen shows and when it's next due a refresh, kept apart from the terminal
+struct WatchTable {
+    moments: Vec<ShareMoment>,
+    interval: Duration,
+    rows: Vec<(String, Vec<WatchCell>)>,
+    /// Keyed by code and column
+    flashes: HashMap<(String, usize), Flash>,
+    last_refresh: Option<Instant>,
+    updated_at: Option<DateTime<Local>>,
+    paused: bool,
+    refresh_requested: bool,
+    refreshing: bool,
+    saved_note: String,
+    /// Codes with an alert from the last refresh, and what the latest one said
+    alerting: Vec<String>,
+    alert_note: String,
+    /// Rates that couldn't be fetched or found on the last refresh
+    fx_note: String,
+}
+
+impl WatchScreen {
+    pub fn new(moments: &[ShareMoment], interval: Duration) -> StockResult<WatchScreen> {
+        let stdout = io::stdout().into_raw_mode().map_err(render_error)?;
+        let stdout = AlternateScreen::from(stdout);
+        let terminal = Terminal::new(TermionBackend::new(stdout)).map_err(render_error)?;
+        let (delivery_tx, delivery_rx) = mpsc::channel();
+        Ok(WatchScreen {
+            terminal,
+            events: Events::with_config(Config {
+                tick_rate: TICK_RATE,
+                ..Config::default()
+            }),
+            table: WatchTable::new(moments, interval),
+            delivery_tx,
+            delivery_rx,
+        })
+    }
+
+    /// Sits on the keyboard and the clock, redrawing as it goes, until it's time to refresh or quit
+    pub fn next_command(&mut self) -> StockResult<WatchCommand> {
+        loop {
+            if self.table.refresh_due(Instant::now()) {
+                self.table.refreshing = true;
+                self.draw()?;
+                return Ok(WatchCommand::Refresh);
+            }
+            match self.events.next().map_err(render_error)? {
+                Event::Input(Key::Char('q')) => return Ok(WatchCommand::Quit),
+                Event::Input(Key::Char('p')) | Event::Input(Key::Char(' ')) => self.table.paused = !self.table.paused,
+                Event::Input(Key::Char('r')) => self.table.refresh_requested = true,
+                Event::Input(_) => {}
+                Event::Tick => {
+                    for deliveries in self.delivery_rx.try_iter() {
+                        self.table.note_deliveries(&deliveries);
+                    }
+                    self.draw()?
+                }
+            }
+        }
+    }
+
+    /// Take in the latest fetch, lighting up whatever moved since the last one and any code that set off an alert
+    pub fn update(&mut self, outcomes: &[FetchOutcome], alerts: &[TriggeredAlert]) {
+        self.table.update(outcomes, alerts);
+    }
+
+    /// Where to send deliveries once they're done, they get tacked onto the alert note on the next tick
+    pub fn delivery_sender(&self) -> mpsc::Sender<Vec<Delivery>> {
+        self.delivery_tx.clone()
+    }
+
+    /// Shown on the status line until the next save
+    pub fn note_saved(&mut self, saved_count: usize) {
+        self.table.note_saved(saved_count);
+    }
+
+    /// Shown on the status line until a refresh comes back without any
+    pub fn note_fx_problems(&mut self, problems: &[String]) {
+        self.table.note_fx_problems(problems);
+    }
+
+    fn draw(&mut self) -> StockResult<()> {
+        let lines = self.table.lines();
+        let status = self.table.status_line(Instant::now());
+        self.terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(1)].as_ref())
+                .split(f.size());
+            let table = Paragraph::new(lines)
+                .block(Block::default().title("Watching").borders(Borders::ALL));
+            f.render_widget(table, chunks[0]);
+            f.render_widget(Paragraph::new(Span::styled(status, Style::default().fg(Color::Gray))), chunks[1]);
+        }).map_err(render_error)?;
+        Ok(())
+    }
+}
+
+impl WatchTable {
+    fn new(moments: &[ShareMoment], interval: Duration) -> WatchTable {
+        WatchTable {
+            moments: moments.to_vec(),
+            interval,
+            rows: Vec::new(),
+            flashes: HashMap::new(),
+            last_refresh: None,
+            updated_at: None,
+            paused: false,
+            refresh_requested: false,
+            refreshing: false,
+            saved_note: String::new(),
+            alerting: Vec::new(),
+            alert_note: String::new(),
+            fx_note: String::new(),
+        }
+    }
+
+    fn update(&mut self, outcomes: &[FetchOutcome], alerts: &[TriggeredAlert]) {
+        let now = Instant::now();
+        let rows: Vec<(String, Vec<WatchCell>)> = outcomes.iter()
+            .map(|outcome| match outcome {
+                FetchOutcome::Found(share_timeline) => (share_timeline.share.company_code.clone(), self.quote_cells(share_timeline)),
+                _ => {
+                    let (company_code, reason) = outcome.failure().unwrap_or_default();
+                    let reason = format!("{}: {}", outcome.failure_kind().unwrap_or_default().to_lowercase(), reason);
+                    (company_code.to_string(), failure_cells(company_code, &reason))
+                }
+            })
+            .collect();
+
+        for (company_code, cells) in &rows {
+            for (column, cell) in cells.iter().enumerate() {
+                let previous = self.cell_value(company_code, column);
+                if let (Some(previous), Some(current)) = (previous, cell.value) {
+                    if current != previous {
+                        self.flashes.insert((company_code.clone(), column), Flash { up: current > previous, at: now });
+                    }
+                }
+            }
+        }
+
+        self.alerting = alerts.iter().map(|alert| alert.company_code.clone()).collect();
+        if let Some(alert) = alerts.last() {
+            self.alert_note = format!("ALERT {} {}", alert.company_code, alert.message);
+        }
+        self.rows = rows;
+        self.last_refresh = Some(now);
+        self.updated_at = Some(Local::now());
+        self.refresh_requested = false;
+        self.refreshing = false;
+    }
+
+    fn note_deliveries(&mut self, deliveries: &[Delivery]) {
+        let failed = deliveries.iter().filter(|delivery| matches!(delivery.outcome, DeliveryOutcome::Failed { .. })).count();
+        if !deliveries.is_empty() {
+            self.alert_note = format!("{} (sent {}/{})", self.alert_note, deliveries.len() - failed, deliveries.len());
+        }
+    }
+
+    fn note_saved(&mut self, saved_count: usize) {
+        self.saved_note = format!("saved {} prices", saved_count);
+    }
+
+    /// Only the first fits on the line, the rest are counted
+    fn note_fx_problems(&mut self, problems: &[String]) {
+        self.fx_note = match problems {
+            [] => String::new(),
+            [problem] => format!("FX {}", problem),
+            [problem, rest @ ..] => format!("FX {} (+{} more)", problem, rest.len()),
+        };
+    }
+
+    fn refresh_due(&self, now: Instant) -> bool {
+        match self.last_refresh {
+            None => true,
+            Some(_) if self.refresh_requested => true,
+            Some(_) if self.paused => false,
+            Some(last_refresh) => now.saturating_duration_since(last_refresh) >= self.interval,
+        }
+    }
+
+    fn cell_value(&self, company_code: &str, column: usize) -> Option<Decimal> {
+        self.rows.iter()
+            .find(|(code, _)| code == company_code)
+            .and_then(|(_, cells)| cells.get(column))
+            .and_then(|cell| cell.value)
+    }
+
+    /// The same columns the quote table prints
+    fn quote_cells(&self, share_timeline: &ShareTimeline) -> Vec<WatchCell> {
+        let share = &share_timeline.share;
+        let mut cells = vec![
+            WatchCell::plain(&share.company_code),
+            WatchCell::number(share.price.to_string(), share.price.amount, Color::LightBlue),
+            WatchCell::plain(&share.price.currency_label()),
+            WatchCell::plain(&share.price_date.format(CELL_DATE_FMT).to_string()),
+        ];
+        for moment in &self.moments {
+            match share_timeline.share_history.get(moment) {
+                Some(share_history) => match Movement::between(share_history, share) {
+                    Some(movement) => {
+                        let movement_color = if movement.is_down() { Color::Red } else { Color::Green };
+                        cells.push(WatchCell::number(share_history.price.to_string(), share_history.price.amount, Color::LightBlue));
+                        cells.push(WatchCell::plain(&share_history.price_date.format(CELL_DATE_FMT).to_string()));
+                        cells.push(WatchCell::number(share.price.shown(movement.absolute).to_string(), movement.absolute, movement_color));
+                        cells.push(WatchCell::number(movement.percent_string(), movement.percent, movement_color));
+                    }
+                    //not the same money, so nothing to work out
+                    None => {
+                        cells.push(WatchCell::plain(&format!("{} {}", share_history.price, share_history.price.currency_label())));
+                        cells.push(WatchCell::plain(&share_history.price_date.format(CELL_DATE_FMT).to_string()));
+                        cells.push(WatchCell { text: format!("{} vs {}", share_history.price.currency_label(), share.price.currency_label()), value: None, color: Color::Red });
+                        cells.push(WatchCell::plain("---"));
+                    }
+                },
+                None => cells.extend((0..4).map(|_| WatchCell::plain("---"))),
+            }
+        }
+        cells
+    }
+
+    fn header(&self) -> Vec<String> {
+        let mut header = vec!["CODE".to_string(), "PRICE".to_string(), "CCY".to_string(), "TIME".to_string()];
+        for moment in &self.moments {
+            header.push(format!("{} PRICE", moment));
+            header.push(format!("{} DATE", moment));
+            header.push(format!("{} MOVEMENT", moment));
+            header.push("%".to_string());
+        }
+        header
+    }
+
+    fn status_line(&self, now: Instant) -> String {
+        let state = if self.refreshing {
+            "Refreshing...".to_string()
+        } else if self.paused {
+            "PAUSED".to_string()
+        } else {
+            let next_in = self.last_refresh
+                .map(|last_refresh| self.interval.saturating_sub(now.saturating_duration_since(last_refresh)))
+                .unwrap_or_default();
+            //rounded up, so it only says 0s once it's due
+            let next_in_secs = next_in.as_secs() + u64::from(next_in.subsec_nanos() > 0);
+            format!("Next refresh in {}s", next_in_secs)
+        };
+        let last = self.updated_at
+            .map(|updated_at| format!("  Updated {}", updated_at.format("%H:%M:%S")))
+            .unwrap_or_default();
+        format!("{}{}  {}  {}  {}  p: pause  r: refresh now  q: quit", state, last, self.saved_note, self.alert_note, self.fx_note)
+    }
+
+    /// The header and a line per code, each cell padded out to its column
+    fn lines(&self) -> Vec<Spans<'static>> {
+        let header = self.header();
+        let rows: Vec<(&str, &[WatchCell])> = self.rows.iter()
+            .map(|(company_code, cells)| (company_code.as_str(), cells.as_slice()))
+            .collect();
+
+        //every column as wide as its widest cell
+        let mut widths: Vec<usize> = header.iter().map(|title| title.chars().count()).collect();
+        //failure rows just run on, they don't get to stretch the columns
+        for (_, cells) in rows.iter().filter(|(_, cells)| cells.len() == header.len()) {
+            for (column, cell) in cells.iter().enumerate() {
+                widths[column] = widths[column].max(cell.text.chars().count());
+            }
+        }
+
+        let mut lines = vec![Spans::from(header.iter()
+            .zip(&widths)
+            .map(|(title, width)| Span::styled(pad(title, *width), Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)))
+            .collect::<Vec<Span>>())];
+        for (company_code, cells) in &rows {
+            lines.push(Spans::from(cells.iter()
+                .enumerate()
+                .map(|(column, cell)| {
+                    let width = widths.get(column).copied().unwrap_or(0);
+                    Span::styled(pad(&cell.text, width), self.cell_style(company_code, column, cell))
+                })
+                .collect::<Vec<Span>>()));
+        }
+        lines
+    }
+
+    fn cell_style(&self, company_code: &str, column: usize, cell: &WatchCell) -> Style {
+        if column == 0 && self.alerting.iter().any(|alerting| alerting == company_code) {
+            return Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD);
+        }
+        match self.flashes.get(&(company_code.to_string(), column)) {
+            Some(flash) if flash.at.elapsed() < FLASH_FOR => Style::default()
+                .fg(Color::Black)
+                .bg(if flash.up { Color::Green } else { Color::Red }),
+            _ => Style::default().fg(cell.color),
+        }
+    }
+}
+
+fn failure_cells(company_code: &str, reason: &str) -> Vec<WatchCell> {
+    vec![
+        WatchCell { text: company_code.to_string(), value: None, color: Color::Red },
+        WatchCell { text: reason.to_string(), value: None, color: Color::Red },
+    ]
+}
+
+fn pad(text: &str, width: usize) -> String {
+    format!("{:<width$}  ", text, width = width)
+}
+
+fn render_error<E: std::fmt::Display>(e: E) -> StockError {
+    StockError::Render(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::NaiveDate;
+    use chrono_tz::Tz;
+
+    use crate::notifier::tests::sample_alert;
+    use crate::share_price_model::{Price, Share};
+
+    use super::*;
+
+    fn share(company_code: &str, price: &str, day: u32) -> Share {
+        Share {
+            company_code: company_code.to_string(),
+            price: Price::parse(price).unwrap(),
+            price_date: NaiveDate::from_ymd_opt(2020, 10, day).unwrap().and_hms_opt(9, 0, 0).unwrap(),
+            exchange_tz: Tz::UTC,
+            source: None,
+        }
+    }
+
+    /// Priced on the 16th, against 100 the day before
+    fn found(company_code: &str, price: &str) -> FetchOutcome {
+        let mut share_history = HashMap::new();
+        share_history.insert(ShareMoment::Yesterday, share(company_code, "100", 15));
+        FetchOutcome::Found(ShareTimeline { share: share(company_code, price, 16), share_history })
+    }
+
+    fn flash(table: &WatchTable, company_code: &str, column: usize) -> Option<bool> {
+        table.flashes.get(&(company_code.to_string(), column)).map(|flash| flash.up)
+    }
+
+    fn table() -> WatchTable {
+        WatchTable::new(&[ShareMoment::Yesterday], Duration::from_secs(30))
+    }
+
+    #[test]
+    fn only_changed_cells_flash() {
+        let mut table = table();
+        let not_found = FetchOutcome::NotFound { company_code: "GONE".to_string(), reason: "no page".to_string() };
+        table.update(&[found("NPN", "110"), found("MTN", "90"), not_found.clone()], &[]);
+        assert!(table.flashes.is_empty());
+
+        table.update(&[found("NPN", "120"), found("MTN", "80"), not_found], &[]);
+        //price, then yesterday's price, date, movement and percent
+        assert_eq!(flash(&table, "NPN", 1), Some(true));
+        assert_eq!(flash(&table, "NPN", 4), None);
+        assert_eq!(flash(&table, "NPN", 6), Some(true));
+        assert_eq!(flash(&table, "NPN", 7), Some(true));
+        assert_eq!(flash(&table, "MTN", 1), Some(false));
+        assert_eq!(flash(&table, "MTN", 6), Some(false));
+        assert!(table.flashes.keys().all(|(company_code, _)| company_code != "GONE"));
+        assert_eq!(table.rows[2].1[1].text, "not found: no page");
+    }
+
+    #[test]
+    fn cells_styled_by_alert_then_flash() {
+        let mut table = table();
+        table.update(&[found("NPN", "110"), found("MTN", "90")], &[]);
+        table.update(&[found("NPN", "120"), found("MTN", "80")], &[sample_alert()]);
+        let cell = |table: &WatchTable, company_code: &str, column: usize| {
+            let cells = &table.rows.iter().find(|(code, _)| code == company_code).unwrap().1;
+            table.cell_style(company_code, column, &cells[column])
+        };
+
+        assert_eq!(cell(&table, "NPN", 0), Style::default().fg(Color::White).bg(Color::Red).add_modifier(Modifier::BOLD));
+        assert_eq!(cell(&table, "MTN", 0), Style::default().fg(Color::Reset));
+        assert_eq!(cell(&table, "NPN", 1), Style::default().fg(Color::Black).bg(Color::Green));
+        assert_eq!(cell(&table, "MTN", 1), Style::default().fg(Color::Black).bg(Color::Red));
+        assert_eq!(cell(&table, "NPN", 4), Style::default().fg(Color::LightBlue));
+
+        //flashes go out after a while
+        table.flashes.values_mut().for_each(|flash| flash.at -= FLASH_FOR);
+        assert_eq!(cell(&table, "NPN", 1), Style::default().fg(Color::LightBlue));
+    }
+
+    #[test]
+    fn status_line_says_whats_next() {
+        let mut table = table();
+        assert!(table.refresh_due(Instant::now()));
+        table.update(&[found("NPN", "110")], &[sample_alert()]);
+        let refreshed_at = table.last_refresh.unwrap();
+        assert!(!table.refresh_due(refreshed_at));
+        assert!(table.status_line(refreshed_at).starts_with("Next refresh in 30s  Updated "));
+        assert!(table.status_line(refreshed_at + Duration::from_millis(100)).starts_with("Next refresh in 30s"));
+        assert!(table.status_line(refreshed_at + Duration::from_millis(29_500)).starts_with("Next refresh in 1s"));
+        assert!(table.refresh_due(refreshed_at + Duration::from_secs(30)));
+        assert!(table.status_line(refreshed_at + Duration::from_secs(31)).starts_with("Next refresh in 0s"));
+
+        table.note_saved(1);
+        table.note_deliveries(&[
+            Delivery { target: "hook".to_string(), company_code: "NPN".to_string(), outcome: DeliveryOutcome::Sent { attempts: 1 } },
+            Delivery { target: "mail".to_string(), company_code: "NPN".to_string(), outcome: DeliveryOutcome::Failed { attempts: 3, error: "down".to_string() } },
+        ]);
+        let status = table.status_line(refreshed_at);
+        assert!(status.contains("saved 1 prices"));
+        assert!(status.contains("ALERT NPN moved +6.12% since Yesterday, more than 5% (sent 1/2)"));
+        table.note_fx_problems(&["Couldn't fetch the USD to ZAR rate".to_string(), "No GBP to ZAR rate".to_string()]);
+        assert!(table.status_line(refreshed_at).contains("FX Couldn't fetch the USD to ZAR rate (+1 more)"));
+        table.note_fx_problems(&[]);
+        assert!(!table.status_line(refreshed_at).contains("FX"));
+
+        table.paused = true;
+        assert!(table.status_line(refreshed_at).starts_with("PAUSED"));
+        assert!(!table.refresh_due(refreshed_at + Duration::from_secs(60)));
+        table.refresh_requested = true;
+        assert!(table.refresh_due(refreshed_at));
+        table.refreshing = true;
+        assert!(table.status_line(refreshed_at).starts_with("Refreshing..."));
+    }
+}